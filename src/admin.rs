@@ -0,0 +1,229 @@
+use std::io::{BufReader, Cursor, ErrorKind, Seek, SeekFrom};
+use std::path::Path;
+
+use clap::ValueEnum;
+use humantime::parse_duration;
+use rocket::data::{Data, Limits, ToByteUnit};
+use rocket::http::{ContentType, Status};
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::Response;
+use rocket::{delete, get, post, put, routes, tokio, Route, State};
+use serde::Serialize;
+use serde_json::json;
+
+use crate::lib::store::Store;
+use crate::lib::{
+    delete_entry, get_entry, hash_token, list_entries, list_revisions, purge_entries, set_pinned, stats, EntryFilter,
+    EntryInfo,
+};
+use crate::lib::export::{export, import, Format, OnConflict};
+use crate::backup::backup;
+use crate::{store_error, CustomResponse, PastebinConfig, StoreReader};
+
+/// Header carrying the admin token given with `--admin-token`.
+pub const ADMIN_TOKEN_HEADER: &str = "X-Admin-Token";
+
+/// Hash of the `--admin-token`, the admin API is disabled without one.
+pub struct AdminToken(pub Option<Vec<u8>>);
+
+/// Request guard for the admin API. Requests are forwarded (404) when the admin API
+/// is disabled and rejected with 401 without a valid token.
+pub struct Admin;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Admin {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let expected = match request.rocket().state::<AdminToken>() {
+            Some(AdminToken(Some(expected))) => expected,
+            _ => return Outcome::Forward(Status::NotFound),
+        };
+
+        match request.headers().get_one(ADMIN_TOKEN_HEADER) {
+            Some(token) if hash_token(token) == *expected => Outcome::Success(Admin),
+            _ => Outcome::Error((Status::Unauthorized, ())),
+        }
+    }
+}
+
+fn json_response<T: Serialize>(value: &T) -> CustomResponse<'static> {
+    let body = serde_json::to_string(value).unwrap();
+    CustomResponse(
+        Response::build()
+            .status(Status::Ok)
+            .header(ContentType::JSON)
+            .sized_body(body.len(), Cursor::new(body))
+            .finalize(),
+    )
+}
+
+/// List pastes in ID order. `next` is the `after` value of the following page.
+#[get("/pastes?<after>&<limit>")]
+fn list(
+    _admin: Admin,
+    after: Option<&str>,
+    limit: Option<usize>,
+    state: &State<Store>,
+) -> Result<CustomResponse<'static>, Status> {
+    let limit = limit.unwrap_or(100).clamp(1, 1000);
+    let pastes = list_entries(after, limit, state).map_err(store_error)?;
+    let next = (pastes.len() == limit).then(|| pastes.last().unwrap().id.clone());
+
+    Ok(json_response(&json!({ "pastes": pastes, "next": next })))
+}
+
+#[get("/pastes/<id>")]
+fn show(_admin: Admin, id: &str, state: &State<Store>) -> Result<CustomResponse<'static>, Status> {
+    let root = get_entry(id, state).map_err(store_error)?.ok_or(Status::NotFound)?;
+    let revisions: Vec<_> = list_revisions(id, &root, state)
+        .map_err(store_error)?
+        .into_iter()
+        .map(|(revision, timestamp)| json!({ "revision": revision, "timestamp": timestamp }))
+        .collect();
+
+    let mut info = serde_json::to_value(EntryInfo::new(id, &root)).unwrap();
+    info["revisions"] = json!(revisions);
+    Ok(json_response(&info))
+}
+
+#[delete("/pastes/<id>")]
+fn remove(_admin: Admin, id: &str, state: &State<Store>) -> Status {
+    match get_entry(id, state) {
+        Ok(Some(_)) => match delete_entry(id, state) {
+            Ok(_) => Status::Ok,
+            Err(e) => store_error(e),
+        },
+        Ok(None) => Status::NotFound,
+        Err(e) => store_error(e),
+    }
+}
+
+/// Pin a paste, so that it is never evicted to stay within `--max-storage`.
+#[put("/pastes/<id>/pin")]
+fn pin(_admin: Admin, id: &str, state: &State<Store>) -> Status {
+    pin_status(set_pinned(id, true, state))
+}
+
+#[delete("/pastes/<id>/pin")]
+fn unpin(_admin: Admin, id: &str, state: &State<Store>) -> Status {
+    pin_status(set_pinned(id, false, state))
+}
+
+fn pin_status(result: std::io::Result<bool>) -> Status {
+    match result {
+        Ok(true) => Status::Ok,
+        Ok(false) => Status::NotFound,
+        Err(e) => store_error(e),
+    }
+}
+
+/// Delete the pastes matching all the given criteria. At least one is required, so
+/// that a mistyped request cannot wipe the database.
+#[delete("/pastes?<older_than>&<larger_than>&<lang>")]
+fn purge(
+    _admin: Admin,
+    older_than: Option<&str>,
+    larger_than: Option<u64>,
+    lang: Option<&str>,
+    state: &State<Store>,
+) -> Result<CustomResponse<'static>, Status> {
+    let older_than = match older_than {
+        Some(age) => Some(parse_duration(age).map_err(|_| Status::BadRequest)?.as_secs()),
+        None => None,
+    };
+    let filter = EntryFilter { older_than, larger_than, lang };
+    if filter.is_empty() {
+        return Err(Status::BadRequest);
+    }
+
+    let deleted = purge_entries(&filter, state).map_err(store_error)?;
+    Ok(json_response(&json!({ "deleted": deleted })))
+}
+
+#[get("/stats")]
+fn get_stats(_admin: Admin, state: &State<Store>) -> Result<CustomResponse<'static>, Status> {
+    Ok(json_response(&stats(state).map_err(store_error)?))
+}
+
+/// Write a backup of the database to `--backup-dir`, as the scheduled backups do. Only
+/// available with a backup directory, on the rocksdb backend.
+#[post("/backups")]
+fn create_backup(
+    _admin: Admin,
+    state: &State<Store>,
+    cfg: &State<PastebinConfig>,
+) -> Result<CustomResponse<'static>, Status> {
+    let dir = cfg.backup_dir.as_ref().ok_or(Status::NotFound)?;
+    match backup(state, Path::new(dir), cfg.backup_keep) {
+        Ok(path) => Ok(json_response(&json!({ "path": path }))),
+        Err(e) if e.kind() == ErrorKind::Unsupported => Err(Status::NotImplemented),
+        Err(e) => Err(store_error(e)),
+    }
+}
+
+fn parse_format(format: Option<&str>) -> Result<Format, Status> {
+    format.map_or(Ok(Format::Jsonl), |format| Format::from_str(format, true).map_err(|_| Status::BadRequest))
+}
+
+/// Export every paste with its revisions and metadata, as JSON Lines (the default) or as a
+/// tar archive. The export is sent as it is written, an error along the way cuts it short.
+#[get("/export?<format>")]
+fn export_pastes(_admin: Admin, format: Option<&str>, state: &State<Store>) -> Result<CustomResponse<'static>, Status> {
+    let format = parse_format(format)?;
+    let state = state.inner().clone();
+    let body = StoreReader::writing(move |out| export(format, &state, out).map(|_| ()));
+
+    let content_type = match format {
+        Format::Jsonl => ContentType::new("application", "jsonl"),
+        Format::Tar => ContentType::new("application", "x-tar"),
+    };
+    Ok(CustomResponse(
+        Response::build()
+            .status(Status::Ok)
+            .header(content_type)
+            .streamed_body(body)
+            .finalize(),
+    ))
+}
+
+/// Import the pastes of an export under their original IDs, see `import`. The body is
+/// limited to the `import` limit of the Rocket configuration, 1 GiB by default, and
+/// received in a temporary file rather than in memory.
+#[post("/import?<format>&<on_conflict>", data = "<data>")]
+async fn import_pastes(
+    _admin: Admin,
+    format: Option<&str>,
+    on_conflict: Option<&str>,
+    data: Data<'_>,
+    limits: &Limits,
+    state: &State<Store>,
+    cfg: &State<PastebinConfig>,
+) -> Result<CustomResponse<'static>, Status> {
+    let format = parse_format(format)?;
+    let on_conflict = match on_conflict {
+        Some(on_conflict) => OnConflict::from_str(on_conflict, true).map_err(|_| Status::BadRequest)?,
+        None => OnConflict::Skip,
+    };
+    let mut input = tokio::fs::File::from_std(tempfile::tempfile().map_err(store_error)?);
+    let received = data.open(limits.get("import").unwrap_or(1.gibibytes())).stream_to(&mut input).await;
+    if !received.map_err(store_error)?.complete {
+        return Err(Status::PayloadTooLarge);
+    }
+    let mut input = input.into_std().await;
+    input.seek(SeekFrom::Start(0)).map_err(store_error)?;
+
+    let (state, compression, max_storage) = (state.inner().clone(), cfg.compression(), cfg.max_storage);
+    let imported = tokio::task::spawn_blocking(move || {
+        import(format, &mut BufReader::new(input), on_conflict, &compression, max_storage, &state)
+    });
+    match imported.await.map_err(|_| Status::InternalServerError)? {
+        Ok(imported) => Ok(json_response(&imported)),
+        Err(e) if e.kind() == ErrorKind::InvalidData => Err(Status::BadRequest),
+        Err(e) => Err(store_error(e)),
+    }
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![list, show, remove, pin, unpin, purge, get_stats, create_backup, export_pastes, import_pastes]
+}
@@ -30,6 +30,19 @@ impl<'a> Entry<'a> {
   pub const VT_LANG: ::flatbuffers::VOffsetT = 10;
   pub const VT_BURN: ::flatbuffers::VOffsetT = 12;
   pub const VT_ENCRYPTED: ::flatbuffers::VOffsetT = 14;
+  pub const VT_OWNER_TOKEN: ::flatbuffers::VOffsetT = 16;
+  pub const VT_REVISION: ::flatbuffers::VOffsetT = 18;
+  pub const VT_UPDATE_TIMESTAMP: ::flatbuffers::VOffsetT = 20;
+  pub const VT_API_KEY: ::flatbuffers::VOffsetT = 22;
+  pub const VT_OWNER: ::flatbuffers::VOffsetT = 24;
+  pub const VT_SIZE: ::flatbuffers::VOffsetT = 26;
+  pub const VT_CODEC: ::flatbuffers::VOffsetT = 28;
+  pub const VT_DIGEST: ::flatbuffers::VOffsetT = 30;
+  pub const VT_MAX_VIEWS: ::flatbuffers::VOffsetT = 32;
+  pub const VT_PREVIEW_TOKEN: ::flatbuffers::VOffsetT = 34;
+  pub const VT_PINNED: ::flatbuffers::VOffsetT = 36;
+  pub const VT_CHUNKS: ::flatbuffers::VOffsetT = 38;
+  pub const VT_KEY_ID: ::flatbuffers::VOffsetT = 40;
 
   #[inline]
   pub unsafe fn init_from_table(table: ::flatbuffers::Table<'a>) -> Self {
@@ -41,10 +54,23 @@ impl<'a> Entry<'a> {
     args: &'args EntryArgs<'args>
   ) -> ::flatbuffers::WIPOffset<Entry<'bldr>> {
     let mut builder = EntryBuilder::new(_fbb);
+    builder.add_size(args.size);
+    builder.add_update_timestamp(args.update_timestamp);
     builder.add_expiry_timestamp(args.expiry_timestamp);
     builder.add_create_timestamp(args.create_timestamp);
+    builder.add_key_id(args.key_id);
+    builder.add_chunks(args.chunks);
+    if let Some(x) = args.preview_token { builder.add_preview_token(x); }
+    builder.add_max_views(args.max_views);
+    if let Some(x) = args.digest { builder.add_digest(x); }
+    if let Some(x) = args.owner { builder.add_owner(x); }
+    if let Some(x) = args.api_key { builder.add_api_key(x); }
+    builder.add_revision(args.revision);
+    if let Some(x) = args.owner_token { builder.add_owner_token(x); }
     if let Some(x) = args.lang { builder.add_lang(x); }
     if let Some(x) = args.data { builder.add_data(x); }
+    builder.add_pinned(args.pinned);
+    builder.add_codec(args.codec);
     builder.add_encrypted(args.encrypted);
     builder.add_burn(args.burn);
     builder.finish()
@@ -93,6 +119,97 @@ impl<'a> Entry<'a> {
     // which contains a valid value in this slot
     unsafe { self._tab.get::<bool>(Entry::VT_ENCRYPTED, Some(false)).unwrap()}
   }
+  #[inline]
+  pub fn owner_token(&self) -> Option<::flatbuffers::Vector<'a, u8>> {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<::flatbuffers::ForwardsUOffset<::flatbuffers::Vector<'a, u8>>>(Entry::VT_OWNER_TOKEN, None)}
+  }
+  #[inline]
+  pub fn revision(&self) -> u32 {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<u32>(Entry::VT_REVISION, Some(0)).unwrap()}
+  }
+  #[inline]
+  pub fn update_timestamp(&self) -> u64 {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<u64>(Entry::VT_UPDATE_TIMESTAMP, Some(0)).unwrap()}
+  }
+  #[inline]
+  pub fn api_key(&self) -> Option<&'a str> {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<::flatbuffers::ForwardsUOffset<&str>>(Entry::VT_API_KEY, None)}
+  }
+  #[inline]
+  pub fn owner(&self) -> Option<&'a str> {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<::flatbuffers::ForwardsUOffset<&str>>(Entry::VT_OWNER, None)}
+  }
+  #[inline]
+  pub fn size(&self) -> u64 {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<u64>(Entry::VT_SIZE, Some(0)).unwrap()}
+  }
+  #[inline]
+  pub fn codec(&self) -> u8 {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<u8>(Entry::VT_CODEC, Some(0)).unwrap()}
+  }
+  #[inline]
+  pub fn digest(&self) -> Option<::flatbuffers::Vector<'a, u8>> {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<::flatbuffers::ForwardsUOffset<::flatbuffers::Vector<'a, u8>>>(Entry::VT_DIGEST, None)}
+  }
+  #[inline]
+  pub fn max_views(&self) -> u32 {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<u32>(Entry::VT_MAX_VIEWS, Some(0)).unwrap()}
+  }
+  #[inline]
+  pub fn preview_token(&self) -> Option<::flatbuffers::Vector<'a, u8>> {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<::flatbuffers::ForwardsUOffset<::flatbuffers::Vector<'a, u8>>>(Entry::VT_PREVIEW_TOKEN, None)}
+  }
+  #[inline]
+  pub fn pinned(&self) -> bool {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<bool>(Entry::VT_PINNED, Some(false)).unwrap()}
+  }
+  #[inline]
+  pub fn chunks(&self) -> u32 {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<u32>(Entry::VT_CHUNKS, Some(0)).unwrap()}
+  }
+  #[inline]
+  pub fn key_id(&self) -> u32 {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<u32>(Entry::VT_KEY_ID, Some(0)).unwrap()}
+  }
 }
 
 impl ::flatbuffers::Verifiable for Entry<'_> {
@@ -107,6 +224,19 @@ impl ::flatbuffers::Verifiable for Entry<'_> {
      .visit_field::<::flatbuffers::ForwardsUOffset<&str>>("lang", Self::VT_LANG, false)?
      .visit_field::<bool>("burn", Self::VT_BURN, false)?
      .visit_field::<bool>("encrypted", Self::VT_ENCRYPTED, false)?
+     .visit_field::<::flatbuffers::ForwardsUOffset<::flatbuffers::Vector<'_, u8>>>("owner_token", Self::VT_OWNER_TOKEN, false)?
+     .visit_field::<u32>("revision", Self::VT_REVISION, false)?
+     .visit_field::<u64>("update_timestamp", Self::VT_UPDATE_TIMESTAMP, false)?
+     .visit_field::<::flatbuffers::ForwardsUOffset<&str>>("api_key", Self::VT_API_KEY, false)?
+     .visit_field::<::flatbuffers::ForwardsUOffset<&str>>("owner", Self::VT_OWNER, false)?
+     .visit_field::<u64>("size", Self::VT_SIZE, false)?
+     .visit_field::<u8>("codec", Self::VT_CODEC, false)?
+     .visit_field::<::flatbuffers::ForwardsUOffset<::flatbuffers::Vector<'_, u8>>>("digest", Self::VT_DIGEST, false)?
+     .visit_field::<u32>("max_views", Self::VT_MAX_VIEWS, false)?
+     .visit_field::<::flatbuffers::ForwardsUOffset<::flatbuffers::Vector<'_, u8>>>("preview_token", Self::VT_PREVIEW_TOKEN, false)?
+     .visit_field::<bool>("pinned", Self::VT_PINNED, false)?
+     .visit_field::<u32>("chunks", Self::VT_CHUNKS, false)?
+     .visit_field::<u32>("key_id", Self::VT_KEY_ID, false)?
      .finish();
     Ok(())
   }
@@ -118,6 +248,19 @@ pub struct EntryArgs<'a> {
     pub lang: Option<::flatbuffers::WIPOffset<&'a str>>,
     pub burn: bool,
     pub encrypted: bool,
+    pub owner_token: Option<::flatbuffers::WIPOffset<::flatbuffers::Vector<'a, u8>>>,
+    pub revision: u32,
+    pub update_timestamp: u64,
+    pub api_key: Option<::flatbuffers::WIPOffset<&'a str>>,
+    pub owner: Option<::flatbuffers::WIPOffset<&'a str>>,
+    pub size: u64,
+    pub codec: u8,
+    pub digest: Option<::flatbuffers::WIPOffset<::flatbuffers::Vector<'a, u8>>>,
+    pub max_views: u32,
+    pub preview_token: Option<::flatbuffers::WIPOffset<::flatbuffers::Vector<'a, u8>>>,
+    pub pinned: bool,
+    pub chunks: u32,
+    pub key_id: u32,
 }
 impl<'a> Default for EntryArgs<'a> {
   #[inline]
@@ -129,6 +272,19 @@ impl<'a> Default for EntryArgs<'a> {
       lang: None,
       burn: false,
       encrypted: false,
+      owner_token: None,
+      revision: 0,
+      update_timestamp: 0,
+      api_key: None,
+      owner: None,
+      size: 0,
+      codec: 0,
+      digest: None,
+      max_views: 0,
+      preview_token: None,
+      pinned: false,
+      chunks: 0,
+      key_id: 0,
     }
   }
 }
@@ -163,6 +319,58 @@ impl<'a: 'b, 'b, A: ::flatbuffers::Allocator + 'a> EntryBuilder<'a, 'b, A> {
     self.fbb_.push_slot::<bool>(Entry::VT_ENCRYPTED, encrypted, false);
   }
   #[inline]
+  pub fn add_owner_token(&mut self, owner_token: ::flatbuffers::WIPOffset<::flatbuffers::Vector<'b , u8>>) {
+    self.fbb_.push_slot_always::<::flatbuffers::WIPOffset<_>>(Entry::VT_OWNER_TOKEN, owner_token);
+  }
+  #[inline]
+  pub fn add_revision(&mut self, revision: u32) {
+    self.fbb_.push_slot::<u32>(Entry::VT_REVISION, revision, 0);
+  }
+  #[inline]
+  pub fn add_update_timestamp(&mut self, update_timestamp: u64) {
+    self.fbb_.push_slot::<u64>(Entry::VT_UPDATE_TIMESTAMP, update_timestamp, 0);
+  }
+  #[inline]
+  pub fn add_api_key(&mut self, api_key: ::flatbuffers::WIPOffset<&'b  str>) {
+    self.fbb_.push_slot_always::<::flatbuffers::WIPOffset<_>>(Entry::VT_API_KEY, api_key);
+  }
+  #[inline]
+  pub fn add_owner(&mut self, owner: ::flatbuffers::WIPOffset<&'b  str>) {
+    self.fbb_.push_slot_always::<::flatbuffers::WIPOffset<_>>(Entry::VT_OWNER, owner);
+  }
+  #[inline]
+  pub fn add_size(&mut self, size: u64) {
+    self.fbb_.push_slot::<u64>(Entry::VT_SIZE, size, 0);
+  }
+  #[inline]
+  pub fn add_codec(&mut self, codec: u8) {
+    self.fbb_.push_slot::<u8>(Entry::VT_CODEC, codec, 0);
+  }
+  #[inline]
+  pub fn add_digest(&mut self, digest: ::flatbuffers::WIPOffset<::flatbuffers::Vector<'b , u8>>) {
+    self.fbb_.push_slot_always::<::flatbuffers::WIPOffset<_>>(Entry::VT_DIGEST, digest);
+  }
+  #[inline]
+  pub fn add_max_views(&mut self, max_views: u32) {
+    self.fbb_.push_slot::<u32>(Entry::VT_MAX_VIEWS, max_views, 0);
+  }
+  #[inline]
+  pub fn add_preview_token(&mut self, preview_token: ::flatbuffers::WIPOffset<::flatbuffers::Vector<'b , u8>>) {
+    self.fbb_.push_slot_always::<::flatbuffers::WIPOffset<_>>(Entry::VT_PREVIEW_TOKEN, preview_token);
+  }
+  #[inline]
+  pub fn add_pinned(&mut self, pinned: bool) {
+    self.fbb_.push_slot::<bool>(Entry::VT_PINNED, pinned, false);
+  }
+  #[inline]
+  pub fn add_chunks(&mut self, chunks: u32) {
+    self.fbb_.push_slot::<u32>(Entry::VT_CHUNKS, chunks, 0);
+  }
+  #[inline]
+  pub fn add_key_id(&mut self, key_id: u32) {
+    self.fbb_.push_slot::<u32>(Entry::VT_KEY_ID, key_id, 0);
+  }
+  #[inline]
   pub fn new(_fbb: &'b mut ::flatbuffers::FlatBufferBuilder<'a, A>) -> EntryBuilder<'a, 'b, A> {
     let start = _fbb.start_table();
     EntryBuilder {
@@ -186,6 +394,19 @@ impl ::core::fmt::Debug for Entry<'_> {
       ds.field("lang", &self.lang());
       ds.field("burn", &self.burn());
       ds.field("encrypted", &self.encrypted());
+      ds.field("owner_token", &self.owner_token());
+      ds.field("revision", &self.revision());
+      ds.field("update_timestamp", &self.update_timestamp());
+      ds.field("api_key", &self.api_key());
+      ds.field("owner", &self.owner());
+      ds.field("size", &self.size());
+      ds.field("codec", &self.codec());
+      ds.field("digest", &self.digest());
+      ds.field("max_views", &self.max_views());
+      ds.field("preview_token", &self.preview_token());
+      ds.field("pinned", &self.pinned());
+      ds.field("chunks", &self.chunks());
+      ds.field("key_id", &self.key_id());
       ds.finish()
   }
 }
@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+use std::fs;
+use std::sync::Mutex;
+
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use serde::Deserialize;
+
+use crate::auth::{bearer_token, looks_like_jwt};
+use crate::lib::store::Store;
+use crate::lib::{hash_token, read_usage};
+use crate::store_error;
+
+/// An API key as listed in the `--api-keys` file. Limits left out are not enforced.
+#[derive(Deserialize, Debug)]
+pub struct ApiKey {
+    pub name: String,
+    pub key: String,
+    /// Largest accepted paste, in bytes.
+    pub max_paste_size: Option<u64>,
+    /// Pastes that can be created within a calendar hour.
+    pub pastes_per_hour: Option<u64>,
+    /// Total size of the pastes (including revisions) kept for this key, in bytes.
+    pub max_stored_bytes: Option<u64>,
+    /// Quota held by the pastes being created, not in the usage counters yet.
+    #[serde(skip)]
+    reserved: Mutex<Reserved>,
+}
+
+#[derive(Default, Debug)]
+struct Reserved {
+    pastes: u64,
+    bytes: u64,
+}
+
+/// Quota of a paste being created or edited, see `ApiKey::reserve_quota`. It is given
+/// back when dropped: by then the paste is in the usage counters, or it was not stored.
+pub struct QuotaReservation<'k> {
+    key: &'k ApiKey,
+    pastes: u64,
+    bytes: u64,
+}
+
+impl Drop for QuotaReservation<'_> {
+    fn drop(&mut self) {
+        let mut reserved = self.key.reserved.lock().unwrap();
+        reserved.pastes -= self.pastes;
+        reserved.bytes -= self.bytes;
+    }
+}
+
+impl ApiKey {
+    /// Check a new paste of `size` bytes against the limits of this key, and hold its
+    /// share of them until it is stored.
+    pub fn reserve_quota(&self, size: u64, state: &Store) -> Result<QuotaReservation<'_>, Status> {
+        self.reserve(1, size, state)
+    }
+
+    /// Like `reserve_quota`, for a new revision of `size` bytes of a paste of this key:
+    /// it does not count as a paste created.
+    pub fn reserve_revision(&self, size: u64, state: &Store) -> Result<QuotaReservation<'_>, Status> {
+        self.reserve(0, size, state)
+    }
+
+    fn reserve(&self, pastes: u64, size: u64, state: &Store) -> Result<QuotaReservation<'_>, Status> {
+        if self.max_paste_size.is_some_and(|max| size > max) {
+            return Err(Status::PayloadTooLarge);
+        }
+
+        // the usage is read under the lock, so that concurrent writes count each other
+        let mut reserved = self.reserved.lock().unwrap();
+        let (hourly, stored) = read_usage(&self.name, state).map_err(store_error)?;
+        if pastes > 0 && self.pastes_per_hour.is_some_and(|max| hourly.max(0) as u64 + reserved.pastes >= max) {
+            return Err(Status::TooManyRequests);
+        }
+        if self.max_stored_bytes.is_some_and(|max| stored.max(0) as u64 + reserved.bytes + size > max) {
+            return Err(Status::InsufficientStorage);
+        }
+
+        reserved.pastes += pastes;
+        reserved.bytes += size;
+        Ok(QuotaReservation { key: self, pastes, bytes: size })
+    }
+}
+
+/// API keys loaded at startup, indexed by the hash of the key.
+#[derive(Default)]
+pub struct ApiKeys(HashMap<Vec<u8>, ApiKey>);
+
+impl ApiKeys {
+    /// Load keys from a JSON file holding a list of `ApiKey` objects.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let content = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+        let keys: Vec<ApiKey> = serde_json::from_str(&content).map_err(|e| format!("{path}: {e}"))?;
+
+        let mut map = HashMap::new();
+        for key in keys {
+            if key.key.is_empty() {
+                return Err(format!("{path}: API key \"{}\" is empty", key.name));
+            }
+            if map.insert(hash_token(&key.key), key).is_some() {
+                return Err(format!("{path}: duplicated API key"));
+            }
+        }
+
+        Ok(ApiKeys(map))
+    }
+
+    fn find(&self, key: &str) -> Option<&ApiKey> {
+        self.0.get(&hash_token(key))
+    }
+
+    /// The key called `name`, ie. the one recorded in a paste it created.
+    pub fn named(&self, name: &str) -> Option<&ApiKey> {
+        self.0.values().find(|key| key.name == name)
+    }
+}
+
+/// The API key sent as `Authorization: Bearer <key>`, `None` for anonymous requests.
+/// When keys are configured, unknown keys are rejected with 401 rather than treated as
+/// anonymous; bearer JWTs and other schemes (ie. the Basic auth of a proxy in front) are
+/// not ours and left alone.
+pub struct ApiCaller<'r>(pub Option<&'r ApiKey>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ApiCaller<'r> {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let keys = match request.rocket().state::<ApiKeys>() {
+            Some(keys) if !keys.0.is_empty() => keys,
+            _ => return Outcome::Success(ApiCaller(None)),
+        };
+        let bearer = match bearer_token(request) {
+            Some(bearer) => bearer,
+            None => return Outcome::Success(ApiCaller(None)),
+        };
+
+        // bearer JWTs are left to the `Identity` guard, or to a proxy in front when it is off
+        if looks_like_jwt(bearer) {
+            return Outcome::Success(ApiCaller(None));
+        }
+
+        match keys.find(bearer) {
+            Some(key) => Outcome::Success(ApiCaller(Some(key))),
+            None => Outcome::Error((Status::Unauthorized, ())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::store::MemoryStore;
+
+    #[test]
+    fn reservations_count_until_dropped() {
+        let state = Store::new(MemoryStore::default());
+        let key = r#"{"name": "ci", "key": "k", "pastes_per_hour": 2, "max_stored_bytes": 10}"#;
+        let key: ApiKey = serde_json::from_str(key).unwrap();
+
+        let first = key.reserve_quota(4, &state).unwrap();
+        assert_eq!(key.reserve_quota(7, &state).err(), Some(Status::InsufficientStorage));
+        let second = key.reserve_quota(6, &state).unwrap();
+        assert_eq!(key.reserve_quota(0, &state).err(), Some(Status::TooManyRequests));
+
+        // a paste not created gives its quota back
+        drop(second);
+        assert!(key.reserve_quota(6, &state).is_ok());
+        drop(first);
+        assert_eq!(key.reserved.lock().unwrap().pastes, 0);
+    }
+}
@@ -0,0 +1,184 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::Utc;
+use rocket::fairing::AdHoc;
+use rocket::tokio;
+
+use crate::lib::store::Store;
+use crate::lib::{open_db, verify};
+
+/// Name prefix of the backups written by `backup`, followed by their UTC creation time so
+/// that they sort oldest first.
+const BACKUP_PREFIX: &str = "backup-";
+
+/// Write a consistent snapshot of the store to a new directory of `dir` while the store
+/// stays in use, then delete the oldest backups of `dir` beyond the `keep` most recent
+/// ones (`0` keeps them all). Returns the path of the new backup.
+pub fn backup(store: &Store, dir: &Path, keep: usize) -> io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let path = dir.join(format!("{BACKUP_PREFIX}{}", Utc::now().format("%Y%m%dT%H%M%S%.3fZ")));
+    store.checkpoint(&path)?;
+
+    if keep > 0 {
+        let backups = list_backups(dir)?;
+        for old in &backups[..backups.len().saturating_sub(keep)] {
+            fs::remove_dir_all(old)?;
+        }
+    }
+    Ok(path)
+}
+
+/// The backups of `dir`, oldest first.
+pub fn list_backups(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut backups = vec![];
+    for item in fs::read_dir(dir)? {
+        let item = item?;
+        if item.file_name().to_string_lossy().starts_with(BACKUP_PREFIX) && item.file_type()?.is_dir() {
+            backups.push(item.path());
+        }
+    }
+    backups.sort();
+    Ok(backups)
+}
+
+/// Replace the RocksDB database at `db_path` with a copy of the backup `snapshot`. The copy
+/// is opened (so upgraded to the current schema, see `migrate`) and verified first, and
+/// nothing changes unless it passes. The database replaced is moved aside, to the path
+/// returned. The server must be stopped: RocksDB refuses to open a database in use.
+pub fn restore(snapshot: &Path, db_path: &Path) -> io::Result<Option<PathBuf>> {
+    if !snapshot.is_dir() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("no backup at {}", snapshot.display())));
+    }
+    let sibling = |suffix: &str| {
+        let mut path = db_path.as_os_str().to_owned();
+        path.push(suffix);
+        PathBuf::from(path)
+    };
+
+    // the snapshot itself stays untouched, opening a database writes to it
+    let staging = sibling(".restoring");
+    if staging.exists() {
+        fs::remove_dir_all(&staging)?;
+    }
+    let checked = copy_dir(snapshot, &staging).and_then(|_| {
+        let db = open_db(staging.to_str().unwrap())?;
+        match verify(None, &db)?.len() {
+            0 => Ok(()),
+            broken => {
+                Err(io::Error::new(io::ErrorKind::InvalidData, format!("the backup has {broken} broken records")))
+            }
+        }
+    });
+    if let Err(e) = checked {
+        let _ = fs::remove_dir_all(&staging);
+        return Err(e);
+    }
+
+    let previous = match db_path.exists() {
+        true => {
+            // fails while the server holds the database
+            drop(open_db(db_path.to_str().unwrap())?);
+            let previous = sibling(&format!(".before-restore-{}", Utc::now().format("%Y%m%dT%H%M%SZ")));
+            fs::rename(db_path, &previous)?;
+            Some(previous)
+        }
+        false => None,
+    };
+    fs::rename(&staging, db_path)?;
+    Ok(previous)
+}
+
+fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for item in fs::read_dir(from)? {
+        let item = item?;
+        if item.file_type()?.is_dir() {
+            copy_dir(&item.path(), &to.join(item.file_name()))?;
+        } else {
+            fs::copy(item.path(), to.join(item.file_name()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Fairing running `backup` to `dir` every `interval` once the server is up, keeping the
+/// last `keep` backups. A zero interval or no directory disables scheduled backups.
+pub fn fairing(dir: Option<String>, interval: Duration, keep: usize) -> AdHoc {
+    AdHoc::on_liftoff("Scheduled backups", move |rocket| {
+        Box::pin(async move {
+            let (store, dir) = match (rocket.state::<Store>(), dir) {
+                (Some(store), Some(dir)) if !interval.is_zero() => (store.clone(), PathBuf::from(dir)),
+                _ => return,
+            };
+
+            tokio::spawn(async move {
+                let mut ticks = tokio::time::interval(interval);
+                // the first tick completes immediately, leave the startup alone
+                ticks.tick().await;
+                loop {
+                    ticks.tick().await;
+                    let (store, dir) = (store.clone(), dir.clone());
+                    match tokio::task::spawn_blocking(move || backup(&store, &dir, keep)).await {
+                        Ok(Ok(path)) => rocket::info!("backup written to {}", path.display()),
+                        Ok(Err(e)) => rocket::error!("backup failed: {e}"),
+                        Err(e) => rocket::error!("backup panicked: {e}"),
+                    }
+                }
+            });
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::store::{MemoryStore, DEFAULT_CF};
+    use crate::lib::{get_entry_data, insert_entry, new_entry, EntryOptions};
+
+    fn insert(store: &Store, id: &str, data: &[u8]) {
+        let mut meta = vec![];
+        new_entry(&mut meta, data, &EntryOptions::default());
+        insert_entry(id, &meta, data, store).unwrap();
+    }
+
+    #[test]
+    fn backups_are_kept_up_to_the_retention_and_restored() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let db_path = tmp.path().join("database");
+        let dir = tmp.path().join("backups");
+        let store = open_db(db_path.to_str().unwrap()).unwrap();
+        insert(&store, "saved", b"in the backup");
+
+        let first = backup(&store, &dir, 2).unwrap();
+        insert(&store, "later", b"after the backup");
+        for _ in 0..2 {
+            std::thread::sleep(Duration::from_millis(5));
+            backup(&store, &dir, 2).unwrap();
+        }
+        let backups = list_backups(&dir).unwrap();
+        assert_eq!(backups.len(), 2);
+        assert!(!backups.contains(&first));
+        assert!(backup(&Store::new(MemoryStore::default()), &dir, 2).is_err());
+
+        // a backup restores as a whole, the replaced database is kept aside
+        let restored = tmp.path().join("restored");
+        assert!(restore(&backups[0], &restored).unwrap().is_none());
+        let copy = open_db(restored.to_str().unwrap()).unwrap();
+        assert_eq!(get_entry_data("later", None, &copy).unwrap().data, b"after the backup");
+        copy.put(DEFAULT_CF, b"broken", b"garbage").unwrap();
+        drop(copy);
+
+        // a broken snapshot is refused, the database stays as it was
+        let error = restore(&restored, &db_path).unwrap_err();
+        assert!(error.to_string().contains("1 broken records"), "{error}");
+        assert_eq!(get_entry_data("saved", None, &store).unwrap().data, b"in the backup");
+        assert!(restore(&dir.join("missing"), &db_path).is_err());
+
+        let previous = restore(&backups[1], &restored).unwrap().unwrap();
+        assert!(previous.exists());
+        assert!(verify(None, &open_db(restored.to_str().unwrap()).unwrap()).unwrap().is_empty());
+    }
+}
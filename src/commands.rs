@@ -0,0 +1,493 @@
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use chrono::DateTime;
+use clap::{Args, Subcommand};
+
+use crate::backup::{backup, restore};
+use crate::lib::crypto::Keyring;
+use crate::lib::export::{export, import, Format, OnConflict};
+use crate::lib::store::{StorageKind, Store};
+use crate::lib::{
+    delete_entry, get_entry, get_entry_data, list_entries, list_revisions, open_store, purge_expired, reencrypt,
+    stats, verify, Codec, Compression, EntryInfo, Repair,
+};
+use crate::PastebinConfig;
+
+#[derive(Subcommand, Debug)]
+#[allow(clippy::large_enum_variant)]
+pub enum Command {
+    /// Run the web server (default when no command is given)
+    Serve(PastebinConfig),
+    /// List stored pastes, without their content
+    List(ListArgs),
+    /// Show the metadata and revisions of a paste
+    Show(ShowArgs),
+    /// Delete pastes together with their revisions
+    Delete(DeleteArgs),
+    /// Delete expired pastes not yet removed by compaction
+    PurgeExpired(DbArgs),
+    /// Print aggregate statistics as JSON
+    Stats(DbArgs),
+    /// Compact the database, dropping expired pastes and stale counters
+    Compact(DbArgs),
+    /// Encrypt again with the first --encryption-key the pastes stored in plaintext or
+    /// with an older key
+    Reencrypt(DbArgs),
+    /// Check that every paste and revision can be read, and remove those which cannot
+    Verify(VerifyArgs),
+    /// Write a consistent snapshot of the database to a new directory of DIR
+    Backup(BackupArgs),
+    /// Replace the database with a backup, once the backup is verified
+    Restore(RestoreArgs),
+    /// Write every paste, with its revisions and metadata, to a file another database can import
+    Export(ExportArgs),
+    /// Store the pastes of an export under their original IDs
+    Import(ImportArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct DbArgs {
+    #[arg(long = "db", help = "Database file path", default_value = "./pastebin.db")]
+    pub db_path: String,
+
+    #[arg(long = "storage", help = "Storage backend", value_enum, default_value_t = StorageKind::Rocksdb)]
+    pub storage: StorageKind,
+
+    #[arg(long = "encryption-key", value_name = "FILE", help = "Key of the encrypted pastes, see serve --help")]
+    pub encryption_keys: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct ListArgs {
+    #[command(flatten)]
+    pub db: DbArgs,
+
+    #[arg(long, help = "List the pastes following this ID")]
+    pub after: Option<String>,
+
+    #[arg(long, help = "Max number of pastes to list (0 = all)", default_value_t = 0)]
+    pub limit: usize,
+
+    #[arg(long, help = "Print one JSON object per paste")]
+    pub json: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct ShowArgs {
+    #[command(flatten)]
+    pub db: DbArgs,
+
+    pub id: String,
+
+    #[arg(long, help = "Also print the content of the paste")]
+    pub content: bool,
+
+    #[arg(long, help = "Allow --content to read (and so delete) a \"burn\" paste")]
+    pub burn: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct DeleteArgs {
+    #[command(flatten)]
+    pub db: DbArgs,
+
+    #[arg(required = true)]
+    pub ids: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct VerifyArgs {
+    #[command(flatten)]
+    pub db: DbArgs,
+
+    #[arg(long, value_enum, help = "Remove the broken records found, or move them to the quarantine column family")]
+    pub repair: Option<Repair>,
+}
+
+#[derive(Args, Debug)]
+pub struct BackupArgs {
+    #[command(flatten)]
+    pub db: DbArgs,
+
+    pub dir: String,
+
+    #[arg(long, help = "Number of backups kept in DIR, the oldest are deleted (0 = all)", default_value_t = 0)]
+    pub keep: usize,
+}
+
+#[derive(Args, Debug)]
+pub struct RestoreArgs {
+    #[command(flatten)]
+    pub db: DbArgs,
+
+    /// Directory of the backup, as written by `backup`
+    pub backup: String,
+}
+
+#[derive(Args, Debug)]
+pub struct ExportArgs {
+    #[command(flatten)]
+    pub db: DbArgs,
+
+    #[arg(long, value_enum, help = "Layout of the export", default_value_t = Format::Jsonl)]
+    pub format: Format,
+
+    #[arg(long, short, value_name = "FILE", help = "Write the export to FILE instead of the standard output")]
+    pub output: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct ImportArgs {
+    #[command(flatten)]
+    pub db: DbArgs,
+
+    /// Export to import, `-` for the standard input
+    pub file: String,
+
+    #[arg(long, value_enum, help = "Layout of the export", default_value_t = Format::Jsonl)]
+    pub format: Format,
+
+    #[arg(
+        long = "on-conflict",
+        value_enum,
+        help = "What to do with pastes whose ID is already in use",
+        default_value_t = OnConflict::Skip
+    )]
+    pub on_conflict: OnConflict,
+
+    #[arg(long, value_enum, help = "Codec used to store the bodies imported", default_value_t = Codec::None)]
+    pub compression: Codec,
+
+    #[arg(
+        long = "max-storage",
+        help = "Content size in bytes of all pastes and revisions beyond which pastes are left out (0 = unlimited)",
+        default_value_t = 0
+    )]
+    pub max_storage: u64,
+}
+
+fn open(args: &DbArgs) -> Result<Store, String> {
+    let db = open_store(args.storage, &args.db_path).map_err(|e| format!("cannot open {}: {e}", args.db_path))?;
+    Ok(match Keyring::load(&args.encryption_keys)? {
+        Some(keyring) => db.with_keyring(keyring),
+        None => db,
+    })
+}
+
+fn format_timestamp(timestamp: u64) -> String {
+    match timestamp {
+        0 => "never".to_string(),
+        _ => DateTime::from_timestamp(timestamp as i64, 0)
+            .map(|time| time.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_default(),
+    }
+}
+
+fn flags(info: &EntryInfo) -> String {
+    [(info.burn, "burn"), (info.encrypted, "encrypted")]
+        .iter()
+        .filter_map(|(set, name)| set.then_some(*name))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Run a maintenance command against the database, writing its report to `out`.
+/// `Serve` is handled by the caller.
+pub fn run(command: Command, out: &mut dyn Write) -> Result<(), String> {
+    let io_err = |e: std::io::Error| e.to_string();
+
+    match command {
+        Command::Serve(_) => unreachable!("the server is started by main"),
+        Command::List(args) => {
+            let db = open(&args.db)?;
+            let limit = if args.limit == 0 { usize::MAX } else { args.limit };
+            if !args.json {
+                writeln!(out, "{:<24} {:<19} {:<19} {:<12} {:>10}  FLAGS", "ID", "CREATED", "EXPIRES", "LANG", "SIZE")
+                    .map_err(io_err)?;
+            }
+            for info in list_entries(args.after.as_deref(), limit, &db).map_err(io_err)? {
+                if args.json {
+                    writeln!(out, "{}", serde_json::to_string(&info).unwrap()).map_err(io_err)?;
+                } else {
+                    writeln!(
+                        out,
+                        "{:<24} {:<19} {:<19} {:<12} {:>10}  {}",
+                        info.id,
+                        format_timestamp(info.create_timestamp),
+                        format_timestamp(info.expiry_timestamp),
+                        info.lang,
+                        info.size,
+                        flags(&info)
+                    )
+                    .map_err(io_err)?;
+                }
+            }
+        }
+        Command::Show(args) => {
+            let db = open(&args.db)?;
+            let root = get_entry(&args.id, &db)
+                .map_err(io_err)?
+                .ok_or_else(|| format!("paste {} not found", args.id))?;
+            let info = EntryInfo::new(&args.id, &root);
+
+            writeln!(out, "id:        {}", info.id).map_err(io_err)?;
+            writeln!(out, "created:   {}", format_timestamp(info.create_timestamp)).map_err(io_err)?;
+            writeln!(out, "expires:   {}", format_timestamp(info.expiry_timestamp)).map_err(io_err)?;
+            writeln!(out, "lang:      {}", info.lang).map_err(io_err)?;
+            writeln!(out, "size:      {}", info.size).map_err(io_err)?;
+            writeln!(out, "flags:     {}", flags(&info)).map_err(io_err)?;
+            if info.max_views > 0 {
+                writeln!(out, "max views: {}", info.max_views).map_err(io_err)?;
+            }
+            if let Some(owner) = &info.owner {
+                writeln!(out, "owner:     {owner}").map_err(io_err)?;
+            }
+            if let Some(api_key) = &info.api_key {
+                writeln!(out, "api key:   {api_key}").map_err(io_err)?;
+            }
+            for (revision, timestamp) in list_revisions(&args.id, &root, &db).map_err(io_err)? {
+                writeln!(out, "revision:  {revision} ({})", format_timestamp(timestamp)).map_err(io_err)?;
+            }
+
+            if args.content {
+                if info.burn && !args.burn {
+                    return Err("reading a \"burn\" paste deletes it, add --burn to proceed".to_string());
+                }
+                let paste = get_entry_data(&args.id, None, &db).map_err(io_err)?;
+                writeln!(out).map_err(io_err)?;
+                std::io::copy(&mut paste.content_reader().map_err(io_err)?, out).map_err(io_err)?;
+            }
+        }
+        Command::Delete(args) => {
+            let db = open(&args.db)?;
+            for id in &args.ids {
+                if get_entry(id, &db).map_err(io_err)?.is_none() {
+                    return Err(format!("paste {id} not found"));
+                }
+                delete_entry(id, &db).map_err(io_err)?;
+                writeln!(out, "deleted {id}").map_err(io_err)?;
+            }
+        }
+        Command::PurgeExpired(args) => {
+            let db = open(&args)?;
+            let purged = purge_expired(&db).map_err(io_err)?;
+            writeln!(out, "purged {} expired pastes", purged.ids.len()).map_err(io_err)?;
+        }
+        Command::Stats(args) => {
+            let db = open(&args)?;
+            let stats = stats(&db).map_err(io_err)?;
+            writeln!(out, "{}", serde_json::to_string_pretty(&stats).unwrap()).map_err(io_err)?;
+        }
+        Command::Compact(args) => {
+            let db = open(&args)?;
+            db.compact().map_err(io_err)?;
+            writeln!(out, "compacted {}", args.db_path).map_err(io_err)?;
+        }
+        Command::Reencrypt(args) => {
+            let db = open(&args)?;
+            let count = reencrypt(&db).map_err(io_err)?;
+            writeln!(out, "reencrypted {count} pastes and revisions").map_err(io_err)?;
+        }
+        Command::Verify(args) => {
+            let db = open(&args.db)?;
+            let broken = verify(args.repair, &db).map_err(io_err)?;
+            for record in &broken {
+                match record.revision {
+                    Some(revision) => writeln!(out, "{} revision {revision}: {}", record.id, record.error),
+                    None => writeln!(out, "{}: {}", record.id, record.error),
+                }
+                .map_err(io_err)?;
+            }
+            match args.repair {
+                _ if broken.is_empty() => writeln!(out, "no broken records").map_err(io_err)?,
+                Some(Repair::Quarantine) => writeln!(out, "quarantined {} records", broken.len()).map_err(io_err)?,
+                Some(Repair::Delete) => writeln!(out, "deleted {} records", broken.len()).map_err(io_err)?,
+                None => return Err(format!("{} broken records, see --repair", broken.len())),
+            }
+        }
+        Command::Backup(args) => {
+            let db = open(&args.db)?;
+            let path = backup(&db, Path::new(&args.dir), args.keep).map_err(io_err)?;
+            writeln!(out, "backup written to {}", path.display()).map_err(io_err)?;
+        }
+        Command::Restore(args) => {
+            if args.db.storage != StorageKind::Rocksdb {
+                return Err("backups need the rocksdb storage backend".to_string());
+            }
+            let previous = restore(Path::new(&args.backup), Path::new(&args.db.db_path)).map_err(io_err)?;
+            writeln!(out, "restored {} from {}", args.db.db_path, args.backup).map_err(io_err)?;
+            if let Some(previous) = previous {
+                writeln!(out, "the previous database was moved to {}", previous.display()).map_err(io_err)?;
+            }
+        }
+        Command::Export(args) => {
+            let db = open(&args.db)?;
+            match &args.output {
+                Some(path) => {
+                    let mut file = File::create(path).map_err(|e| format!("cannot write {path}: {e}"))?;
+                    let count = export(args.format, &db, &mut file).map_err(io_err)?;
+                    writeln!(out, "exported {count} pastes to {path}").map_err(io_err)?;
+                }
+                None => {
+                    export(args.format, &db, out).map_err(io_err)?;
+                }
+            }
+        }
+        Command::Import(args) => {
+            let db = open(&args.db)?;
+            let mut input: Box<dyn Read> = match args.file.as_str() {
+                "-" => Box::new(std::io::stdin().lock()),
+                path => Box::new(File::open(path).map_err(|e| format!("cannot read {path}: {e}"))?),
+            };
+            let compression = Compression { codec: args.compression, level: 3, threshold: 1024 };
+            let imported = import(args.format, &mut input, args.on_conflict, &compression, args.max_storage, &db);
+            let imported = imported.map_err(io_err)?;
+
+            for id in &imported.skipped {
+                writeln!(out, "skipped {id}: the ID is in use").map_err(io_err)?;
+            }
+            for id in &imported.over_budget {
+                writeln!(out, "skipped {id}: over --max-storage").map_err(io_err)?;
+            }
+            for id in &imported.overwritten {
+                writeln!(out, "overwrote {id}").map_err(io_err)?;
+            }
+            for (id, new_id) in &imported.renamed {
+                writeln!(out, "renamed {id} to {new_id}").map_err(io_err)?;
+            }
+            writeln!(out, "imported {} pastes, left out {} expired", imported.imported, imported.expired)
+                .map_err(io_err)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::{insert_entry, new_entry, open_db, EntryOptions};
+    use tempfile::TempDir;
+
+    fn run_command(args: &[&str]) -> Result<String, String> {
+        let command = match <crate::Cli as clap::Parser>::parse_from(args).command {
+            Some(command) => command,
+            None => panic!("no command in {args:?}"),
+        };
+        let mut out = vec![];
+        run(command, &mut out)?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    fn insert(db: &Store, id: &str, data: &[u8], opts: &EntryOptions) {
+        let mut buf = vec![];
+        new_entry(&mut buf, data, opts);
+        insert_entry(id, &buf, data, db).unwrap();
+    }
+
+    #[test]
+    fn maintenance_commands_operate_on_the_database() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("database").to_str().unwrap().to_string();
+        {
+            let db = open_db(&path).unwrap();
+            insert(&db, "alive", b"hello", &EntryOptions { lang: "rust", ..Default::default() });
+            insert(&db, "burned", b"secret", &EntryOptions { burn: true, ..Default::default() });
+            let mut buf = vec![];
+            new_entry(&mut buf, b"old", &EntryOptions { ttl: 1, ..Default::default() });
+            insert_entry("expired", &buf, b"old", &db).unwrap();
+        }
+        std::thread::sleep(std::time::Duration::from_millis(1100));
+
+        let list = run_command(&["pastebin", "list", "--db", &path]).unwrap();
+        assert_eq!(list.lines().count(), 4);
+        assert!(list.lines().any(|line| line.starts_with("alive") && line.contains("rust")));
+
+        let show = run_command(&["pastebin", "show", "--db", &path, "alive", "--content"]).unwrap();
+        assert!(show.contains("lang:      rust"));
+        assert!(show.ends_with("\nhello"));
+        assert!(run_command(&["pastebin", "show", "--db", &path, "burned", "--content"]).is_err());
+
+        assert_eq!(run_command(&["pastebin", "purge-expired", "--db", &path]).unwrap(), "purged 1 expired pastes\n");
+        assert_eq!(run_command(&["pastebin", "delete", "--db", &path, "burned"]).unwrap(), "deleted burned\n");
+        assert!(run_command(&["pastebin", "delete", "--db", &path, "burned"]).is_err());
+
+        let stats: serde_json::Value =
+            serde_json::from_str(&run_command(&["pastebin", "stats", "--db", &path]).unwrap()).unwrap();
+        assert_eq!(stats["pastes"], 1);
+        assert_eq!(stats["bytes"], 5);
+    }
+
+    #[test]
+    fn verify_reports_broken_records_until_repaired() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("database").to_str().unwrap().to_string();
+        {
+            let db = open_db(&path).unwrap();
+            insert(&db, "alive", b"hello", &EntryOptions::default());
+            db.put(crate::lib::store::DEFAULT_CF, b"broken", b"garbage").unwrap();
+        }
+
+        let verify = |repair: &[&str]| run_command(&[&["pastebin", "verify", "--db", &path], repair].concat());
+        assert_eq!(verify(&[]).unwrap_err(), "1 broken records, see --repair");
+        assert_eq!(verify(&["--repair", "delete"]).unwrap().lines().last(), Some("deleted 1 records"));
+        assert_eq!(verify(&[]).unwrap(), "no broken records\n");
+    }
+
+    #[test]
+    fn backup_and_restore_commands_round_trip() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("database").to_str().unwrap().to_string();
+        let dir = tmp.path().join("backups").to_str().unwrap().to_string();
+        insert(&open_db(&path).unwrap(), "alive", b"hello", &EntryOptions::default());
+
+        let written = run_command(&["pastebin", "backup", "--db", &path, &dir]).unwrap();
+        let backup = written.trim().strip_prefix("backup written to ").unwrap().to_string();
+        run_command(&["pastebin", "delete", "--db", &path, "alive"]).unwrap();
+
+        let restored = run_command(&["pastebin", "restore", "--db", &path, &backup]).unwrap();
+        assert!(restored.contains("the previous database was moved to"), "{restored}");
+        let show = run_command(&["pastebin", "show", "--db", &path, "alive", "--content"]).unwrap();
+        assert!(show.ends_with("\nhello"));
+        assert!(run_command(&["pastebin", "backup", "--db", &path, "--storage", "fs", &dir]).is_err());
+    }
+
+    #[test]
+    fn export_and_import_commands_copy_pastes_between_databases() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("database").to_str().unwrap().to_string();
+        let copy = tmp.path().join("copy").to_str().unwrap().to_string();
+        let file = tmp.path().join("pastes.tar").to_str().unwrap().to_string();
+        insert(&open_db(&path).unwrap(), "alive", b"hello", &EntryOptions { lang: "rust", ..Default::default() });
+
+        let exported = run_command(&["pastebin", "export", "--db", &path, "--format", "tar", "--output", &file]);
+        assert_eq!(exported.unwrap(), format!("exported 1 pastes to {file}\n"));
+        let import = ["pastebin", "import", "--db", &copy, "--format", "tar", &file];
+        assert_eq!(run_command(&import).unwrap(), "imported 1 pastes, left out 0 expired\n");
+        assert!(run_command(&import).unwrap().starts_with("skipped alive"));
+
+        let show = run_command(&["pastebin", "show", "--db", &copy, "alive", "--content"]).unwrap();
+        assert!(show.contains("lang:      rust"));
+        assert!(show.ends_with("\nhello"));
+        let lines = run_command(&["pastebin", "export", "--db", &copy]).unwrap();
+        assert!(lines.starts_with("{\"id\":\"alive\""), "{lines}");
+    }
+
+    #[test]
+    fn reencrypt_moves_plaintext_pastes_to_the_encryption_key() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("database").to_str().unwrap().to_string();
+        let key = tmp.path().join("pastebin.key").to_str().unwrap().to_string();
+        std::fs::write(&key, "42".repeat(32)).unwrap();
+        insert(&open_db(&path).unwrap(), "alive", b"hello", &EntryOptions::default());
+
+        let reencrypt = ["pastebin", "reencrypt", "--db", &path, "--encryption-key", &key];
+        assert_eq!(run_command(&reencrypt).unwrap(), "reencrypted 1 pastes and revisions\n");
+        assert_eq!(run_command(&reencrypt).unwrap(), "reencrypted 0 pastes and revisions\n");
+        assert!(run_command(&["pastebin", "show", "--db", &path, "alive", "--content"]).is_err());
+        let show = run_command(&["pastebin", "show", "--db", &path, "alive", "--content", "--encryption-key", &key]);
+        assert!(show.unwrap().ends_with("\nhello"));
+    }
+}
@@ -0,0 +1,163 @@
+//! Encryption at rest of paste bodies, with AES-256-GCM. A key is 32 random bytes, kept hex
+//! encoded in a file (ie. `openssl rand -hex 32 > pastebin.key`). Every record notes the ID
+//! of the key it is encrypted with, so that keys can be rotated, see `reencrypt`.
+
+use std::fs;
+use std::io;
+
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use sha2::{Digest, Sha256};
+
+/// An encryption key, with the key of the digests of the bodies it encrypts.
+pub struct Key {
+    id: u32,
+    aead: LessSafeKey,
+    mac: hmac::Key,
+}
+
+impl Key {
+    /// Derive the keys from `secret`. The ID is taken from its SHA-256 digest, so the same
+    /// secret always gets the same ID.
+    pub fn new(secret: &[u8]) -> Result<Self, String> {
+        if secret.len() != 32 {
+            return Err(format!("encryption keys are 32 bytes long, not {}", secret.len()));
+        }
+        let id = u32::from_be_bytes(Sha256::digest(secret)[..4].try_into().unwrap());
+        // 0 stands for records stored in plaintext
+        if id == 0 {
+            return Err("this key cannot be used, generate another one".to_string());
+        }
+
+        let master = hmac::Key::new(hmac::HMAC_SHA256, secret);
+        let aead = UnboundKey::new(&AES_256_GCM, hmac::sign(&master, b"pastebin encryption").as_ref()).unwrap();
+        let mac = hmac::Key::new(hmac::HMAC_SHA256, hmac::sign(&master, b"pastebin digest").as_ref());
+        Ok(Key { id, aead: LessSafeKey::new(aead), mac })
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Encrypt `data`, bound to `aad` (the key of the record, so that records cannot be
+    /// swapped): a random nonce followed by the ciphertext and its tag.
+    pub fn seal(&self, data: &[u8], aad: &[u8]) -> Vec<u8> {
+        let mut nonce = [0u8; NONCE_LEN];
+        SystemRandom::new().fill(&mut nonce).expect("no random source");
+        let mut sealed = data.to_vec();
+        self.aead
+            .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::from(aad), &mut sealed)
+            .unwrap();
+        [&nonce[..], &sealed].concat()
+    }
+
+    /// Decrypt a value returned by `seal` with the same `aad`.
+    pub fn open(&self, sealed: &[u8], aad: &[u8]) -> io::Result<Vec<u8>> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "cannot decrypt record");
+        if sealed.len() < NONCE_LEN {
+            return Err(invalid());
+        }
+        let (nonce, data) = sealed.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| invalid())?;
+        let mut data = data.to_vec();
+        let len = self.aead.open_in_place(nonce, Aad::from(aad), &mut data).map_err(|_| invalid())?.len();
+        data.truncate(len);
+        Ok(data)
+    }
+
+    /// Start the digest of a body encrypted with this key: an HMAC, so that the digests
+    /// stored in the clear say nothing about the content.
+    pub fn digest(&self) -> hmac::Context {
+        hmac::Context::with_key(&self.mac)
+    }
+}
+
+/// The keys given with `--encryption-key`: the first one encrypts new bodies, the others
+/// only decrypt the bodies written before a rotation.
+pub struct Keyring(Vec<Key>);
+
+impl Keyring {
+    pub fn new(secrets: &[&[u8]]) -> Result<Self, String> {
+        let mut keys: Vec<Key> = vec![];
+        for secret in secrets {
+            let key = Key::new(secret)?;
+            if keys.iter().any(|other| other.id == key.id) {
+                return Err(format!("duplicated encryption key {:08x}", key.id));
+            }
+            keys.push(key);
+        }
+        if keys.is_empty() {
+            return Err("no encryption key".to_string());
+        }
+        Ok(Keyring(keys))
+    }
+
+    /// Load the hex encoded keys of `paths`, `None` when there are none.
+    pub fn load(paths: &[String]) -> Result<Option<Self>, String> {
+        if paths.is_empty() {
+            return Ok(None);
+        }
+        let mut secrets = vec![];
+        for path in paths {
+            let content = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+            secrets.push(unhex(content.trim()).ok_or_else(|| format!("{path}: not a hex encoded key"))?);
+        }
+        let secrets: Vec<&[u8]> = secrets.iter().map(Vec::as_slice).collect();
+        Keyring::new(&secrets).map(Some)
+    }
+
+    /// The key encrypting new bodies.
+    pub fn current(&self) -> &Key {
+        &self.0[0]
+    }
+
+    pub fn get(&self, id: u32) -> io::Result<&Key> {
+        self.0.iter().find(|key| key.id == id).ok_or_else(|| missing_key(id))
+    }
+}
+
+/// Error reading a record encrypted with key `id`, which is not loaded.
+pub fn missing_key(id: u32) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("record encrypted with unknown key {id:08x}"))
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sealed_records_open_with_their_key_and_location_only() {
+        let keyring = Keyring::new(&[&[1; 32], &[2; 32]]).unwrap();
+        let (current, previous) = (keyring.current(), keyring.get(Key::new(&[2; 32]).unwrap().id()).unwrap());
+
+        let sealed = current.seal(b"secret", b"paste");
+        assert_ne!(sealed, current.seal(b"secret", b"paste"));
+        assert_eq!(current.open(&sealed, b"paste").unwrap(), b"secret");
+        assert!(current.open(&sealed, b"other").is_err());
+        assert!(previous.open(&sealed, b"paste").is_err());
+        assert!(keyring.get(0).is_err());
+    }
+
+    #[test]
+    fn keys_are_loaded_from_hex_files() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let path = |name: &str| tmp.path().join(name).to_str().unwrap().to_string();
+        fs::write(path("new.key"), format!("{}\n", "ab".repeat(32))).unwrap();
+        fs::write(path("short.key"), "abcd").unwrap();
+
+        assert!(Keyring::load(&[]).unwrap().is_none());
+        let keyring = Keyring::load(&[path("new.key")]).unwrap().unwrap();
+        assert_eq!(keyring.current().id(), Key::new(&[0xab; 32]).unwrap().id());
+        assert!(Keyring::load(&[path("short.key")]).is_err());
+        assert!(Keyring::load(&[path("new.key"), path("new.key")]).is_err());
+        assert!(Keyring::load(&[path("missing.key")]).is_err());
+    }
+}
@@ -0,0 +1,594 @@
+//! Export of the pastes, with their revisions and all of their metadata, to a file which
+//! another database (of any storage backend, encrypted at rest or not) can import. Bodies
+//! are exported decoded, so they are compressed and encrypted again as the importing
+//! database stores them.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+use crate::api_generated::api::root_as_entry;
+use super::store::{Store, DEFAULT_CF};
+use super::{
+    compress, delete_entry, get_entry, insert_body, is_expired, is_past, load_paste, new_entry, now, rebuild_entry,
+    revision_prefix, save_revision, scan_entries, stored_bytes, ChunkWriter, Compression, EntryChanges, EntryOptions,
+    StoredBody, CHUNK_SIZE, REVISIONS_CF,
+};
+
+/// Name of the manifest of a tar export, its last file.
+pub const MANIFEST: &str = "manifest.json";
+
+/// Longest paste ID `import` keeps, see `valid_id`.
+const MAX_ID_LEN: usize = 64;
+
+/// Length of the IDs given to the imported pastes whose ID is not valid, and least length
+/// of the ones given on a conflict: the default length of the URL slug, long enough not to
+/// run out of free IDs.
+const NEW_ID_LEN: usize = 21;
+
+/// Layout of an export.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    /// One JSON object per paste and per line, with the bodies in base64
+    Jsonl,
+    /// A tar archive: a file per body, then `manifest.json`, a JSON array of the pastes
+    Tar,
+}
+
+/// What `import` does with a paste whose ID is already in use.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum OnConflict {
+    /// Keep the paste stored, leave out the imported one
+    Skip,
+    /// Replace the paste stored, with its revisions
+    Overwrite,
+    /// Import the paste under a new random ID
+    Rename,
+}
+
+/// A paste with its revisions, as exported.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ExportedPaste {
+    pub id: String,
+    pub create_timestamp: u64,
+    /// `0` for a paste which never expires.
+    pub expiry_timestamp: u64,
+    pub burn: bool,
+    /// Number of reads allowed, `0` for no limit. The reads counted so far are not exported.
+    pub max_views: u32,
+    pub pinned: bool,
+    pub owner: Option<String>,
+    pub api_key: Option<String>,
+    /// Digest of the owner token, in base64: the token handed out keeps working.
+    pub owner_token: Option<String>,
+    /// Digest of the preview token of a "burn" paste, in base64.
+    pub preview_token: Option<String>,
+    /// Oldest first, the last one is the current version.
+    pub revisions: Vec<ExportedRevision>,
+}
+
+/// A version of an exported paste.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ExportedRevision {
+    pub revision: u32,
+    /// `0` for the original version.
+    pub update_timestamp: u64,
+    pub lang: String,
+    /// Encrypted by the client: the body is the ciphertext it sent.
+    pub encrypted: bool,
+    /// The content, in base64. Left out of the manifest of a tar export, see `file`.
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "base64_body")]
+    pub body: Option<Vec<u8>>,
+    /// Path of the content in a tar export.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+}
+
+mod base64_body {
+    use super::{Engine, BASE64};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(body: &Option<Vec<u8>>, serializer: S) -> Result<S::Ok, S::Error> {
+        match body {
+            Some(body) => serializer.serialize_some(&BASE64.encode(body)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Vec<u8>>, D::Error> {
+        let body = Option::<String>::deserialize(deserializer)?;
+        body.map(|body| BASE64.decode(body).map_err(serde::de::Error::custom)).transpose()
+    }
+}
+
+/// Outcome of an `import`.
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct Imported {
+    /// Number of pastes stored, renamed and overwritten ones included.
+    pub imported: usize,
+    /// Number of pastes left out as they already expired.
+    pub expired: usize,
+    /// IDs already in use, whose paste was left out (`OnConflict::Skip`).
+    pub skipped: Vec<String>,
+    /// IDs of the pastes left out as they did not fit within the storage budget.
+    pub over_budget: Vec<String>,
+    /// IDs already in use, whose paste was replaced (`OnConflict::Overwrite`).
+    pub overwritten: Vec<String>,
+    /// New IDs of the pastes imported under another ID, by original ID (`OnConflict::Rename`,
+    /// or an original ID the server would not hand out).
+    pub renamed: BTreeMap<String, String>,
+}
+
+fn invalid(error: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error.to_string())
+}
+
+/// Write every live paste of `state` to `out`, returning their number. Reading a paste for
+/// the export neither deletes a "burn" paste nor counts a view.
+pub fn export(format: Format, state: &Store, out: &mut dyn Write) -> io::Result<usize> {
+    let pastes = scan_entries(state, DEFAULT_CF, b"").filter_map(|item| match item {
+        Ok((_, value)) if is_expired(&value) => None,
+        Ok((key, value)) => Some(export_paste(&String::from_utf8_lossy(&key), value, state)),
+        Err(e) => Some(Err(e)),
+    });
+
+    match format {
+        Format::Jsonl => {
+            let mut count = 0;
+            for paste in pastes {
+                serde_json::to_writer(&mut *out, &paste?)?;
+                out.write_all(b"\n")?;
+                count += 1;
+            }
+            Ok(count)
+        }
+        Format::Tar => {
+            // the bodies are written as the pastes are read, only the metadata waits for the manifest
+            let mut archive = tar::Builder::new(out);
+            let mut manifest = vec![];
+            let mut files = 0;
+            for paste in pastes {
+                let mut paste = paste?;
+                for revision in &mut paste.revisions {
+                    let file = format!("bodies/{files}");
+                    append_file(&mut archive, &file, &revision.body.take().unwrap_or_default())?;
+                    revision.file = Some(file);
+                    files += 1;
+                }
+                manifest.push(paste);
+            }
+            append_file(&mut archive, MANIFEST, &serde_json::to_vec_pretty(&manifest)?)?;
+            archive.finish()?;
+            Ok(manifest.len())
+        }
+    }
+}
+
+fn append_file(archive: &mut tar::Builder<&mut dyn Write>, path: &str, data: &[u8]) -> io::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(now());
+    archive.append_data(&mut header, path, data)
+}
+
+/// The paste `id`, whose latest metadata is `current`, with its revisions and their content.
+fn export_paste(id: &str, current: Vec<u8>, state: &Store) -> io::Result<ExportedPaste> {
+    let export_revision = |meta| {
+        export_revision(meta, state).map_err(|e| io::Error::new(e.kind(), format!("paste {id}: {e}")))
+    };
+    let prefix = revision_prefix(id);
+    let mut revisions = vec![];
+    for item in scan_entries(state, REVISIONS_CF, &prefix) {
+        let (key, value) = item?;
+        if !key.starts_with(&prefix) {
+            break;
+        }
+        revisions.push(export_revision(value)?);
+    }
+    revisions.push(export_revision(current.clone())?);
+
+    let entry = root_as_entry(&current).unwrap();
+    Ok(ExportedPaste {
+        id: id.to_string(),
+        create_timestamp: entry.create_timestamp(),
+        expiry_timestamp: entry.expiry_timestamp(),
+        burn: entry.burn(),
+        max_views: entry.max_views(),
+        pinned: entry.pinned(),
+        owner: entry.owner().map(str::to_string),
+        api_key: entry.api_key().map(str::to_string),
+        owner_token: entry.owner_token().map(|token| BASE64.encode(token.bytes())),
+        preview_token: entry.preview_token().map(|token| BASE64.encode(token.bytes())),
+        revisions,
+    })
+}
+
+fn export_revision(meta: Vec<u8>, state: &Store) -> io::Result<ExportedRevision> {
+    let paste = load_paste(meta, state)?;
+    let entry = paste.entry();
+    Ok(ExportedRevision {
+        revision: entry.revision(),
+        update_timestamp: entry.update_timestamp(),
+        lang: entry.lang().unwrap_or("markup").to_string(),
+        encrypted: entry.encrypted(),
+        body: Some(paste.content()?.into_owned()),
+        file: None,
+    })
+}
+
+/// Store the pastes read from `input` under their original IDs, unless `on_conflict` says
+/// otherwise, with their timestamps, revisions and tokens. Bodies are stored with
+/// `compression`. Pastes which already expired are left out, as are those which would take
+/// the content stored beyond `max_storage` bytes (`0` for no limit): unlike creates, an
+/// import evicts nothing.
+pub fn import(
+    format: Format,
+    input: &mut dyn Read,
+    on_conflict: OnConflict,
+    compression: &Compression,
+    max_storage: u64,
+    state: &Store,
+) -> io::Result<Imported> {
+    let mut imported = Imported::default();
+    match format {
+        Format::Jsonl => {
+            for (number, line) in BufReader::new(input).lines().enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let paste = serde_json::from_str(&line).map_err(|e| invalid(format!("line {}: {e}", number + 1)))?;
+                import_paste(paste, on_conflict, compression, max_storage, state, &mut imported)?;
+            }
+        }
+        Format::Tar => {
+            let (pastes, mut bodies) = read_archive(input)?;
+            for mut paste in pastes {
+                for revision in &mut paste.revisions {
+                    if let Some(file) = revision.file.take() {
+                        revision.body = Some(bodies.read(&file)?);
+                    }
+                }
+                import_paste(paste, on_conflict, compression, max_storage, state, &mut imported)?;
+            }
+        }
+    }
+    Ok(imported)
+}
+
+/// The files of a tar export other than the manifest, copied to a temporary file as the
+/// archive is read.
+struct Bodies {
+    spill: File,
+    /// Offset and length in `spill`, by path.
+    files: HashMap<String, (u64, u64)>,
+}
+
+impl Bodies {
+    fn read(&mut self, path: &str) -> io::Result<Vec<u8>> {
+        let (offset, len) = *self.files.get(path).ok_or_else(|| invalid(format!("no {path} in the archive")))?;
+        self.spill.seek(SeekFrom::Start(offset))?;
+        let mut data = vec![];
+        (&mut self.spill).take(len).read_to_end(&mut data)?;
+        Ok(data)
+    }
+}
+
+/// The pastes of a tar export, whose revisions refer to their content in `Bodies`. The
+/// manifest may come anywhere in the archive.
+fn read_archive(input: &mut dyn Read) -> io::Result<(Vec<ExportedPaste>, Bodies)> {
+    let mut manifest = None;
+    let mut bodies = Bodies { spill: tempfile::tempfile()?, files: HashMap::new() };
+    let mut offset = 0;
+    for file in tar::Archive::new(input).entries()? {
+        let mut file = file?;
+        let path = file.path()?.to_string_lossy().into_owned();
+        if path == MANIFEST {
+            let mut data = vec![];
+            file.read_to_end(&mut data)?;
+            manifest = Some(serde_json::from_slice::<Vec<ExportedPaste>>(&data).map_err(invalid)?);
+        } else {
+            let len = io::copy(&mut file, &mut bodies.spill)?;
+            bodies.files.insert(path, (offset, len));
+            offset += len;
+        }
+    }
+
+    let pastes = manifest.ok_or_else(|| invalid(format!("no {MANIFEST} in the archive")))?;
+    Ok((pastes, bodies))
+}
+
+/// Whether `id` is an ID the server could have handed out: made of the characters of the
+/// default URL slug, and short enough for the keys derived from it.
+fn valid_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_ID_LEN && id.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'))
+}
+
+fn import_paste(
+    paste: ExportedPaste,
+    on_conflict: OnConflict,
+    compression: &Compression,
+    max_storage: u64,
+    state: &Store,
+    imported: &mut Imported,
+) -> io::Result<()> {
+    if is_past(paste.expiry_timestamp) {
+        imported.expired += 1;
+        return Ok(());
+    }
+    if paste.revisions.is_empty() {
+        return Err(invalid(format!("paste {} has no revisions", paste.id)));
+    }
+    let decode = |token: &Option<String>| token.as_ref().map(|token| BASE64.decode(token).map_err(invalid));
+    let owner_token = decode(&paste.owner_token).transpose()?;
+    let preview_token = decode(&paste.preview_token).transpose()?;
+    let size: u64 = paste.revisions.iter().filter_map(|revision| revision.body.as_ref())
+        .map(|body| body.len() as u64)
+        .sum();
+    if max_storage > 0 && stored_bytes(state)? + size > max_storage {
+        imported.over_budget.push(paste.id);
+        return Ok(());
+    }
+
+    let mut id = paste.id.clone();
+    if !valid_id(&id) {
+        while !valid_id(&id) || get_entry(&id, state)?.is_some() {
+            id = nanoid::nanoid!(NEW_ID_LEN);
+        }
+        imported.renamed.insert(paste.id.clone(), id.clone());
+    }
+    match get_entry(&id, state)? {
+        // an expired paste only waits for compaction, it is no conflict
+        Some(current) if is_expired(&current) => {
+            delete_entry(&id, state)?;
+        }
+        Some(_) => match on_conflict {
+            OnConflict::Skip => {
+                imported.skipped.push(id);
+                return Ok(());
+            }
+            OnConflict::Overwrite => {
+                delete_entry(&id, state)?;
+                imported.overwritten.push(id.clone());
+            }
+            OnConflict::Rename => {
+                let len = paste.id.len().max(NEW_ID_LEN);
+                while get_entry(&id, state)?.is_some() {
+                    id = nanoid::nanoid!(len);
+                }
+                imported.renamed.insert(paste.id.clone(), id.clone());
+            }
+        },
+        None => {}
+    }
+
+    let mut current: Option<Vec<u8>> = None;
+    for revision in &paste.revisions {
+        let data = revision.body.as_deref().ok_or_else(|| {
+            invalid(format!("revision {} of paste {} has no body", revision.revision, paste.id))
+        })?;
+        let body = store_body(data, compression, state)?;
+
+        let mut meta = vec![];
+        new_entry(
+            &mut meta,
+            &[],
+            &EntryOptions {
+                lang: &revision.lang,
+                ttl: 0,
+                burn: paste.burn,
+                max_views: paste.max_views,
+                encrypted: revision.encrypted,
+                owner_token: owner_token.as_deref(),
+                preview_token: preview_token.as_deref(),
+                api_key: paste.api_key.as_deref(),
+                owner: paste.owner.as_deref(),
+                codec: body.codec,
+            },
+        );
+        let entry = root_as_entry(&meta).unwrap();
+        let changes = EntryChanges {
+            create_timestamp: paste.create_timestamp,
+            expiry_timestamp: paste.expiry_timestamp,
+            revision: revision.revision,
+            update_timestamp: revision.update_timestamp,
+            pinned: paste.pinned,
+            ..EntryChanges::of(&entry)
+        };
+        let mut next = vec![];
+        rebuild_entry(&mut next, &entry, &changes);
+
+        match &current {
+            Some(current) => save_revision(&id, current, &next, Some(&body), state)?,
+            None => insert_body(&id, &next, &body, state)?,
+        }
+        current = get_entry(&id, state)?;
+    }
+    imported.imported += 1;
+    Ok(())
+}
+
+/// Store an imported body, in chunks when it is larger than a chunk like the server does.
+fn store_body(data: &[u8], compression: &Compression, state: &Store) -> io::Result<StoredBody<'static>> {
+    if data.len() <= CHUNK_SIZE {
+        let (stored, codec) = compress(data, compression);
+        return Ok(StoredBody::inline(stored.into_owned(), data.len() as u64, codec, state));
+    }
+    let mut writer = ChunkWriter::new(compression, state)?;
+    writer.write_all(data)?;
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::store::MemoryStore;
+    use super::super::{get_entry_data, hash_token, insert_entry, is_owner, list_revisions, new_revision, Codec};
+    use super::*;
+
+    fn compression() -> Compression {
+        Compression { codec: Codec::Zstd, level: 3, threshold: 0 }
+    }
+
+    fn insert(state: &Store, id: &str, data: &[u8], opts: &EntryOptions) {
+        let mut meta = vec![];
+        new_entry(&mut meta, data, opts);
+        insert_entry(id, &meta, data, state).unwrap();
+    }
+
+    fn edit(state: &Store, id: &str, data: &[u8]) {
+        let current = get_entry(id, state).unwrap().unwrap();
+        let body = StoredBody::inline(data.to_vec(), data.len() as u64, Codec::None, state);
+        let mut next = vec![];
+        new_revision(&mut next, &current, Some(&body), Some("rust"), None);
+        save_revision(id, &current, &next, Some(&body), state).unwrap();
+    }
+
+    fn source() -> Store {
+        let state = Store::new(MemoryStore::default());
+        let token = hash_token("owner");
+        insert(&state, "edited", b"first", &EntryOptions { owner_token: Some(&token), ..Default::default() });
+        edit(&state, "edited", b"second");
+        insert(&state, "burn", b"secret", &EntryOptions { burn: true, ttl: 3600, ..Default::default() });
+        let large = vec![b'x'; CHUNK_SIZE + 10];
+        let mut writer = ChunkWriter::new(&compression(), &state).unwrap();
+        writer.write_all(&large).unwrap();
+        let body = writer.finish().unwrap();
+        let mut meta = vec![];
+        new_entry(&mut meta, &[], &EntryOptions { codec: body.codec, ..Default::default() });
+        insert_body("large", &meta, &body, &state).unwrap();
+        state
+    }
+
+    #[test]
+    fn exports_import_with_their_ids_revisions_and_metadata() {
+        for format in [Format::Jsonl, Format::Tar] {
+            let state = source();
+            let mut exported = vec![];
+            assert_eq!(export(format, &state, &mut exported).unwrap(), 3);
+            // exporting a "burn" paste does not burn it
+            assert!(get_entry("burn", &state).unwrap().is_some());
+
+            let copy = Store::new(MemoryStore::default());
+            let imported = import(format, &mut exported.as_slice(), OnConflict::Skip, &compression(), 0, &copy);
+            let imported = imported.unwrap();
+            assert_eq!(imported.imported, 3);
+
+            for id in ["edited", "burn", "large"] {
+                let (original, restored) = (get_entry(id, &state).unwrap().unwrap(), get_entry(id, &copy).unwrap());
+                let restored = restored.unwrap();
+                let (original, restored) = (root_as_entry(&original).unwrap(), root_as_entry(&restored).unwrap());
+                assert_eq!(original.create_timestamp(), restored.create_timestamp());
+                assert_eq!(original.expiry_timestamp(), restored.expiry_timestamp());
+                assert_eq!(original.update_timestamp(), restored.update_timestamp());
+                assert_eq!(original.burn(), restored.burn());
+                assert_eq!(original.lang(), restored.lang());
+            }
+            let edited = get_entry("edited", &copy).unwrap().unwrap();
+            assert!(is_owner(&edited, "owner"));
+            assert_eq!(list_revisions("edited", &edited, &copy).unwrap().len(), 2);
+            assert_eq!(get_entry_data("edited", Some(0), &copy).unwrap().content().unwrap(), &b"first"[..]);
+            assert_eq!(get_entry_data("edited", None, &copy).unwrap().content().unwrap(), &b"second"[..]);
+            assert_eq!(get_entry_data("large", None, &copy).unwrap().content().unwrap().len(), CHUNK_SIZE + 10);
+        }
+    }
+
+    #[test]
+    fn imports_resolve_conflicts_and_skip_expired_pastes() {
+        let state = source();
+        let mut exported = vec![];
+        export(Format::Jsonl, &state, &mut exported).unwrap();
+        let first = exported.split(|&b| b == b'\n').next().unwrap();
+        let mut expired: ExportedPaste = serde_json::from_slice(first).unwrap();
+        expired.id = "gone".to_string();
+        expired.expiry_timestamp = 1;
+        exported.extend(serde_json::to_vec(&expired).unwrap());
+
+        let copy = Store::new(MemoryStore::default());
+        insert(&copy, "edited", b"local", &EntryOptions::default());
+        let import =
+            |on_conflict| import(Format::Jsonl, &mut exported.as_slice(), on_conflict, &compression(), 0, &copy);
+
+        let skipped = import(OnConflict::Skip).unwrap();
+        assert_eq!((skipped.imported, skipped.expired), (2, 1));
+        assert_eq!(skipped.skipped, ["edited"]);
+        assert!(get_entry("gone", &copy).unwrap().is_none());
+        assert_eq!(get_entry_data("edited", None, &copy).unwrap().content().unwrap(), &b"local"[..]);
+
+        let renamed = import(OnConflict::Rename).unwrap();
+        assert_eq!(renamed.renamed.len(), 3);
+        let new_id = &renamed.renamed["edited"];
+        assert_eq!(new_id.len(), NEW_ID_LEN);
+        assert_eq!(get_entry_data(new_id, None, &copy).unwrap().content().unwrap(), &b"second"[..]);
+
+        let overwritten = import(OnConflict::Overwrite).unwrap();
+        assert_eq!(overwritten.overwritten, ["burn", "edited", "large"]);
+        assert_eq!(get_entry_data("edited", None, &copy).unwrap().content().unwrap(), &b"second"[..]);
+
+        let broken = super::import(Format::Jsonl, &mut &b"{\"id\": 1}"[..], OnConflict::Skip, &compression(), 0, &copy);
+        assert_eq!(broken.map(|_| ()).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        let broken = super::import(Format::Tar, &mut &b"not a tar"[..], OnConflict::Skip, &compression(), 0, &copy);
+        assert!(broken.is_err());
+    }
+
+    #[test]
+    fn imports_leave_out_pastes_beyond_the_storage_budget() {
+        let state = source();
+        let mut exported = vec![];
+        export(Format::Jsonl, &state, &mut exported).unwrap();
+
+        let copy = Store::new(MemoryStore::default());
+        let imported = import(Format::Jsonl, &mut exported.as_slice(), OnConflict::Skip, &compression(), 20, &copy);
+        let imported = imported.unwrap();
+        assert_eq!(imported.imported, 2);
+        assert_eq!(imported.over_budget, ["large"]);
+        assert!(get_entry("large", &copy).unwrap().is_none());
+        assert!(stored_bytes(&copy).unwrap() <= 20);
+    }
+
+    #[test]
+    fn archives_end_with_the_manifest_and_imports_rename_invalid_ids() {
+        let state = source();
+        let mut exported = vec![];
+        export(Format::Tar, &state, &mut exported).unwrap();
+        let mut files = vec![];
+        for file in tar::Archive::new(exported.as_slice()).entries().unwrap() {
+            let mut file = file.unwrap();
+            let mut data = vec![];
+            file.read_to_end(&mut data).unwrap();
+            files.push((file.path().unwrap().to_string_lossy().into_owned(), data));
+        }
+        assert_eq!(files.last().unwrap().0, MANIFEST);
+
+        // an archive with the manifest first imports as well, here with IDs the server would not hand out
+        let (_, manifest) = files.pop().unwrap();
+        let mut pastes: Vec<ExportedPaste> = serde_json::from_slice(&manifest).unwrap();
+        pastes[0].id = "../a b".to_string();
+        pastes[1].id = "x".repeat(MAX_ID_LEN + 1);
+        files.insert(0, (MANIFEST.to_string(), serde_json::to_vec(&pastes).unwrap()));
+        let mut archive = tar::Builder::new(vec![]);
+        for (path, data) in &files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            archive.append_data(&mut header, path, data.as_slice()).unwrap();
+        }
+        let archive = archive.into_inner().unwrap();
+
+        let copy = Store::new(MemoryStore::default());
+        let imported = import(Format::Tar, &mut archive.as_slice(), OnConflict::Skip, &compression(), 0, &copy);
+        let imported = imported.unwrap();
+        assert_eq!(imported.imported, 3);
+        assert_eq!(imported.renamed.len(), 2);
+        for (original, paste) in [("../a b", &pastes[0]), (pastes[1].id.as_str(), &pastes[1])] {
+            let id = &imported.renamed[original];
+            assert!(valid_id(id));
+            let body = get_entry_data(id, None, &copy).unwrap().content().unwrap().into_owned();
+            let file = paste.revisions.last().unwrap().file.as_ref().unwrap();
+            assert_eq!(&body, &files.iter().find(|(path, _)| path == file).unwrap().1);
+        }
+    }
+}
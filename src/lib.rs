@@ -1,16 +1,94 @@
 extern crate flatbuffers;
 
-use std::io;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::io::{self, Read, Write};
+use std::sync::Mutex;
 use std::time::SystemTime;
 
 use flatbuffers::FlatBufferBuilder;
-use rocket::State;
-use rocksdb::{compaction_filter, DB};
+use rocksdb::{compaction_filter, Options};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
 
 #[path = "api_generated.rs"]
 mod api_generated;
 use crate::api_generated::api::{finish_entry_buffer, root_as_entry, Entry, EntryArgs};
 
+#[path = "store/mod.rs"]
+pub mod store;
+
+#[path = "crypto.rs"]
+pub mod crypto;
+#[path = "export.rs"]
+pub mod export;
+use crypto::{missing_key, Key, Keyring};
+use store::{decode_counter, Batch, FsStore, MemoryStore, RocksStore, StorageKind, Store, DEFAULT_CF};
+
+/// Column family holding previous revisions of edited pastes, keyed by `revision_key`.
+pub const REVISIONS_CF: &str = "revisions";
+
+/// Column family holding paste bodies, keyed by the SHA-256 digest of the stored bytes
+/// (see `content_digest`), so that identical bodies are stored once. The `Entry` records
+/// in the default column family and in `REVISIONS_CF` only carry metadata and the digest,
+/// so that expiry checks, listings and stats never load the content. See `blob_value`
+/// for the value layout.
+pub const BLOBS_CF: &str = "blobs";
+
+/// Column family counting the references to every blob: one key per paste or revision
+/// using it (see `blob_ref_key`), holding the expiry of that paste. A blob is dropped
+/// once no live reference is left.
+pub const BLOB_REFS_CF: &str = "blob_refs";
+
+/// Column family where paste bodies were kept per paste before `BLOBS_CF` existed.
+/// `migrate_content` moves them to blobs.
+pub const CONTENT_CF: &str = "content";
+
+/// Column family holding the bodies larger than `CHUNK_SIZE`, split in chunks so that they
+/// are received and served without ever being held in memory whole. Every set of chunks
+/// is registered under its `chunk_set_key`, and the blob of such a body holds the ID of
+/// its set instead of the content. Sets whose blob is gone are marked by a `freed_set_key`.
+pub const CHUNKS_CF: &str = "chunks";
+
+/// Length of the IDs of the chunk sets in `CHUNKS_CF`.
+const SET_ID_LEN: usize = 21;
+
+/// Size of the chunks in `CHUNKS_CF`. Smaller bodies are stored whole in their blob.
+pub const CHUNK_SIZE: usize = 1 << 20;
+
+/// Chunk sets being written or read, with their number of users: `release_chunks` and
+/// `sweep_chunks` leave them alone even when no paste references them (yet, or anymore).
+static CHUNKS_IN_USE: Mutex<BTreeMap<Vec<u8>, usize>> = Mutex::new(BTreeMap::new());
+
+/// Column family counting the reads of pastes created with a `max_views` limit, keyed by
+/// `views_key`.
+pub const VIEWS_CF: &str = "views";
+
+/// Column family accounting for the content size of the pastes and their revisions: the
+/// total under `STORED_BYTES_KEY`, and the size of every paste under its `age_key`, which
+/// orders them oldest first for `make_room`. Both are counters.
+pub const STORAGE_CF: &str = "storage";
+
+/// Key of the total content size in `STORAGE_CF`.
+pub const STORED_BYTES_KEY: &[u8] = b"bytes";
+
+/// Serializes `make_room`, so that two creates do not evict pastes for the same space.
+static STORAGE: Mutex<()> = Mutex::new(());
+
+/// Column family holding usage counters of API keys, see `usage_bytes_key` and `usage_hour_key`.
+pub const API_KEYS_CF: &str = "api_keys";
+
+/// Column family of the settings of the database as a whole, i.e. `SCHEMA_VERSION_KEY`.
+pub const META_CF: &str = "meta";
+
+/// Key in `META_CF` of the schema version of the database, a big-endian `u32`: the number
+/// of `MIGRATIONS` it has been through.
+pub const SCHEMA_VERSION_KEY: &[u8] = b"schema_version";
+
+/// Column family where `verify` moves the broken records of the default column family and
+/// of `REVISIONS_CF`, under `quarantine_key`, for a later inspection.
+pub const QUARANTINE_CF: &str = "quarantine";
+
 #[macro_export]
 macro_rules! load_static_resources(
     { $($key:expr => $value:expr),+ } => {
@@ -20,111 +98,1920 @@ macro_rules! load_static_resources(
                 resources.insert($key, include_bytes!($value));
             )*
 
-            resources
+            resources
+        }
+     };
+);
+
+/// Serializes the changes to blob references, which read the references left before
+/// deciding whether a blob is stored or dropped.
+static BLOB_REFS: Mutex<()> = Mutex::new(());
+
+/// Options of the database and of its column families. The options must be set before
+/// opening so the compaction filters are applied. Revisions carry the expiry of the paste
+/// they belong to, so the same filter applies. Blob references carry it as well, and blobs
+/// the latest expiry of their references, so an expired paste releases its body through
+/// compaction without any write.
+fn db_options() -> (Options, Vec<(&'static str, Options)>) {
+    let mut db_opts = Options::default();
+    db_opts.set_compaction_filter("ttl_entries", compaction_filter_expired_entries);
+    let mut revisions_opts = Options::default();
+    revisions_opts.set_compaction_filter("ttl_revisions", compaction_filter_expired_entries);
+    let mut blobs_opts = Options::default();
+    blobs_opts.set_compaction_filter("ttl_blobs", compaction_filter_expired_blobs);
+    let mut blob_refs_opts = Options::default();
+    blob_refs_opts.set_compaction_filter("ttl_blob_refs", compaction_filter_expired_blobs);
+    let mut content_opts = Options::default();
+    content_opts.set_compaction_filter("ttl_content", compaction_filter_expired_blobs);
+    let mut api_keys_opts = Options::default();
+    api_keys_opts.set_compaction_filter("stale_counters", compaction_filter_stale_counters);
+    let mut views_opts = Options::default();
+    views_opts.set_compaction_filter("ttl_views", compaction_filter_expired_views);
+
+    let cfs = vec![
+        (REVISIONS_CF, revisions_opts),
+        (BLOBS_CF, blobs_opts),
+        (BLOB_REFS_CF, blob_refs_opts),
+        (CONTENT_CF, content_opts),
+        (API_KEYS_CF, api_keys_opts),
+        (VIEWS_CF, views_opts),
+        (STORAGE_CF, Options::default()),
+        (CHUNKS_CF, Options::default()),
+        (QUARANTINE_CF, Options::default()),
+        (META_CF, Options::default()),
+    ];
+    (db_opts, cfs)
+}
+
+/// Open (or create) the database with its column families, see `db_options`.
+pub fn open_db(path: &str) -> io::Result<Store> {
+    let (db_opts, cfs) = db_options();
+    let db = Store::new(RocksStore::open(path, db_opts, cfs)?);
+    migrate(&db)?;
+    Ok(db)
+}
+
+/// Open the database at `primary`, which a server keeps open, as a read-only secondary
+/// instance with its own files in `path`, see `RocksStore::open_secondary`. A secondary
+/// cannot upgrade the records: its release must match the schema of the primary.
+pub fn open_secondary(primary: &str, path: &str) -> io::Result<Store> {
+    let (db_opts, cfs) = db_options();
+    let db = Store::new(RocksStore::open_secondary(primary, path, db_opts, cfs)?);
+    match schema_version(&db)? {
+        SCHEMA_VERSION => Ok(db),
+        version => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("the primary database has schema version {version}, this release needs {SCHEMA_VERSION}"),
+        )),
+    }
+}
+
+/// Open the `--storage` backend at `path` (a directory, unused by the memory backend).
+/// Compaction filters only exist in RocksDB; the other backends drop expired pastes when
+/// they are read or purged.
+pub fn open_store(kind: StorageKind, path: &str) -> io::Result<Store> {
+    match kind {
+        StorageKind::Rocksdb => open_db(path),
+        StorageKind::Memory => {
+            let store = Store::new(MemoryStore::default());
+            migrate(&store)?;
+            Ok(store)
+        }
+        StorageKind::Fs => {
+            let store = Store::new(FsStore::open(path)?);
+            migrate(&store)?;
+            Ok(store)
+        }
+    }
+}
+
+/// Metadata of paste `id`, expired or not.
+pub fn get_entry(id: &str, state: &Store) -> io::Result<Option<Vec<u8>>> {
+    state.get(DEFAULT_CF, id.as_bytes())?.map(|root| checked_entry(id.as_bytes(), root)).transpose()
+}
+
+/// Check that `value`, read at `key` of the default column family or of `REVISIONS_CF`, is
+/// a valid `Entry`. A record damaged on disk or written by another program fails with an
+/// `InvalidData` error instead of panicking its reader; `verify` finds and removes them.
+fn checked_entry(key: &[u8], value: Vec<u8>) -> io::Result<Vec<u8>> {
+    match root_as_entry(&value) {
+        Ok(_) => Ok(value),
+        Err(e) => {
+            let key = String::from_utf8_lossy(key);
+            Err(io::Error::new(io::ErrorKind::InvalidData, format!("corrupt record {key:?}: {e}")))
+        }
+    }
+}
+
+/// Pairs of `cf` (the default column family or `REVISIONS_CF`) starting with `from`, less
+/// the records which are not valid entries, see `checked_entry`.
+fn scan_entries<'a>(state: &'a Store, cf: &str, from: &[u8]) -> store::Pairs<'a> {
+    Box::new(state.scan(cf, from).filter(|item| match item {
+        Ok((_, value)) => root_as_entry(value).is_ok(),
+        Err(_) => true,
+    }))
+}
+
+/// A step upgrading a database to the next schema version.
+pub struct Migration {
+    pub description: &'static str,
+    pub run: fn(&Store) -> io::Result<()>,
+}
+
+/// The upgrades of the database schema, in order. A change to `Entry` or to the column
+/// families which the records already stored do not follow gets a new step at the end;
+/// released steps are never edited, as databases may have been through them already.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration { description: "move paste bodies to blobs", run: migrate_content },
+    Migration { description: "account for the content size of the pastes", run: migrate_storage },
+];
+
+/// Schema version of the databases written by this release.
+pub const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
+
+/// Schema version of the database, `0` for one written before versions were recorded
+/// (or a new one).
+pub fn schema_version(state: &Store) -> io::Result<u32> {
+    Ok(match state.get(META_CF, SCHEMA_VERSION_KEY)? {
+        Some(value) => value
+            .try_into()
+            .map(u32::from_be_bytes)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid schema version"))?,
+        None => 0,
+    })
+}
+
+/// Bring the database to `SCHEMA_VERSION` by running the `MIGRATIONS` it has not been
+/// through, in order. The version is recorded after every step, so that an interrupted
+/// upgrade resumes where it stopped. A database of a newer schema, written by a later
+/// release, is refused untouched. Returns the number of steps run.
+pub fn migrate(state: &Store) -> io::Result<u32> {
+    let version = schema_version(state)?;
+    if version > SCHEMA_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("database schema version {version} is newer than this release supports ({SCHEMA_VERSION})"),
+        ));
+    }
+
+    for (done, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        (migration.run)(state)
+            .map_err(|e| io::Error::new(e.kind(), format!("migration \"{}\" failed: {e}", migration.description)))?;
+        state.put(META_CF, SCHEMA_VERSION_KEY, &(done as u32 + 1).to_be_bytes())?;
+    }
+    Ok(SCHEMA_VERSION - version)
+}
+
+/// Move the content of records written before `BLOBS_CF` existed to blobs: either
+/// from the `data` field of the `Entry` (oldest records) or from `CONTENT_CF`. Records
+/// already converted carry a digest and are left alone, so this is cheap once done.
+pub fn migrate_content(state: &Store) -> io::Result<()> {
+    let _refs = BLOB_REFS.lock().unwrap();
+
+    for cf in [DEFAULT_CF, REVISIONS_CF] {
+        for item in scan_entries(state, cf, b"") {
+            let (key, value) = item?;
+            let entry = root_as_entry(&value).unwrap();
+            if entry.digest().is_some() {
+                continue;
+            }
+            let (data, size) = match entry.data() {
+                Some(data) => (data.bytes().to_vec(), data.len() as u64),
+                None => match state.get(CONTENT_CF, &key)? {
+                    Some(value) => (blob_data(value), entry.size()),
+                    None => continue,
+                },
+            };
+            let digest = content_digest(&data);
+
+            let mut meta = vec![];
+            let changes = EntryChanges { size, digest: Some(&digest), ..EntryChanges::of(&entry) };
+            rebuild_entry(&mut meta, &entry, &changes);
+
+            let mut batch = Batch::default();
+            batch.put(cf, &key, &meta);
+            add_blob_ref(&mut batch, &digest, &key, entry.expiry_timestamp(), &data, state)?;
+            batch.delete(CONTENT_CF, &key);
+            state.write(batch)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Account for the pastes written before `STORAGE_CF` existed, once: the total is only
+/// missing from databases that never had it.
+pub fn migrate_storage(state: &Store) -> io::Result<()> {
+    if state.get(STORAGE_CF, STORED_BYTES_KEY)?.is_some() {
+        return Ok(());
+    }
+
+    let mut batch = Batch::default();
+    batch.add(STORAGE_CF, STORED_BYTES_KEY, 0);
+    for item in scan_entries(state, DEFAULT_CF, b"") {
+        let (key, value) = item?;
+        let id = String::from_utf8_lossy(&key);
+        let prefix = revision_prefix(&id);
+        let mut size = data_len(&value);
+        for item in scan_entries(state, REVISIONS_CF, &prefix) {
+            let (key, value) = item?;
+            if !key.starts_with(&prefix) {
+                break;
+            }
+            size += data_len(&value);
+        }
+        add_storage(&mut batch, &id, &value, size);
+    }
+    state.write(batch)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .expect("time went backwards")
+        .as_secs()
+}
+
+pub fn compaction_filter_expired_entries(
+    _: u32,
+    _: &[u8],
+    value: &[u8],
+) -> compaction_filter::Decision {
+    use compaction_filter::Decision::*;
+
+    // a corrupt record is kept for `verify` to report
+    match root_as_entry(value) {
+        Ok(entry) if is_past(entry.expiry_timestamp()) => Remove,
+        _ => Keep,
+    }
+}
+
+/// Remove blobs and blob references once expired, using the expiry stored in front of
+/// them. A blob outlives every paste referencing it, see `blob_value`.
+pub fn compaction_filter_expired_blobs(_: u32, _: &[u8], value: &[u8]) -> compaction_filter::Decision {
+    use compaction_filter::Decision::*;
+
+    if is_past(blob_expiry(value)) {
+        Remove
+    } else {
+        Keep
+    }
+}
+
+/// Remove view counters once their paste expired, see `views_key`.
+pub fn compaction_filter_expired_views(_: u32, key: &[u8], _: &[u8]) -> compaction_filter::Decision {
+    use compaction_filter::Decision::*;
+
+    if is_past(blob_expiry(key)) {
+        Remove
+    } else {
+        Keep
+    }
+}
+
+fn is_past(expiry: u64) -> bool {
+    expiry != 0 && now() >= expiry
+}
+
+/// The later of two expiry timestamps, where `0` (never) beats any other.
+fn later_expiry(a: u64, b: u64) -> u64 {
+    if a == 0 || b == 0 {
+        0
+    } else {
+        a.max(b)
+    }
+}
+
+/// Length of the digests keying `BLOBS_CF`, plain or keyed (see `BodyDigest`).
+const DIGEST_LEN: usize = 32;
+
+/// SHA-256 digest of a paste body as stored, the key of its blob in `BLOBS_CF`.
+pub fn content_digest(data: &[u8]) -> Vec<u8> {
+    Sha256::digest(data).to_vec()
+}
+
+/// Digest of a body encrypted with `key`, or stored in plaintext without one: see
+/// `content_digest` and `Key::digest`. Identical bodies encrypted with the same key still
+/// share their blob.
+enum BodyDigest {
+    Plain(Sha256),
+    Keyed(ring::hmac::Context),
+}
+
+impl BodyDigest {
+    fn new(key: Option<&Key>) -> Self {
+        match key {
+            Some(key) => BodyDigest::Keyed(key.digest()),
+            None => BodyDigest::Plain(Sha256::new()),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            BodyDigest::Plain(hasher) => hasher.update(data),
+            BodyDigest::Keyed(context) => context.update(data),
+        }
+    }
+
+    fn finish(self) -> Vec<u8> {
+        match self {
+            BodyDigest::Plain(hasher) => hasher.finalize().to_vec(),
+            BodyDigest::Keyed(context) => context.sign().as_ref().to_vec(),
+        }
+    }
+}
+
+/// Key `id` of the keyring of `state`, to decrypt a record with.
+fn decryption_key(id: u32, state: &Store) -> io::Result<&Key> {
+    state.keyring().ok_or_else(|| missing_key(id))?.get(id)
+}
+
+/// Value stored in `BLOBS_CF`: the latest expiry among the pastes referencing the blob,
+/// `0` when one never expires (big-endian `u64`, so that compaction can drop the blob
+/// without looking up its references), followed by the content. Blob references in
+/// `BLOB_REFS_CF` only hold the expiry of their paste.
+pub fn blob_value(expiry: u64, data: &[u8]) -> Vec<u8> {
+    [&expiry.to_be_bytes(), data].concat()
+}
+
+fn blob_expiry(value: &[u8]) -> u64 {
+    value.get(..8).and_then(|v| v.try_into().ok()).map(u64::from_be_bytes).unwrap_or(0)
+}
+
+/// Codec of a stored paste body, recorded in the `codec` field of its `Entry`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Codec {
+    None = 0,
+    Zstd = 1,
+}
+
+impl Codec {
+    pub fn of(entry: &Entry) -> Result<Codec, io::Error> {
+        match entry.codec() {
+            0 => Ok(Codec::None),
+            1 => Ok(Codec::Zstd),
+            codec => Err(io::Error::new(io::ErrorKind::InvalidData, format!("unknown codec {codec}"))),
+        }
+    }
+}
+
+/// How new paste bodies are compressed.
+pub struct Compression {
+    pub codec: Codec,
+    pub level: i32,
+    /// Bodies smaller than this many bytes are stored as is.
+    pub threshold: usize,
+}
+
+/// Compress a paste body, returning the bytes to store and their codec. The body is
+/// kept as is when it is below the threshold or does not get any smaller.
+pub fn compress<'a>(data: &'a [u8], compression: &Compression) -> (Cow<'a, [u8]>, Codec) {
+    if compression.codec == Codec::None || data.len() < compression.threshold {
+        return (Cow::Borrowed(data), Codec::None);
+    }
+
+    match zstd::bulk::compress(data, compression.level) {
+        Ok(compressed) if compressed.len() < data.len() => (Cow::Owned(compressed), Codec::Zstd),
+        _ => (Cow::Borrowed(data), Codec::None),
+    }
+}
+
+/// Decode a stored paste body.
+pub fn decompress(data: &[u8], codec: Codec) -> Result<Cow<'_, [u8]>, io::Error> {
+    match codec {
+        Codec::None => Ok(Cow::Borrowed(data)),
+        Codec::Zstd => zstd::stream::decode_all(data).map(Cow::Owned),
+    }
+}
+
+fn blob_data(mut value: Vec<u8>) -> Vec<u8> {
+    value.drain(..8.min(value.len()));
+    value
+}
+
+/// Key of the reference from `key` (a paste ID or a `revision_key`) to the blob `digest`.
+/// Digests have a fixed length, so the digest prefix selects exactly the references of
+/// one blob.
+pub fn blob_ref_key(digest: &[u8], key: &[u8]) -> Vec<u8> {
+    [digest, key].concat()
+}
+
+/// Add to `batch` a reference from `key` to the blob of `data`, storing the blob unless a
+/// live copy already exists (whose expiry is extended to `expiry` when needed). Returns
+/// whether `data` is stored. Callers hold `BLOB_REFS`.
+fn add_blob_ref(
+    batch: &mut Batch,
+    digest: &[u8],
+    key: &[u8],
+    expiry: u64,
+    data: &[u8],
+    state: &Store,
+) -> io::Result<bool> {
+    batch.put(BLOB_REFS_CF, blob_ref_key(digest, key), expiry.to_be_bytes());
+    match state.get(BLOBS_CF, digest)?.filter(|value| !is_past(blob_expiry(value))) {
+        Some(value) => {
+            let (stored, later) = (blob_expiry(&value), later_expiry(blob_expiry(&value), expiry));
+            if later != stored {
+                batch.put(BLOBS_CF, digest, blob_value(later, &value[8..]));
+            }
+            Ok(false)
+        }
+        None => {
+            batch.put(BLOBS_CF, digest, blob_value(expiry, data));
+            Ok(true)
+        }
+    }
+}
+
+/// Add to `batch` a reference from `key` to `body`, see `add_blob_ref`. The chunks of a
+/// body identical to one already stored are dropped. Callers hold `BLOB_REFS`.
+fn add_body_ref(batch: &mut Batch, key: &[u8], expiry: u64, body: &StoredBody, state: &Store) -> io::Result<()> {
+    let stored = add_blob_ref(batch, &body.digest, key, expiry, &body.data, state)?;
+    if !stored && body.chunks > 0 {
+        delete_chunk_set(batch, &body.data, body.chunks);
+    }
+    Ok(())
+}
+
+/// Add to `batch` the removal of the `released` references, given as `(digest, key)`.
+/// Blobs left without live references are dropped, the others get the expiry of the
+/// references left. The chunks of a dropped blob are marked for `release_chunks`, so that
+/// reads in progress can finish. Callers hold `BLOB_REFS`.
+fn release_blob_refs(batch: &mut Batch, released: &[(Vec<u8>, Vec<u8>)], state: &Store) -> io::Result<()> {
+    let gone: HashSet<Vec<u8>> = released.iter().map(|(digest, key)| blob_ref_key(digest, key)).collect();
+    for key in &gone {
+        batch.delete(BLOB_REFS_CF, key);
+    }
+
+    let digests: BTreeSet<&[u8]> = released.iter().map(|(digest, _)| digest.as_slice()).collect();
+    for digest in digests {
+        let mut remaining: Option<u64> = None;
+        for item in state.scan(BLOB_REFS_CF, digest) {
+            let (key, value) = item?;
+            if !key.starts_with(digest) {
+                break;
+            }
+            let expiry = blob_expiry(&value);
+            if is_past(expiry) {
+                // not compacted yet
+                batch.delete(BLOB_REFS_CF, &key);
+            } else if !gone.contains(&key) {
+                remaining = Some(remaining.map_or(expiry, |other| later_expiry(other, expiry)));
+            }
+        }
+
+        match (remaining, state.get(BLOBS_CF, digest)?) {
+            (None, blob) => {
+                batch.delete(BLOBS_CF, digest);
+                // the content of a blob stored in chunks is the ID of its chunk set
+                let data = blob.map(blob_data).filter(|data| data.len() == SET_ID_LEN);
+                if let Some(set) = data {
+                    if state.get(CHUNKS_CF, &chunk_set_key(&set))?.is_some() {
+                        batch.put(CHUNKS_CF, freed_set_key(&set), []);
+                    }
+                }
+            }
+            (Some(expiry), Some(value)) if blob_expiry(&value) != expiry => {
+                batch.put(BLOBS_CF, digest, blob_value(expiry, &value[8..]));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn entry_digest(root: &[u8]) -> Option<Vec<u8>> {
+    root_as_entry(root).unwrap().digest().map(|digest| digest.bytes().to_vec())
+}
+
+/// Copy of the metadata `root` describing the content of `body`.
+fn with_body(root: &[u8], body: &StoredBody) -> Vec<u8> {
+    let entry = root_as_entry(root).unwrap();
+    let changes = EntryChanges {
+        size: body.size,
+        codec: body.codec as u8,
+        digest: Some(&body.digest),
+        chunks: body.chunks,
+        key_id: body.key_id,
+        ..EntryChanges::of(&entry)
+    };
+    let mut dest = vec![];
+    rebuild_entry(&mut dest, &entry, &changes);
+    dest
+}
+
+/// Key of chunk set `set` in `CHUNKS_CF`: a counter of the bytes written to it, so that
+/// its chunks can be found (see `chunk_count`) even when no blob references it.
+pub fn chunk_set_key(set: &[u8]) -> Vec<u8> {
+    [b"set\0", set].concat()
+}
+
+/// Key marking in `CHUNKS_CF` the chunk set `set` of a blob which is gone, for
+/// `release_chunks` to drop once no read uses it.
+fn freed_set_key(set: &[u8]) -> Vec<u8> {
+    [b"free\0", set].concat()
+}
+
+/// Key of chunk `index` of set `set` in `CHUNKS_CF`. Chunk keys sort before the keys of
+/// the sets, so that the sets are listed without loading any chunk.
+pub fn chunk_key(set: &[u8], index: u32) -> Vec<u8> {
+    [b"chunk\0", set, &index.to_be_bytes()].concat()
+}
+
+/// Number of chunks holding `bytes` bytes: all of them are full but the last one.
+pub fn chunk_count(bytes: u64) -> u32 {
+    bytes.div_ceil(CHUNK_SIZE as u64) as u32
+}
+
+/// Add to `batch` the removal of chunk set `set`, made of `chunks` chunks.
+fn delete_chunk_set(batch: &mut Batch, set: &[u8], chunks: u32) {
+    for index in 0..chunks {
+        batch.delete(CHUNKS_CF, chunk_key(set, index));
+    }
+    batch.delete(CHUNKS_CF, chunk_set_key(set));
+}
+
+/// Marks a chunk set as in use (see `CHUNKS_IN_USE`) for as long as it lives.
+struct ChunksInUse(Vec<u8>);
+
+impl ChunksInUse {
+    fn new(set: &[u8]) -> Self {
+        *CHUNKS_IN_USE.lock().unwrap().entry(set.to_vec()).or_default() += 1;
+        ChunksInUse(set.to_vec())
+    }
+}
+
+impl Drop for ChunksInUse {
+    fn drop(&mut self) {
+        let mut in_use = CHUNKS_IN_USE.lock().unwrap();
+        if let Some(users) = in_use.get_mut(&self.0) {
+            *users -= 1;
+            if *users == 0 {
+                in_use.remove(&self.0);
+            }
+        }
+    }
+}
+
+/// A paste body ready to be stored by `insert_body` or `save_revision`.
+pub struct StoredBody<'a> {
+    /// Digest of the bytes stored before encryption, see `BodyDigest`.
+    pub digest: Vec<u8>,
+    /// Content of the blob: the bytes stored (encrypted with key `key_id`), or the ID of the
+    /// chunk set holding them.
+    pub data: Cow<'a, [u8]>,
+    /// Length of the content, decoded.
+    pub size: u64,
+    pub codec: Codec,
+    /// Number of chunks in `CHUNKS_CF`, `0` for a body kept whole in its blob.
+    pub chunks: u32,
+    /// ID of the key encrypting the body, `0` for none.
+    pub key_id: u32,
+    _in_use: Option<ChunksInUse>,
+}
+
+impl<'a> StoredBody<'a> {
+    /// A body kept whole in its blob: `stored` is the content as stored (see `compress`)
+    /// and `size` the length of the content. It is encrypted with the current key of
+    /// `state`, if any.
+    pub fn inline(stored: impl Into<Cow<'a, [u8]>>, size: u64, codec: Codec, state: &Store) -> Self {
+        let stored = stored.into();
+        let key = state.keyring().map(Keyring::current);
+        let mut digest = BodyDigest::new(key);
+        digest.update(&stored);
+        let digest = digest.finish();
+        let data = match key {
+            Some(key) => Cow::Owned(key.seal(&stored, &digest)),
+            None => stored,
+        };
+        StoredBody { digest, data, size, codec, chunks: 0, key_id: key.map_or(0, Key::id), _in_use: None }
+    }
+
+    /// Drop the chunks of a body which is not stored after all.
+    pub fn discard(self, state: &Store) -> io::Result<()> {
+        if self.chunks == 0 {
+            return Ok(());
+        }
+        let mut batch = Batch::default();
+        delete_chunk_set(&mut batch, &self.data, self.chunks);
+        state.write(batch)
+    }
+}
+
+/// Writes the bytes stored for a body to a new chunk set, a chunk at a time, encrypted
+/// with the current key of the store if any.
+struct ChunkSink {
+    state: Store,
+    set: Vec<u8>,
+    buf: Vec<u8>,
+    chunks: u32,
+    hasher: BodyDigest,
+    in_use: ChunksInUse,
+}
+
+impl ChunkSink {
+    /// Store the next chunk, from the start of `buf`.
+    fn write_chunk(&mut self) -> io::Result<()> {
+        let len = self.buf.len().min(CHUNK_SIZE);
+        let key = chunk_key(&self.set, self.chunks);
+        let chunk = match self.state.keyring() {
+            Some(keyring) => Cow::Owned(keyring.current().seal(&self.buf[..len], &key)),
+            None => Cow::Borrowed(&self.buf[..len]),
+        };
+        let mut batch = Batch::default();
+        batch.put(CHUNKS_CF, key, chunk);
+        batch.add(CHUNKS_CF, chunk_set_key(&self.set), len as i64);
+        self.state.write(batch)?;
+        self.buf.drain(..len);
+        self.chunks += 1;
+        Ok(())
+    }
+}
+
+impl Write for ChunkSink {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.hasher.update(data);
+        self.buf.extend_from_slice(data);
+        while self.buf.len() >= CHUNK_SIZE {
+            self.write_chunk()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+enum ChunkEncoder {
+    Plain(ChunkSink),
+    Zstd(zstd::stream::write::Encoder<'static, ChunkSink>),
+}
+
+/// Streams a paste body to a new chunk set as it is received, compressed on the way with
+/// the codec of `compression` (without its threshold: such a body is large anyway).
+/// `finish` returns the body to store.
+pub struct ChunkWriter {
+    encoder: ChunkEncoder,
+    size: u64,
+}
+
+impl ChunkWriter {
+    pub fn new(compression: &Compression, state: &Store) -> io::Result<Self> {
+        let set = nanoid::nanoid!(SET_ID_LEN).into_bytes();
+        let sink = ChunkSink {
+            state: state.clone(),
+            in_use: ChunksInUse::new(&set),
+            set,
+            buf: vec![],
+            chunks: 0,
+            hasher: BodyDigest::new(state.keyring().map(Keyring::current)),
+        };
+        let encoder = match compression.codec {
+            Codec::None => ChunkEncoder::Plain(sink),
+            Codec::Zstd => ChunkEncoder::Zstd(zstd::stream::write::Encoder::new(sink, compression.level)?),
+        };
+        Ok(ChunkWriter { encoder, size: 0 })
+    }
+
+    /// Store the last chunk and return the body written.
+    pub fn finish(self) -> io::Result<StoredBody<'static>> {
+        let (mut sink, codec) = match self.encoder {
+            ChunkEncoder::Plain(sink) => (sink, Codec::None),
+            ChunkEncoder::Zstd(encoder) => (encoder.finish()?, Codec::Zstd),
+        };
+        while !sink.buf.is_empty() {
+            sink.write_chunk()?;
+        }
+        Ok(StoredBody {
+            key_id: sink.state.keyring().map_or(0, |keyring| keyring.current().id()),
+            digest: sink.hasher.finish(),
+            data: Cow::Owned(sink.set),
+            size: self.size,
+            codec,
+            chunks: sink.chunks,
+            _in_use: Some(sink.in_use),
+        })
+    }
+
+    /// Drop the chunks written so far, i.e. when the body turns out to be too large.
+    pub fn discard(self) -> io::Result<()> {
+        let sink = match &self.encoder {
+            ChunkEncoder::Plain(sink) => sink,
+            ChunkEncoder::Zstd(encoder) => encoder.get_ref(),
+        };
+        let mut batch = Batch::default();
+        delete_chunk_set(&mut batch, &sink.set, sink.chunks);
+        sink.state.write(batch)
+    }
+}
+
+impl Write for ChunkWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let written = match &mut self.encoder {
+            ChunkEncoder::Plain(sink) => sink.write(data)?,
+            ChunkEncoder::Zstd(encoder) => encoder.write(data)?,
+        };
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Reads the chunks of a set in order, see `Paste::stored_reader`.
+struct ChunkReader {
+    state: Store,
+    set: Vec<u8>,
+    chunks: u32,
+    /// ID of the key encrypting the chunks, `0` for none.
+    key_id: u32,
+    next: u32,
+    chunk: io::Cursor<Vec<u8>>,
+    _in_use: ChunksInUse,
+}
+
+impl Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read = self.chunk.read(buf)?;
+            if read > 0 || buf.is_empty() || self.next == self.chunks {
+                return Ok(read);
+            }
+            let key = chunk_key(&self.set, self.next);
+            let chunk = self.state.get(CHUNKS_CF, &key)?;
+            let chunk = chunk.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing chunk"))?;
+            self.chunk = match self.key_id {
+                0 => io::Cursor::new(chunk),
+                id => io::Cursor::new(decryption_key(id, &self.state)?.open(&chunk, &key)?),
+            };
+            self.next += 1;
+        }
+    }
+}
+
+/// Drop hourly API key counters once their hour is over; byte totals are kept.
+pub fn compaction_filter_stale_counters(
+    _: u32,
+    key: &[u8],
+    _: &[u8],
+) -> compaction_filter::Decision {
+    use compaction_filter::Decision::*;
+
+    match key.strip_prefix(b"hour\0") {
+        Some(rest) if rest.len() >= 8 => {
+            let hour = u64::from_be_bytes(rest[rest.len() - 8..].try_into().unwrap());
+            if hour < now() / 3600 {
+                Remove
+            } else {
+                Keep
+            }
+        }
+        _ => Keep,
+    }
+}
+
+/// Key of the counter with the total number of bytes stored through an API key.
+pub fn usage_bytes_key(name: &str) -> Vec<u8> {
+    [b"bytes\0", name.as_bytes()].concat()
+}
+
+/// Key of the counter with the number of pastes created through an API key within
+/// `hour` (hours since UNIX epoch).
+pub fn usage_hour_key(name: &str, hour: u64) -> Vec<u8> {
+    [b"hour\0", name.as_bytes(), b"\0", &hour.to_be_bytes()].concat()
+}
+
+/// Pastes created through an API key in the current hour, and bytes stored by it overall.
+pub fn read_usage(name: &str, state: &Store) -> io::Result<(i64, i64)> {
+    let read = |key: Vec<u8>| -> io::Result<i64> {
+        Ok(state.get(API_KEYS_CF, &key)?.map(|value| decode_counter(&value)).unwrap_or(0))
+    };
+
+    Ok((read(usage_hour_key(name, now() / 3600))?, read(usage_bytes_key(name))?))
+}
+
+/// Key of paste `id` in the age index of `STORAGE_CF`: its creation time (which never
+/// changes) first, so that pastes sort oldest first.
+pub fn age_key(id: &str, create_timestamp: u64) -> Vec<u8> {
+    [b"age\0", &create_timestamp.to_be_bytes()[..], id.as_bytes()].concat()
+}
+
+/// Add to `batch` a change of `bytes` in the content size of paste `id`, whose metadata
+/// is `root`.
+fn add_storage(batch: &mut Batch, id: &str, root: &[u8], bytes: i64) {
+    let create_timestamp = root_as_entry(root).unwrap().create_timestamp();
+    batch.add(STORAGE_CF, age_key(id, create_timestamp), bytes);
+    batch.add(STORAGE_CF, STORED_BYTES_KEY, bytes);
+}
+
+/// Content size of all the pastes and revisions stored.
+pub fn stored_bytes(state: &Store) -> io::Result<u64> {
+    Ok(state.get(STORAGE_CF, STORED_BYTES_KEY)?.map_or(0, |value| decode_counter(&value).max(0) as u64))
+}
+
+/// Evict the oldest pastes, besides pinned ones and `keep`, until `needed` more bytes fit
+/// within `budget`; returns the IDs evicted, or `None` (evicting nothing) when even that
+/// would not be enough. Pastes written concurrently may still overshoot the budget by
+/// their own size.
+pub fn make_room(needed: u64, budget: u64, keep: Option<&str>, state: &Store) -> io::Result<Option<Vec<String>>> {
+    if needed > budget {
+        return Ok(None);
+    }
+    let _storage = STORAGE.lock().unwrap();
+    let mut excess = (stored_bytes(state)? + needed).saturating_sub(budget);
+
+    let mut victims = vec![];
+    let mut stale = Batch::default();
+    for item in state.scan(STORAGE_CF, b"age\0") {
+        if excess == 0 {
+            break;
+        }
+        let (key, value) = item?;
+        if !key.starts_with(b"age\0") {
+            break;
+        }
+        let id = String::from_utf8_lossy(&key[12..]).into_owned();
+        let size = decode_counter(&value).max(0) as u64;
+        match state.get(DEFAULT_CF, id.as_bytes())? {
+            // dropped by a compaction filter, the index and the total lag behind
+            None => {
+                stale.delete(STORAGE_CF, &key);
+                stale.add(STORAGE_CF, STORED_BYTES_KEY, -(size as i64));
+            }
+            // a corrupt record is left for `verify`
+            Some(root) if root_as_entry(&root).map_or(true, |entry| entry.pinned()) => continue,
+            Some(_) if keep == Some(id.as_str()) => continue,
+            Some(_) => victims.push(id),
+        }
+        excess = excess.saturating_sub(size);
+    }
+    state.write(stale)?;
+
+    if excess > 0 {
+        return Ok(None);
+    }
+    for id in &victims {
+        delete_entry(id, state)?;
+    }
+    Ok(Some(victims))
+}
+
+fn add_usage(batch: &mut Batch, root: &[u8], pastes: i64, bytes: i64) {
+    let entry = root_as_entry(root).unwrap();
+    let name = match entry.api_key() {
+        Some(name) => name,
+        None => return,
+    };
+
+    if pastes != 0 {
+        batch.add(API_KEYS_CF, usage_hour_key(name, now() / 3600), pastes);
+    }
+    batch.add(API_KEYS_CF, usage_bytes_key(name), bytes);
+}
+
+fn data_len(root: &[u8]) -> i64 {
+    root_as_entry(root).unwrap().size() as i64
+}
+
+/// Validate a Prism language identifier. Prism names are lowercase alphanumeric
+/// plus `-`, `_`, `+`, `#` (e.g. "c++", "c#", "shell-session"). Anything else
+/// falls back to "markup" so it can never inject arbitrary CSS class names.
+pub fn sanitize_lang(lang: &str) -> &str {
+    if !lang.is_empty()
+        && lang
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '+' | '#'))
+    {
+        lang
+    } else {
+        "markup"
+    }
+}
+
+pub fn get_extension(filename: &str) -> &str {
+    filename
+        .rfind('.')
+        .map(|idx| &filename[idx..])
+        .filter(|ext| ext.chars().skip(1).all(|c| c.is_ascii_alphanumeric()))
+        .unwrap_or("")
+}
+
+/// Hash an owner token for storage. Only the digest is kept in the `Entry`, so a
+/// leaked database does not hand out working deletion tokens.
+pub fn hash_token(token: &str) -> Vec<u8> {
+    Sha256::digest(token.as_bytes()).to_vec()
+}
+
+/// Check a presented owner token against the digest stored with the paste.
+/// Entries created before owner tokens existed carry no digest and never match.
+pub fn is_owner(root: &[u8], token: &str) -> bool {
+    matches_token(root_as_entry(root).unwrap().owner_token(), token)
+}
+
+/// Check a presented preview token (given to the creator of a "burn" paste) against the
+/// digest stored with the paste.
+pub fn is_previewer(root: &[u8], token: &str) -> bool {
+    matches_token(root_as_entry(root).unwrap().preview_token(), token)
+}
+
+fn matches_token(stored: Option<flatbuffers::Vector<u8>>, token: &str) -> bool {
+    let stored = match stored {
+        Some(stored) => stored.bytes(),
+        None => return false,
+    };
+    let presented = hash_token(token);
+
+    // constant-time comparison, the digests have a fixed length
+    stored.len() == presented.len()
+        && stored
+            .iter()
+            .zip(presented.iter())
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+/// Whether the paste is deleted after its first reading.
+pub fn is_burn(root: &[u8]) -> bool {
+    root_as_entry(root).unwrap().burn()
+}
+
+/// Check whether reading the paste consumes it: a "burn" or `max_views` paste.
+pub fn has_counted_reads(root: &[u8]) -> bool {
+    let entry = root_as_entry(root).unwrap();
+    entry.burn() || entry.max_views() > 0
+}
+
+/// Check whether the paste was created by the logged in user `subject`.
+pub fn is_owned_by(root: &[u8], subject: &str) -> bool {
+    root_as_entry(root).unwrap().owner() == Some(subject)
+}
+
+/// Key of revision `rev` of paste `id` in the revisions column family. Slugs never
+/// contain NUL, so the `id\0` prefix selects exactly the revisions of one paste.
+pub fn revision_key(id: &str, rev: u32) -> Vec<u8> {
+    let mut key = revision_prefix(id);
+    key.extend_from_slice(&rev.to_be_bytes());
+    key
+}
+
+/// Key of the view counter of paste `id` in `VIEWS_CF`. The expiry of the paste (which
+/// never changes) comes first, so that compaction drops the counter along with the paste.
+pub fn views_key(id: &str, expiry: u64) -> Vec<u8> {
+    [&expiry.to_be_bytes(), id.as_bytes()].concat()
+}
+
+fn revision_prefix(id: &str) -> Vec<u8> {
+    let mut prefix = id.as_bytes().to_vec();
+    prefix.push(0);
+    prefix
+}
+
+/// Store a new paste, its metadata `root` (see `new_entry`) and its content `data` as
+/// stored (see `compress`).
+// the server stores bodies with `insert_body`, this is for the tools and tests
+#[allow(dead_code)]
+pub fn insert_entry(id: &str, root: &[u8], data: &[u8], state: &Store) -> io::Result<()> {
+    let entry = root_as_entry(root).unwrap();
+    insert_body(id, root, &StoredBody::inline(data, entry.size(), Codec::of(&entry)?, state), state)
+}
+
+/// Store a new paste, its metadata `root` (see `new_entry`, the fields describing the
+/// content are taken from `body`) and its body. A body identical to one already stored
+/// is shared with it. Pastes created through an API key are added to its usage counters
+/// in the same atomic write.
+pub fn insert_body(id: &str, root: &[u8], body: &StoredBody, state: &Store) -> io::Result<()> {
+    let root = with_body(root, body);
+    let expiry = root_as_entry(&root).unwrap().expiry_timestamp();
+    let _refs = BLOB_REFS.lock().unwrap();
+
+    let mut batch = Batch::default();
+    add_body_ref(&mut batch, id.as_bytes(), expiry, body, state)?;
+    add_usage(&mut batch, &root, 1, data_len(&root));
+    add_storage(&mut batch, id, &root, data_len(&root));
+    batch.put(DEFAULT_CF, id, root);
+    state.write(batch)
+}
+
+/// Remove a paste together with all of its previous revisions, returning their content
+/// size. Their bodies are only dropped when no other paste shares them.
+pub fn delete_entry(id: &str, state: &Store) -> io::Result<u64> {
+    let _refs = BLOB_REFS.lock().unwrap();
+    match get_entry(id, state)? {
+        Some(current) => remove_entry(id, &current, state),
+        None => Ok(0),
+    }
+}
+
+/// Remove the entry `id`, whose latest metadata is `current`, with its revisions and
+/// blob references, returning their content size. The caller holds `BLOB_REFS`.
+fn remove_entry(id: &str, current: &[u8], state: &Store) -> io::Result<u64> {
+    let prefix = revision_prefix(id);
+
+    let mut batch = Batch::default();
+    let mut size = data_len(current);
+    let mut released = vec![];
+    batch.delete(DEFAULT_CF, id);
+    batch.delete(VIEWS_CF, views_key(id, root_as_entry(current).unwrap().expiry_timestamp()));
+    released.extend(entry_digest(current).map(|digest| (digest, id.as_bytes().to_vec())));
+    for item in state.scan(REVISIONS_CF, &prefix) {
+        let (key, value) = item?;
+        if !key.starts_with(&prefix) {
+            break;
+        }
+        batch.delete(REVISIONS_CF, &key);
+        // a corrupt revision goes as well, without its unknown size and body
+        if root_as_entry(&value).is_ok() {
+            size += data_len(&value);
+            released.extend(entry_digest(&value).map(|digest| (digest, key)));
+        }
+    }
+    release_blob_refs(&mut batch, &released, state)?;
+    add_usage(&mut batch, current, 0, -size);
+    batch.delete(STORAGE_CF, age_key(id, root_as_entry(current).unwrap().create_timestamp()));
+    batch.add(STORAGE_CF, STORED_BYTES_KEY, -size);
+
+    state.write(batch)?;
+    release_chunks(state)?;
+    Ok(size as u64)
+}
+
+/// List the revisions of a paste as `(revision, timestamp)` pairs, oldest first. The
+/// timestamp is the moment the revision was written. `current` is the latest stored entry.
+pub fn list_revisions(id: &str, current: &[u8], state: &Store) -> io::Result<Vec<(u32, u64)>> {
+    let prefix = revision_prefix(id);
+
+    let mut list = vec![];
+    for item in scan_entries(state, REVISIONS_CF, &prefix) {
+        let (key, value) = item?;
+        if !key.starts_with(&prefix) {
+            break;
+        }
+        let entry = root_as_entry(&value).unwrap();
+        list.push((entry.revision(), revision_timestamp(&entry)));
+    }
+
+    let entry = root_as_entry(current).unwrap();
+    list.push((entry.revision(), revision_timestamp(&entry)));
+    Ok(list)
+}
+
+fn revision_timestamp(entry: &Entry) -> u64 {
+    if entry.update_timestamp() != 0 {
+        entry.update_timestamp()
+    } else {
+        entry.create_timestamp()
+    }
+}
+
+/// Store `next` as the current version of paste `id`, moving `current` to the
+/// revisions column family in the same atomic write. `data` is the new body, `None`
+/// keeps the current content.
+pub fn save_revision(
+    id: &str,
+    current: &[u8],
+    next: &[u8],
+    data: Option<&StoredBody>,
+    state: &Store,
+) -> io::Result<()> {
+    let entry = root_as_entry(current).unwrap();
+    let expiry = entry.expiry_timestamp();
+    let key = revision_key(id, entry.revision());
+    let _refs = BLOB_REFS.lock().unwrap();
+
+    let mut batch = Batch::default();
+    batch.put(REVISIONS_CF, &key, current);
+    if let Some(digest) = entry.digest() {
+        batch.put(BLOB_REFS_CF, blob_ref_key(digest.bytes(), &key), expiry.to_be_bytes());
+    }
+    let next = match data {
+        Some(body) => {
+            if let Some(previous) = entry.digest() {
+                batch.delete(BLOB_REFS_CF, blob_ref_key(previous.bytes(), id.as_bytes()));
+            }
+            add_body_ref(&mut batch, id.as_bytes(), expiry, body, state)?;
+            with_body(next, body)
+        }
+        None => next.to_vec(),
+    };
+    add_usage(&mut batch, &next, 0, data_len(&next));
+    add_storage(&mut batch, id, &next, data_len(&next));
+    batch.put(DEFAULT_CF, id, next);
+    // the paste may have been burned, removed or edited since `current` was read
+    if state.write_if(DEFAULT_CF, id.as_bytes(), current, batch)? {
+        return Ok(());
+    }
+    Err(match state.get(DEFAULT_CF, id.as_bytes())? {
+        Some(_) => io::Error::new(io::ErrorKind::ResourceBusy, "paste edited meanwhile"),
+        None => io::Error::new(io::ErrorKind::NotFound, "record not found"),
+    })
+}
+
+/// Check if data expired (might not be yet deleted by rocksb compaction hook).
+pub fn is_expired(root: &[u8]) -> bool {
+    is_past(root_as_entry(root).unwrap().expiry_timestamp())
+}
+
+/// Metadata of a stored paste, without its content.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct EntryInfo {
+    pub id: String,
+    pub create_timestamp: u64,
+    pub expiry_timestamp: u64,
+    pub update_timestamp: u64,
+    pub lang: String,
+    pub size: u64,
+    pub burn: bool,
+    /// Number of reads allowed, `0` for no limit.
+    pub max_views: u32,
+    pub encrypted: bool,
+    /// Never evicted to make room, see `make_room`.
+    pub pinned: bool,
+    pub revision: u32,
+    pub owner: Option<String>,
+    pub api_key: Option<String>,
+}
+
+impl EntryInfo {
+    pub fn new(id: &str, root: &[u8]) -> Self {
+        let entry = root_as_entry(root).unwrap();
+        EntryInfo {
+            id: id.to_string(),
+            create_timestamp: entry.create_timestamp(),
+            expiry_timestamp: entry.expiry_timestamp(),
+            update_timestamp: entry.update_timestamp(),
+            lang: entry.lang().unwrap_or("markup").to_string(),
+            size: data_len(root) as u64,
+            burn: entry.burn(),
+            max_views: entry.max_views(),
+            encrypted: entry.encrypted(),
+            pinned: entry.pinned(),
+            revision: entry.revision(),
+            owner: entry.owner().map(str::to_string),
+            api_key: entry.api_key().map(str::to_string),
+        }
+    }
+}
+
+/// List up to `limit` pastes in key order, starting right after the paste `after`.
+pub fn list_entries(after: Option<&str>, limit: usize, state: &Store) -> io::Result<Vec<EntryInfo>> {
+    scan_entries(state, DEFAULT_CF, after.unwrap_or("").as_bytes())
+        .filter(|item| !matches!(item, Ok((key, _)) if after.is_some_and(|after| key == after.as_bytes())))
+        .take(limit)
+        .map(|item| item.map(|(key, value)| EntryInfo::new(&String::from_utf8_lossy(&key), &value)))
+        .collect()
+}
+
+/// Selects pastes to purge; every criterion given must match.
+#[derive(Default)]
+pub struct EntryFilter<'a> {
+    /// Created more than this many seconds ago.
+    pub older_than: Option<u64>,
+    /// Content larger than this many bytes.
+    pub larger_than: Option<u64>,
+    pub lang: Option<&'a str>,
+}
+
+impl EntryFilter<'_> {
+    pub fn is_empty(&self) -> bool {
+        self.older_than.is_none() && self.larger_than.is_none() && self.lang.is_none()
+    }
+
+    pub fn matches(&self, info: &EntryInfo) -> bool {
+        self.older_than.is_none_or(|age| info.create_timestamp + age < now())
+            && self.larger_than.is_none_or(|size| info.size > size)
+            && self.lang.is_none_or(|lang| info.lang.eq_ignore_ascii_case(lang))
+    }
+}
+
+/// Delete every paste matched by `filter`, returning the IDs removed.
+pub fn purge_entries(filter: &EntryFilter, state: &Store) -> io::Result<Vec<String>> {
+    let mut ids = vec![];
+    for item in scan_entries(state, DEFAULT_CF, b"") {
+        let (key, value) = item?;
+        let info = EntryInfo::new(&String::from_utf8_lossy(&key), &value);
+        if filter.matches(&info) {
+            ids.push(info.id);
+        }
+    }
+
+    for id in &ids {
+        delete_entry(id, state)?;
+    }
+    Ok(ids)
+}
+
+/// Pastes removed by `purge_expired`.
+#[derive(Debug, Default, PartialEq)]
+pub struct Purged {
+    pub ids: Vec<String>,
+    /// Content size of the pastes and of their revisions.
+    pub bytes: u64,
+}
+
+/// Delete every expired paste. Unlike compaction, this also releases the stored bytes
+/// counted against API keys.
+pub fn purge_expired(state: &Store) -> io::Result<Purged> {
+    let mut purged = Purged::default();
+    for item in scan_entries(state, DEFAULT_CF, b"") {
+        let (key, value) = item?;
+        if is_expired(&value) {
+            purged.ids.push(String::from_utf8_lossy(&key).into_owned());
+        }
+    }
+
+    for id in &purged.ids {
+        purged.bytes += delete_entry(id, state)?;
+    }
+    release_compacted(state)?;
+    sweep_chunks(state)?;
+    Ok(purged)
+}
+
+/// Drop the accounting of the pastes removed by a compaction filter, which cannot write.
+fn release_compacted(state: &Store) -> io::Result<()> {
+    let _storage = STORAGE.lock().unwrap();
+    let mut batch = Batch::default();
+    for item in state.scan(STORAGE_CF, b"age\0") {
+        let (key, value) = item?;
+        if !key.starts_with(b"age\0") {
+            break;
+        }
+        if state.get(DEFAULT_CF, &key[12..])?.is_none() {
+            batch.delete(STORAGE_CF, &key);
+            batch.add(STORAGE_CF, STORED_BYTES_KEY, -decode_counter(&value));
+        }
+    }
+    state.write(batch)
+}
+
+/// Drop the chunk sets of the blobs dropped by `release_blob_refs`, unless reads still use
+/// them: these stay marked for the next call.
+fn release_chunks(state: &Store) -> io::Result<()> {
+    let mut batch = Batch::default();
+    let in_use = CHUNKS_IN_USE.lock().unwrap();
+    for item in state.scan(CHUNKS_CF, b"free\0") {
+        let (key, _) = item?;
+        let set = match key.strip_prefix(b"free\0") {
+            Some(set) => set,
+            None => break,
+        };
+        if in_use.contains_key(set) {
+            continue;
+        }
+        if let Some(value) = state.get(CHUNKS_CF, &chunk_set_key(set))? {
+            delete_chunk_set(&mut batch, set, chunk_count(decode_counter(&value).max(0) as u64));
+        }
+        batch.delete(CHUNKS_CF, &key);
+    }
+    drop(in_use);
+    state.write(batch)
+}
+
+/// Drop every chunk set no paste references, unless it is still in use: besides the ones
+/// of `release_chunks`, those left by interrupted uploads and by blobs a compaction filter
+/// dropped. It goes through every paste, so it is left to `purge_expired` (the periodic
+/// sweep) and to `verify`.
+fn sweep_chunks(state: &Store) -> io::Result<()> {
+    release_chunks(state)?;
+    // a body being stored is in use until it is referenced
+    let _refs = BLOB_REFS.lock().unwrap();
+    let mut referenced = HashSet::new();
+    for cf in [DEFAULT_CF, REVISIONS_CF] {
+        for item in scan_entries(state, cf, b"") {
+            let (_, value) = item?;
+            let entry = root_as_entry(&value).unwrap();
+            let blob = match entry.digest() {
+                Some(digest) if entry.chunks() > 0 => state.get(BLOBS_CF, digest.bytes())?,
+                _ => None,
+            };
+            referenced.extend(blob.map(blob_data));
+        }
+    }
+
+    let mut batch = Batch::default();
+    let in_use = CHUNKS_IN_USE.lock().unwrap();
+    for item in state.scan(CHUNKS_CF, b"set\0") {
+        let (key, value) = item?;
+        let set = match key.strip_prefix(b"set\0") {
+            Some(set) => set,
+            None => break,
+        };
+        if !referenced.contains(set) && !in_use.contains_key(set) {
+            delete_chunk_set(&mut batch, set, chunk_count(decode_counter(&value).max(0) as u64));
+        }
+    }
+    drop(in_use);
+    state.write(batch)
+}
+
+/// Encrypt again with the current key every paste and revision stored in plaintext or
+/// with another key, i.e. after a key rotation, so that the older keys can be dropped.
+/// Returns the number of records rewritten.
+pub fn reencrypt(state: &Store) -> io::Result<u64> {
+    let current = state.keyring().map_or(0, |keyring| keyring.current().id());
+    let mut stale = vec![];
+    for cf in [DEFAULT_CF, REVISIONS_CF] {
+        for item in scan_entries(state, cf, b"") {
+            let (key, value) = item?;
+            let entry = root_as_entry(&value).unwrap();
+            if entry.key_id() != current && entry.digest().is_some() && !is_past(entry.expiry_timestamp()) {
+                stale.push((cf, key));
+            }
+        }
+    }
+
+    let mut count = 0;
+    for (cf, key) in stale {
+        let _refs = BLOB_REFS.lock().unwrap();
+        // read again under the lock, the record may have changed since the scan
+        let meta = match state.get(cf, &key)? {
+            Some(meta) if root_as_entry(&meta).unwrap().key_id() != current => meta,
+            _ => continue,
+        };
+        let entry = root_as_entry(&meta).unwrap();
+        let (size, codec) = (entry.size(), Codec::of(&entry)?);
+        let paste = load_paste(meta.clone(), state)?;
+        let body = if paste.is_chunked() {
+            // the content is copied as stored, already compressed
+            let mut writer = ChunkWriter::new(&Compression { codec: Codec::None, level: 0, threshold: 0 }, state)?;
+            io::copy(&mut paste.stored_reader(), &mut writer)?;
+            StoredBody { size, codec, ..writer.finish()? }
+        } else {
+            StoredBody::inline(paste.data.as_slice(), size, codec, state)
+        };
+
+        // digests depend on the key, the body moves to a new blob
+        let mut batch = Batch::default();
+        add_body_ref(&mut batch, &key, entry.expiry_timestamp(), &body, state)?;
+        release_blob_refs(&mut batch, &[(entry_digest(&meta).unwrap(), key.clone())], state)?;
+        batch.put(cf, &key, with_body(&meta, &body));
+        state.write(batch)?;
+        count += 1;
+    }
+    release_chunks(state)?;
+    Ok(count)
+}
+
+/// A paste or revision record found broken by `verify`.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct BrokenRecord {
+    pub id: String,
+    /// Set for a revision, `None` for the current version of a paste.
+    pub revision: Option<u32>,
+    pub error: String,
+}
+
+/// What `verify` does with the broken records it finds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Repair {
+    /// Move them to the quarantine column family
+    Quarantine,
+    /// Delete them
+    Delete,
+}
+
+/// Key in `QUARANTINE_CF` of the record `key` of `cf`.
+pub fn quarantine_key(cf: &str, key: &[u8]) -> Vec<u8> {
+    [cf.as_bytes(), b"\0", key].concat()
+}
+
+/// Why a paste or revision record cannot be read, if it cannot: it is not a valid `Entry`,
+/// or its body is not stored.
+fn entry_problem(value: &[u8], state: &Store) -> io::Result<Option<String>> {
+    let entry = match root_as_entry(value) {
+        Ok(entry) => entry,
+        Err(e) => return Ok(Some(format!("invalid entry: {e}"))),
+    };
+    if let Err(e) = Codec::of(&entry) {
+        return Ok(Some(e.to_string()));
+    }
+    let blob = match entry.digest() {
+        Some(digest) => state.get(BLOBS_CF, digest.bytes())?,
+        None => None,
+    };
+    // the body of an expired paste may already be compacted away
+    if blob.is_none() && !is_past(entry.expiry_timestamp()) {
+        return Ok(Some("content not found".to_string()));
+    }
+    Ok(None)
+}
+
+/// Check every paste and revision, returning those which cannot be read (see
+/// `entry_problem`), as well as the revisions of a broken or missing paste. With `repair`,
+/// they are removed (or moved to `QUARANTINE_CF`) along with their blob references, and
+/// the content size of the pastes removed is released.
+pub fn verify(repair: Option<Repair>, state: &Store) -> io::Result<Vec<BrokenRecord>> {
+    let mut broken = vec![];
+    let mut broken_ids = HashSet::new();
+    for item in state.scan(DEFAULT_CF, b"") {
+        let (key, value) = item?;
+        if let Some(error) = entry_problem(&value, state)? {
+            let id = String::from_utf8_lossy(&key).into_owned();
+            broken_ids.insert(id.clone());
+            broken.push((DEFAULT_CF, key, value, BrokenRecord { id, revision: None, error }));
+        }
+    }
+    for item in state.scan(REVISIONS_CF, b"") {
+        let (key, value) = item?;
+        // see `revision_key`
+        let (id, rev) = match key.len().checked_sub(5).filter(|&end| key[end] == 0) {
+            Some(end) => (String::from_utf8_lossy(&key[..end]).into_owned(), key[end + 1..].try_into().ok()),
+            None => (String::from_utf8_lossy(&key).into_owned(), None),
+        };
+        let error = match entry_problem(&value, state)? {
+            Some(error) => error,
+            None if rev.is_none() => "invalid revision key".to_string(),
+            None if broken_ids.contains(&id) || state.get(DEFAULT_CF, id.as_bytes())?.is_none() => {
+                "revision of a broken or missing paste".to_string()
+            }
+            None => continue,
+        };
+        let record = BrokenRecord { id, revision: rev.map(u32::from_be_bytes), error };
+        broken.push((REVISIONS_CF, key, value, record));
+    }
+
+    let repair = match repair {
+        Some(repair) if !broken.is_empty() => repair,
+        _ => return Ok(broken.into_iter().map(|(_, _, _, record)| record).collect()),
+    };
+    let refs = BLOB_REFS.lock().unwrap();
+    let mut batch = Batch::default();
+    let removed: HashSet<&[u8]> = broken.iter().map(|(_, key, _, _)| key.as_slice()).collect();
+    for (cf, key, value, _) in &broken {
+        batch.delete(cf, key);
+        if repair == Repair::Quarantine {
+            batch.put(QUARANTINE_CF, quarantine_key(cf, key), value);
         }
-     };
-);
+    }
+    // the digest of a corrupt record is unknown, its references are found by key instead
+    let mut released = vec![];
+    for item in state.scan(BLOB_REFS_CF, b"") {
+        let (key, _) = item?;
+        if key.len() > DIGEST_LEN && removed.contains(&key[DIGEST_LEN..]) {
+            released.push((key[..DIGEST_LEN].to_vec(), key[DIGEST_LEN..].to_vec()));
+        }
+    }
+    release_blob_refs(&mut batch, &released, state)?;
+    state.write(batch)?;
+    drop(refs);
 
-pub fn compaction_filter_expired_entries(
-    _: u32,
-    _: &[u8],
-    value: &[u8],
-) -> compaction_filter::Decision {
-    use compaction_filter::Decision::*;
+    release_compacted(state)?;
+    sweep_chunks(state)?;
+    Ok(broken.into_iter().map(|(_, _, _, record)| record).collect())
+}
 
-    let entry = root_as_entry(value).unwrap();
-    let now = SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .expect("time went backwards")
-        .as_secs();
+/// Aggregate figures over the whole database.
+#[derive(Serialize, Default, Debug)]
+pub struct Stats {
+    pub pastes: u64,
+    /// Content size of the current versions of the pastes.
+    pub bytes: u64,
+    pub revisions: u64,
+    pub revision_bytes: u64,
+    /// Expired pastes not yet removed by compaction.
+    pub expired: u64,
+    pub burn: u64,
+    pub encrypted: u64,
+    pub langs: BTreeMap<String, u64>,
+    /// Distinct bodies stored, shared by identical pastes and revisions.
+    pub blobs: u64,
+    /// Size of the stored bodies, after deduplication and compression. Bodies stored in
+    /// chunks only count for the ID of their chunk set here.
+    pub blob_bytes: u64,
+    /// Size of the bodies stored in chunks, see `CHUNKS_CF`.
+    pub chunk_bytes: u64,
+    /// Content size counted against `--max-storage`, see `STORAGE_CF`.
+    pub stored_bytes: u64,
+    /// See `migrate`.
+    pub schema_version: u32,
+}
 
-    if entry.expiry_timestamp() != 0 && now >= entry.expiry_timestamp() {
-        Remove
-    } else {
-        Keep
+pub fn stats(state: &Store) -> io::Result<Stats> {
+    let mut stats = Stats::default();
+
+    for item in scan_entries(state, DEFAULT_CF, b"") {
+        let (_, value) = item?;
+        let entry = root_as_entry(&value).unwrap();
+        stats.pastes += 1;
+        stats.bytes += data_len(&value) as u64;
+        stats.expired += is_expired(&value) as u64;
+        stats.burn += entry.burn() as u64;
+        stats.encrypted += entry.encrypted() as u64;
+        *stats.langs.entry(entry.lang().unwrap_or("markup").to_string()).or_default() += 1;
+    }
+
+    for item in scan_entries(state, REVISIONS_CF, b"") {
+        let (_, value) = item?;
+        stats.revisions += 1;
+        stats.revision_bytes += data_len(&value) as u64;
+    }
+
+    for item in state.scan(BLOBS_CF, b"") {
+        let (_, value) = item?;
+        stats.blobs += 1;
+        stats.blob_bytes += value.len().saturating_sub(8) as u64;
+    }
+    for item in state.scan(CHUNKS_CF, b"set\0") {
+        let (key, value) = item?;
+        if !key.starts_with(b"set\0") {
+            break;
+        }
+        stats.chunk_bytes += decode_counter(&value).max(0) as u64;
     }
+    stats.stored_bytes = stored_bytes(state)?;
+    stats.schema_version = schema_version(state)?;
+
+    Ok(stats)
 }
 
-/// Validate a Prism language identifier. Prism names are lowercase alphanumeric
-/// plus `-`, `_`, `+`, `#` (e.g. "c++", "c#", "shell-session"). Anything else
-/// falls back to "markup" so it can never inject arbitrary CSS class names.
-pub fn sanitize_lang(lang: &str) -> &str {
-    if !lang.is_empty()
-        && lang
-            .chars()
-            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '+' | '#'))
-    {
-        lang
-    } else {
-        "markup"
+/// A paste as read from the database: its `Entry` metadata and its content, as
+/// stored (see `Paste::content` for the decoded content).
+pub struct Paste {
+    pub root: Vec<u8>,
+    /// The content, empty for pastes stored in chunks (see `Paste::stored_reader`).
+    pub data: Vec<u8>,
+    /// Reads left after this one, for pastes with a `max_views` limit.
+    pub views_left: Option<u32>,
+    /// The chunk set holding the content, if any, and the store it comes from. The set is in
+    /// use for as long as the paste lives.
+    chunks: Option<(Vec<u8>, Store, ChunksInUse)>,
+}
+
+impl Paste {
+    pub fn entry(&self) -> Entry<'_> {
+        root_as_entry(&self.root).unwrap()
+    }
+
+    pub fn codec(&self) -> Result<Codec, io::Error> {
+        Codec::of(&self.entry())
+    }
+
+    /// The content, decompressed. Pastes stored in chunks are loaded whole, see
+    /// `Paste::content_reader` to stream them.
+    pub fn content(&self) -> Result<Cow<'_, [u8]>, io::Error> {
+        if !self.is_chunked() {
+            return decompress(&self.data, self.codec()?);
+        }
+        let mut content = vec![];
+        self.content_reader()?.read_to_end(&mut content)?;
+        Ok(Cow::Owned(content))
+    }
+
+    /// Whether the content is stored in chunks, i.e. is larger than `CHUNK_SIZE`.
+    pub fn is_chunked(&self) -> bool {
+        self.chunks.is_some()
+    }
+
+    /// The content as stored, read a chunk at a time for chunked pastes. The chunks stay
+    /// readable as long as the paste or the reader live, even when the paste is removed.
+    pub fn stored_reader(&self) -> Box<dyn Read + Send> {
+        match &self.chunks {
+            Some((set, state, _)) => Box::new(ChunkReader {
+                state: state.clone(),
+                set: set.clone(),
+                chunks: self.entry().chunks(),
+                key_id: self.entry().key_id(),
+                next: 0,
+                chunk: io::Cursor::new(vec![]),
+                _in_use: ChunksInUse::new(set),
+            }),
+            None => Box::new(io::Cursor::new(self.data.clone())),
+        }
+    }
+
+    /// The content, decompressed while it is read.
+    pub fn content_reader(&self) -> Result<Box<dyn Read + Send>, io::Error> {
+        Ok(match self.codec()? {
+            Codec::None => self.stored_reader(),
+            Codec::Zstd => Box::new(zstd::stream::read::Decoder::new(self.stored_reader())?),
+        })
     }
 }
 
-pub fn get_extension(filename: &str) -> &str {
-    filename
-        .rfind('.')
-        .map(|idx| &filename[idx..])
-        .filter(|ext| ext.chars().skip(1).all(|c| c.is_ascii_alphanumeric()))
-        .unwrap_or("")
+/// Read a paste. With `rev` set, an older revision is returned instead of the current
+/// one; expiry, "burn" and `max_views` always follow the current entry. A read-only store
+/// refuses reads it would have to count with a `ReadOnlyFilesystem` error.
+pub fn get_entry_data(id: &str, rev: Option<u32>, state: &Store) -> Result<Paste, io::Error> {
+    let root = live_entry(id, state)?;
+    let entry = root_as_entry(&root).unwrap();
+
+    if state.is_read_only() && (entry.burn() || entry.max_views() > 0) {
+        return Err(io::Error::new(io::ErrorKind::ReadOnlyFilesystem, "counted reads need a writable store"));
+    }
+
+    if !entry.burn() {
+        return match entry.max_views() {
+            0 => read_paste(id, rev, root, state),
+            _ => read_counted(id, rev, root, state),
+        };
+    }
+
+    // "burn" one time only pastebin content: the record is taken out of the store first,
+    // so that of concurrent readers only one gets it
+    let root = match state.take(DEFAULT_CF, id.as_bytes())? {
+        Some(root) => root,
+        None => return Err(io::Error::new(io::ErrorKind::NotFound, "record not found")),
+    };
+    let paste = match read_paste(id, rev, root.clone(), state) {
+        Ok(paste) => paste,
+        Err(e) => {
+            // not read after all: the paste is put back for the next reader
+            state.put(DEFAULT_CF, id.as_bytes(), &root)?;
+            return Err(e);
+        }
+    };
+    let _refs = BLOB_REFS.lock().unwrap();
+    remove_entry(id, &root, state)?;
+    Ok(paste)
+}
+
+/// Read a paste like `get_entry_data`, without deleting a "burn" paste or counting a view:
+/// for the creator's preview.
+pub fn peek_entry_data(id: &str, rev: Option<u32>, state: &Store) -> Result<Paste, io::Error> {
+    let root = live_entry(id, state)?;
+    read_paste(id, rev, root, state)
 }
 
-pub fn get_entry_data(id: &str, state: &State<DB>) -> Result<Vec<u8>, io::Error> {
-    // read data from DB to Entry struct
-    let root = match state.get(id).unwrap() {
+/// Metadata of paste `id`, or a `NotFound` error when it is missing or expired (an
+/// expired paste is deleted on the way).
+fn live_entry(id: &str, state: &Store) -> Result<Vec<u8>, io::Error> {
+    let root = match get_entry(id, state)? {
         Some(root) => root,
         None => return Err(io::Error::new(io::ErrorKind::NotFound, "record not found")),
     };
-    let entry = root_as_entry(&root).unwrap();
+    if is_expired(&root) {
+        // a read-only store leaves it to its primary
+        if !state.is_read_only() {
+            delete_entry(id, state)?;
+        }
+        return Err(io::Error::new(io::ErrorKind::NotFound, "record not found"));
+    }
+    Ok(root)
+}
 
-    // check if data expired (might not be yet deleted by rocksb compaction hook)
-    let now = SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .expect("time went backwards")
-        .as_secs();
+/// Read a paste with a `max_views` limit, whose latest metadata is `root`.
+fn read_counted(id: &str, rev: Option<u32>, root: Vec<u8>, state: &Store) -> Result<Paste, io::Error> {
+    let entry = root_as_entry(&root).unwrap();
+    let max_views = entry.max_views() as i64;
 
-    if entry.expiry_timestamp() != 0 && now >= entry.expiry_timestamp() {
-        state.delete(id).unwrap();
+    // the read is counted before serving it: the store hands every reader a different
+    // count, so only `max_views` of them get the paste
+    let views = state.add(VIEWS_CF, &views_key(id, entry.expiry_timestamp()), 1)?;
+    // a reader which fetched `root` just before the last view removed the paste starts a
+    // new count, hence the second lookup, which drops that count again
+    if get_entry(id, state)?.is_none() {
+        let mut batch = Batch::default();
+        batch.delete(VIEWS_CF, views_key(id, entry.expiry_timestamp()));
+        state.write(batch)?;
+        return Err(io::Error::new(io::ErrorKind::NotFound, "record not found"));
+    }
+    if views > max_views {
         return Err(io::Error::new(io::ErrorKind::NotFound, "record not found"));
     }
 
-    // "burn" one time only pastebin content
-    if entry.burn() {
-        state.delete(id).unwrap();
+    let paste = match read_paste(id, rev, root.clone(), state) {
+        Ok(paste) => Paste { views_left: Some((max_views - views) as u32), ..paste },
+        Err(e) => {
+            // not read after all: the view is given back
+            state.add(VIEWS_CF, &views_key(id, entry.expiry_timestamp()), -1)?;
+            return Err(e);
+        }
+    };
+    if views == max_views {
+        if let Some(root) = state.take(DEFAULT_CF, id.as_bytes())? {
+            let _refs = BLOB_REFS.lock().unwrap();
+            remove_entry(id, &root, state)?;
+        }
     }
+    Ok(paste)
+}
 
-    Ok(root)
+/// Read revision `rev` (the latest one when `None`) of the entry `id`, whose latest
+/// metadata is `root`.
+fn read_paste(id: &str, rev: Option<u32>, root: Vec<u8>, state: &Store) -> Result<Paste, io::Error> {
+    let revision = root_as_entry(&root).unwrap().revision();
+    let meta = match rev {
+        Some(rev) if rev < revision => match state.get(REVISIONS_CF, &revision_key(id, rev))? {
+            Some(meta) => checked_entry(&revision_key(id, rev), meta)?,
+            None => return Err(io::Error::new(io::ErrorKind::NotFound, "revision not found")),
+        },
+        Some(rev) if rev > revision => return Err(io::Error::new(io::ErrorKind::NotFound, "revision not found")),
+        _ => root,
+    };
+    load_paste(meta, state)
+}
+
+/// Load the content described by the metadata `meta` of a paste or revision, decrypted.
+fn load_paste(meta: Vec<u8>, state: &Store) -> io::Result<Paste> {
+    let digest = entry_digest(&meta);
+    let data = match digest.as_ref().map(|digest| state.get(BLOBS_CF, digest)).transpose()? {
+        Some(Some(value)) => blob_data(value),
+        _ => return Err(io::Error::new(io::ErrorKind::NotFound, "content not found")),
+    };
+    let entry = root_as_entry(&meta).unwrap();
+
+    if entry.chunks() > 0 {
+        let in_use = ChunksInUse::new(&data);
+        let chunks = Some((data, state.clone(), in_use));
+        return Ok(Paste { root: meta, data: vec![], views_left: None, chunks });
+    }
+    let data = match entry.key_id() {
+        0 => data,
+        id => decryption_key(id, state)?.open(&data, digest.as_deref().unwrap())?,
+    };
+    Ok(Paste { root: meta, data, views_left: None, chunks: None })
 }
 
-pub fn new_entry(
+/// Build the metadata of the next revision of an entry. The content, language and
+/// encryption flag are replaced when given; timestamps, expiry and the owners carry over.
+pub fn new_revision(
     dest: &mut Vec<u8>,
-    data: &[u8],
-    lang: &str,
-    ttl: u64,
-    burn: bool,
-    encrypted: bool,
+    current: &[u8],
+    data: Option<&StoredBody>,
+    lang: Option<&str>,
+    encrypted: Option<bool>,
 ) {
+    let entry = root_as_entry(current).unwrap();
+    let unchanged = EntryChanges::of(&entry);
+
+    let changes = EntryChanges {
+        lang: lang.unwrap_or(unchanged.lang),
+        encrypted: encrypted.unwrap_or(unchanged.encrypted),
+        size: data.map_or(unchanged.size, |body| body.size),
+        codec: data.map_or(unchanged.codec, |body| body.codec as u8),
+        chunks: data.map_or(unchanged.chunks, |body| body.chunks),
+        key_id: data.map_or(unchanged.key_id, |body| body.key_id),
+        revision: entry.revision() + 1,
+        update_timestamp: now(),
+        ..unchanged
+    };
+    rebuild_entry(dest, &entry, &changes);
+}
+
+/// Pin or unpin paste `id`: pinned pastes are never evicted by `make_room`. Returns
+/// `false` when the paste does not exist.
+pub fn set_pinned(id: &str, pinned: bool, state: &Store) -> io::Result<bool> {
+    // edits rewrite the entry under the same lock, see `save_revision`
+    let _refs = BLOB_REFS.lock().unwrap();
+    let current = match get_entry(id, state)? {
+        Some(current) => current,
+        None => return Ok(false),
+    };
+    let entry = root_as_entry(&current).unwrap();
+    let mut dest = vec![];
+    rebuild_entry(&mut dest, &entry, &EntryChanges { pinned, ..EntryChanges::of(&entry) });
+    let mut batch = Batch::default();
+    batch.put(DEFAULT_CF, id, dest);
+    // a "burn" paste may have been read meanwhile, which takes the entry without the lock
+    state.write_if(DEFAULT_CF, id.as_bytes(), &current, batch)
+}
+
+/// Fields that can differ between an entry and its rebuilt copy.
+struct EntryChanges<'a> {
+    create_timestamp: u64,
+    expiry_timestamp: u64,
+    lang: &'a str,
+    encrypted: bool,
+    size: u64,
+    codec: u8,
+    revision: u32,
+    update_timestamp: u64,
+    digest: Option<&'a [u8]>,
+    pinned: bool,
+    chunks: u32,
+    key_id: u32,
+}
+
+impl<'a> EntryChanges<'a> {
+    fn of(entry: &Entry<'a>) -> Self {
+        EntryChanges {
+            create_timestamp: entry.create_timestamp(),
+            expiry_timestamp: entry.expiry_timestamp(),
+            lang: entry.lang().unwrap_or("markup"),
+            encrypted: entry.encrypted(),
+            size: entry.size(),
+            codec: entry.codec(),
+            revision: entry.revision(),
+            update_timestamp: entry.update_timestamp(),
+            digest: entry.digest().map(|digest| digest.bytes()),
+            pinned: entry.pinned(),
+            chunks: entry.chunks(),
+            key_id: entry.key_id(),
+        }
+    }
+}
+
+/// Copy the metadata of `entry` with `changes` applied. The content is never copied.
+fn rebuild_entry(dest: &mut Vec<u8>, entry: &Entry, changes: &EntryChanges) {
+    let mut bldr = FlatBufferBuilder::new();
+
+    dest.clear();
+
+    let lang = bldr.create_string(changes.lang);
+    let owner_token = entry.owner_token().map(|token| bldr.create_vector(token.bytes()));
+    let preview_token = entry.preview_token().map(|token| bldr.create_vector(token.bytes()));
+    let api_key = entry.api_key().map(|name| bldr.create_string(name));
+    let owner = entry.owner().map(|owner| bldr.create_string(owner));
+    let digest = changes.digest.map(|digest| bldr.create_vector(digest));
+
+    let args = EntryArgs {
+        create_timestamp: changes.create_timestamp,
+        expiry_timestamp: changes.expiry_timestamp,
+        data: None,
+        lang: Some(lang),
+        burn: entry.burn(),
+        encrypted: changes.encrypted,
+        owner_token,
+        revision: changes.revision,
+        update_timestamp: changes.update_timestamp,
+        api_key,
+        owner,
+        size: changes.size,
+        codec: changes.codec,
+        digest,
+        max_views: entry.max_views(),
+        preview_token,
+        pinned: changes.pinned,
+        chunks: changes.chunks,
+        key_id: changes.key_id,
+    };
+
+    let user_offset = Entry::create(&mut bldr, &args);
+    finish_entry_buffer(&mut bldr, user_offset);
+
+    dest.extend_from_slice(bldr.finished_data());
+}
+
+/// Attributes of a new paste, besides its content.
+pub struct EntryOptions<'a> {
+    pub lang: &'a str,
+    pub ttl: u64,
+    pub burn: bool,
+    /// Delete the paste after that many reads, `0` for no limit.
+    pub max_views: u32,
+    pub encrypted: bool,
+    pub owner_token: Option<&'a [u8]>,
+    /// Digest of the token of the creator's preview of a "burn" paste, see `is_previewer`.
+    pub preview_token: Option<&'a [u8]>,
+    pub api_key: Option<&'a str>,
+    /// Subject of the logged in user creating the paste.
+    pub owner: Option<&'a str>,
+    /// Codec the content is stored with, see `compress`.
+    pub codec: Codec,
+}
+
+impl Default for EntryOptions<'_> {
+    fn default() -> Self {
+        EntryOptions {
+            lang: "markup",
+            ttl: 0,
+            burn: false,
+            max_views: 0,
+            encrypted: false,
+            owner_token: None,
+            preview_token: None,
+            api_key: None,
+            owner: None,
+            codec: Codec::None,
+        }
+    }
+}
+
+/// Build the metadata of a new paste holding `data` (uncompressed). The content itself
+/// is stored separately, see `insert_entry`.
+pub fn new_entry(dest: &mut Vec<u8>, data: &[u8], opts: &EntryOptions) {
     let mut bldr = FlatBufferBuilder::new();
 
     dest.clear();
     bldr.reset();
 
-    let data_vec = bldr.create_vector(data);
+    let owner_token = opts.owner_token.map(|token| bldr.create_vector(token));
+    let preview_token = opts.preview_token.map(|token| bldr.create_vector(token));
+    let api_key = opts.api_key.map(|name| bldr.create_string(name));
+    let owner = opts.owner.map(|owner| bldr.create_string(owner));
 
     // calc expiry datetime
-    let now = SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .expect("time went backwards")
-        .as_secs();
-    let expiry = if ttl == 0 { ttl } else { now + ttl };
+    let now = now();
+    let expiry = if opts.ttl == 0 { 0 } else { now + opts.ttl };
 
     // setup actual struct
     let args = EntryArgs {
         create_timestamp: now,
         expiry_timestamp: expiry,
-        data: Some(data_vec),
-        lang: Some(bldr.create_string(lang)),
-        burn,
-        encrypted,
+        lang: Some(bldr.create_string(opts.lang)),
+        burn: opts.burn,
+        max_views: opts.max_views,
+        encrypted: opts.encrypted,
+        owner_token,
+        preview_token,
+        api_key,
+        owner,
+        size: data.len() as u64,
+        codec: opts.codec as u8,
+        ..Default::default()
     };
 
     let user_offset = Entry::create(&mut bldr, &args);
@@ -180,6 +2067,495 @@ mod tests {
         assert_eq!(get_extension("file.tar.gz-backup"), "");
     }
 
+    // ── owner tokens ──────────────────────────────────────────────────────────
+
+    #[test]
+    fn is_owner_matches_only_the_minted_token() {
+        let mut buf = vec![];
+        let token = hash_token("secret");
+        new_entry(&mut buf, b"test", &EntryOptions { owner_token: Some(&token), ..Default::default() });
+        assert!(is_owner(&buf, "secret"));
+        assert!(!is_owner(&buf, "Secret"));
+        assert!(!is_owner(&buf, ""));
+    }
+
+    #[test]
+    fn is_owner_rejects_entries_without_token() {
+        let mut buf = vec![];
+        new_entry(&mut buf, b"test", &EntryOptions::default());
+        assert!(!is_owner(&buf, "secret"));
+    }
+
+    #[test]
+    fn is_owned_by_matches_subject_across_revisions() {
+        let mut buf = vec![];
+        new_entry(&mut buf, b"test", &EntryOptions { owner: Some("alice"), ..Default::default() });
+        assert!(is_owned_by(&buf, "alice"));
+        assert!(!is_owned_by(&buf, "bob"));
+
+        let (mut next, state) = (vec![], Store::new(MemoryStore::default()));
+        new_revision(&mut next, &buf, Some(&StoredBody::inline(&b"edited"[..], 6, Codec::None, &state)), None, None);
+        assert!(is_owned_by(&next, "alice"));
+
+        new_entry(&mut buf, b"test", &EntryOptions::default());
+        assert!(!is_owned_by(&buf, "alice"));
+    }
+
+    // ── revisions ─────────────────────────────────────────────────────────────
+
+    #[test]
+    fn new_revision_replaces_content_and_keeps_metadata() {
+        let mut current = vec![];
+        let token = hash_token("secret");
+        let opts = EntryOptions { lang: "rust", ttl: 3600, owner_token: Some(&token), ..Default::default() };
+        new_entry(&mut current, b"first", &opts);
+
+        let (mut next, state) = (vec![], Store::new(MemoryStore::default()));
+        let body = StoredBody::inline(&b"second"[..], 6, Codec::None, &state);
+        new_revision(&mut next, &current, Some(&body), None, None);
+
+        let (before, after) = (root_as_entry(&current).unwrap(), root_as_entry(&next).unwrap());
+        assert_eq!(after.size(), 6);
+        assert!(after.data().is_none());
+        assert_eq!(after.lang(), Some("rust"));
+        assert_eq!(after.revision(), before.revision() + 1);
+        assert_eq!(after.create_timestamp(), before.create_timestamp());
+        assert_eq!(after.expiry_timestamp(), before.expiry_timestamp());
+        assert_ne!(after.update_timestamp(), 0);
+        assert!(is_owner(&next, "secret"));
+    }
+
+    #[test]
+    fn new_revision_can_change_only_the_language() {
+        let mut current = vec![];
+        new_entry(&mut current, b"first", &EntryOptions { lang: "rust", ..Default::default() });
+
+        let mut next = vec![];
+        new_revision(&mut next, &current, None, Some("toml"), None);
+
+        let after = root_as_entry(&next).unwrap();
+        assert_eq!(after.size(), 5);
+        assert_eq!(after.lang(), Some("toml"));
+    }
+
+    #[test]
+    fn migrations_run_once_and_newer_schemas_are_refused() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let path = tmp.path().join("database");
+        let path = path.to_str().unwrap();
+        {
+            // a database at version 1, from before the content size was accounted for
+            let db = open_db(path).unwrap();
+            assert_eq!(schema_version(&db).unwrap(), SCHEMA_VERSION);
+            for (id, data) in [("first", &b"0123456789"[..]), ("second", b"abc")] {
+                let mut meta = vec![];
+                new_entry(&mut meta, data, &EntryOptions::default());
+                insert_entry(id, &meta, data, &db).unwrap();
+            }
+            let keys: Vec<_> = db.scan(STORAGE_CF, b"").map(|item| item.unwrap().0).collect();
+            for key in keys {
+                db.delete(STORAGE_CF, &key).unwrap();
+            }
+            db.put(META_CF, SCHEMA_VERSION_KEY, &1u32.to_be_bytes()).unwrap();
+        }
+
+        let db = open_db(path).unwrap();
+        assert_eq!(stats(&db).unwrap().stored_bytes, 13);
+        assert_eq!(stats(&db).unwrap().schema_version, SCHEMA_VERSION);
+        assert_eq!(migrate(&db).unwrap(), 0);
+        assert_eq!(get_entry_data("first", None, &db).unwrap().data, b"0123456789");
+
+        db.put(META_CF, SCHEMA_VERSION_KEY, &(SCHEMA_VERSION + 1).to_be_bytes()).unwrap();
+        drop(db);
+        let error = open_db(path).err().unwrap();
+        assert!(error.to_string().contains("newer than this release supports"), "{error}");
+    }
+
+    #[test]
+    fn secondaries_read_the_primary_and_catch_up_with_it() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let path = tmp.path().join("database");
+        let primary = open_db(path.to_str().unwrap()).unwrap();
+        let insert = |id: &str, opts: &EntryOptions| {
+            let mut meta = vec![];
+            new_entry(&mut meta, b"shared", opts);
+            insert_entry(id, &meta, b"shared", &primary).unwrap();
+        };
+        insert("first", &EntryOptions::default());
+
+        let secondary = open_secondary(path.to_str().unwrap(), tmp.path().join("secondary").to_str().unwrap()).unwrap();
+        assert!(secondary.is_read_only());
+        assert_eq!(get_entry_data("first", None, &secondary).unwrap().data, b"shared");
+        assert!(delete_entry("first", &secondary).is_err());
+
+        insert("second", &EntryOptions { max_views: 2, ..Default::default() });
+        assert!(get_entry("second", &secondary).unwrap().is_none());
+        secondary.catch_up().unwrap();
+        // a counted read needs the primary
+        let error = get_entry_data("second", None, &secondary).err().map(|e| e.kind());
+        assert_eq!(error, Some(io::ErrorKind::ReadOnlyFilesystem));
+        assert_eq!(peek_entry_data("second", None, &secondary).unwrap().data, b"shared");
+        assert!(primary.catch_up().is_err());
+    }
+
+    #[test]
+    fn revision_keys_sort_by_paste_then_revision() {
+        assert!(revision_key("abc", 1) < revision_key("abc", 2));
+        assert!(revision_key("abc", 255) < revision_key("abc", 256));
+        assert!(!revision_key("abcd", 0).starts_with(&revision_prefix("abc")));
+    }
+
+    #[test]
+    fn open_db_moves_legacy_content_to_blobs() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let path = tmp.path().join("database");
+        let path = path.to_str().unwrap();
+        {
+            // the oldest records kept the content in `data`, later ones in CONTENT_CF
+            let db = open_db(path).unwrap();
+            let mut legacy = make_entry_with_expiry(0);
+            db.put(DEFAULT_CF, b"paste", &legacy).unwrap();
+            db.put(REVISIONS_CF, &revision_key("paste", 0), &legacy).unwrap();
+            legacy = make_entry_with_expiry(1);
+            db.put(DEFAULT_CF, b"expired", &legacy).unwrap();
+
+            let mut meta = vec![];
+            new_entry(&mut meta, b"other", &EntryOptions::default());
+            db.put(DEFAULT_CF, b"split", &meta).unwrap();
+            db.put(CONTENT_CF, b"split", &blob_value(0, b"other")).unwrap();
+            // as written before schema versions were recorded
+            db.delete(META_CF, SCHEMA_VERSION_KEY).unwrap();
+        }
+
+        let db = open_db(path).unwrap();
+        let meta = get_entry("paste", &db).unwrap().unwrap();
+        assert!(root_as_entry(&meta).unwrap().data().is_none());
+        assert_eq!(root_as_entry(&meta).unwrap().size(), 4);
+        assert_eq!(get_entry_data("paste", None, &db).unwrap().data, b"test");
+        assert_eq!(get_entry_data("split", None, &db).unwrap().data, b"other");
+        assert_eq!(db.scan(CONTENT_CF, b"").count(), 0);
+
+        // the three "test" records share one blob
+        let stats = stats(&db).unwrap();
+        assert_eq!((stats.blobs, stats.blob_bytes), (2, 9));
+        let digest = content_digest(b"test");
+        assert_eq!(db.scan(BLOB_REFS_CF, &digest).take(3).count(), 3);
+
+        // the expiry travels with the reference, so compaction can drop it
+        let reference = db.get(BLOB_REFS_CF, &blob_ref_key(&digest, b"expired")).unwrap().unwrap();
+        assert!(matches!(compaction_filter_expired_blobs(0, &[], &reference), Decision::Remove));
+    }
+
+    #[test]
+    fn identical_bodies_share_a_blob_until_the_last_reference_goes() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let db = open_db(tmp.path().join("database").to_str().unwrap()).unwrap();
+        let insert = |id: &str, opts: &EntryOptions| {
+            let mut meta = vec![];
+            new_entry(&mut meta, b"stack trace", opts);
+            insert_entry(id, &meta, b"stack trace", &db).unwrap();
+        };
+        let blobs = |db: &Store| db.scan(BLOBS_CF, b"").map(|item| blob_expiry(&item.unwrap().1)).collect::<Vec<_>>();
+
+        insert("forever", &EntryOptions::default());
+        insert("burned", &EntryOptions { burn: true, ..Default::default() });
+        insert("short", &EntryOptions { ttl: 2, ..Default::default() });
+        assert_eq!(blobs(&db), [0]);
+
+        // an edit keeps the shared body as revision 0
+        let current = get_entry("forever", &db).unwrap().unwrap();
+        let (mut next, fixed) = (vec![], StoredBody::inline(&b"fixed"[..], 5, Codec::None, &db));
+        new_revision(&mut next, &current, Some(&fixed), None, None);
+        save_revision("forever", &current, &next, Some(&fixed), &db).unwrap();
+        assert_eq!(blobs(&db).len(), 2);
+
+        assert_eq!(get_entry_data("burned", None, &db).unwrap().data, b"stack trace");
+        assert!(get_entry_data("burned", None, &db).is_err());
+        delete_entry("forever", &db).unwrap();
+        assert_eq!(get_entry_data("short", None, &db).unwrap().data, b"stack trace");
+
+        // the blob now expires with its last reference, and compaction drops it
+        let expiry = root_as_entry(&get_entry("short", &db).unwrap().unwrap()).unwrap().expiry_timestamp();
+        assert_eq!(blobs(&db), [expiry]);
+        std::thread::sleep(std::time::Duration::from_millis(2100));
+        db.compact().unwrap();
+        assert!(get_entry("short", &db).unwrap().is_none());
+        assert!(blobs(&db).is_empty());
+        assert_eq!(db.scan(BLOB_REFS_CF, b"").count(), 0);
+    }
+
+    #[test]
+    fn large_bodies_are_stored_in_chunks_until_the_sweep_releases_them() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let db = open_db(tmp.path().join("database").to_str().unwrap()).unwrap();
+        let content = b"build log line\n".repeat(CHUNK_SIZE / 6);
+        let none = Compression { codec: Codec::None, level: 3, threshold: 64 };
+        let upload = |id: &str| {
+            let mut writer = ChunkWriter::new(&none, &db).unwrap();
+            for piece in content.chunks(100_000) {
+                writer.write_all(piece).unwrap();
+            }
+            let body = writer.finish().unwrap();
+            let mut meta = vec![];
+            new_entry(&mut meta, &[], &EntryOptions::default());
+            insert_body(id, &meta, &body, &db).unwrap();
+        };
+        let chunks = |db: &Store| {
+            let keys = db.scan(CHUNKS_CF, b"").map(|item| item.unwrap().0);
+            keys.filter(|key| key.starts_with(b"chunk\0")).count()
+        };
+
+        upload("log");
+        assert_eq!(chunks(&db), 3);
+        assert_eq!(stats(&db).unwrap().chunk_bytes, content.len() as u64);
+        let log = get_entry_data("log", None, &db).unwrap();
+        assert!(log.is_chunked());
+        assert_eq!(log.entry().size(), content.len() as u64);
+        assert_eq!(log.content().unwrap(), &content[..]);
+        drop(log);
+
+        // an identical body reuses the chunks of the first one
+        upload("copy");
+        assert_eq!(chunks(&db), 3);
+
+        // a reader keeps the chunks of a removed paste until it is done
+        let paste = get_entry_data("copy", None, &db).unwrap();
+        delete_entry("log", &db).unwrap();
+        delete_entry("copy", &db).unwrap();
+        purge_expired(&db).unwrap();
+        let mut read = vec![];
+        paste.content_reader().unwrap().read_to_end(&mut read).unwrap();
+        assert_eq!(read, content);
+        drop(paste);
+        purge_expired(&db).unwrap();
+        assert_eq!(db.scan(CHUNKS_CF, b"").count(), 0);
+
+        // without readers, the chunks go with the paste
+        upload("log");
+        delete_entry("log", &db).unwrap();
+        assert_eq!(db.scan(CHUNKS_CF, b"").count(), 0);
+
+        // a body which is not stored after all is dropped right away
+        let mut writer = ChunkWriter::new(&Compression { codec: Codec::Zstd, ..none }, &db).unwrap();
+        writer.write_all(&content).unwrap();
+        writer.finish().unwrap().discard(&db).unwrap();
+        assert_eq!(db.scan(CHUNKS_CF, b"").count(), 0);
+    }
+
+    #[test]
+    fn bodies_are_encrypted_at_rest_and_reencrypted_after_a_key_rotation() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let plain = open_db(tmp.path().join("database").to_str().unwrap()).unwrap();
+        let (old, new) = (&[1u8; 32][..], &[2u8; 32][..]);
+        let with_keys = |keys: &[&[u8]]| plain.clone().with_keyring(Keyring::new(keys).unwrap());
+        let large = b"secret build log\n".repeat(CHUNK_SIZE / 8);
+        let insert = |id: &str, data: &[u8], db: &Store| {
+            let mut meta = vec![];
+            new_entry(&mut meta, data, &EntryOptions::default());
+            insert_entry(id, &meta, data, db).unwrap();
+        };
+        let leaks = |db: &Store| {
+            let values: Vec<Vec<u8>> = [BLOBS_CF, CHUNKS_CF]
+                .iter()
+                .flat_map(|cf| db.scan(cf, b"").map(|item| item.unwrap().1))
+                .collect();
+            values.iter().any(|value| value.windows(6).any(|window| window == b"secret"))
+        };
+
+        insert("plain", b"secret in plaintext", &plain);
+        let db = with_keys(&[old]);
+        insert("small", b"secret token", &db);
+        let mut writer = ChunkWriter::new(&Compression { codec: Codec::None, level: 0, threshold: 0 }, &db).unwrap();
+        writer.write_all(&large).unwrap();
+        let mut meta = vec![];
+        new_entry(&mut meta, &[], &EntryOptions::default());
+        insert_body("large", &meta, &writer.finish().unwrap(), &db).unwrap();
+
+        assert_eq!(get_entry_data("small", None, &db).unwrap().data, b"secret token");
+        assert_eq!(get_entry_data("large", None, &db).unwrap().content().unwrap(), &large[..]);
+        assert_eq!(get_entry_data("plain", None, &db).unwrap().data, b"secret in plaintext");
+        assert!(get_entry_data("small", None, &plain).is_err());
+        assert!(get_entry_data("small", None, &with_keys(&[new])).is_err());
+
+        // rotation: the new key encrypts, the old one still decrypts until every record moves
+        let rotated = with_keys(&[new, old]);
+        assert_eq!(reencrypt(&rotated).unwrap(), 3);
+        assert_eq!(reencrypt(&rotated).unwrap(), 0);
+        assert!(!leaks(&rotated));
+        let db = with_keys(&[new]);
+        assert_eq!(get_entry_data("plain", None, &db).unwrap().data, b"secret in plaintext");
+        assert_eq!(get_entry_data("small", None, &db).unwrap().data, b"secret token");
+        assert_eq!(get_entry_data("large", None, &db).unwrap().content().unwrap(), &large[..]);
+        assert_eq!(stats(&db).unwrap().chunk_bytes, large.len() as u64);
+    }
+
+    #[test]
+    fn corrupt_records_fail_their_readers_and_are_quarantined_by_verify() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let db = open_db(tmp.path().join("database").to_str().unwrap()).unwrap();
+        let insert = |id: &str, data: &[u8]| {
+            let mut meta = vec![];
+            new_entry(&mut meta, data, &EntryOptions::default());
+            insert_entry(id, &meta, data, &db).unwrap();
+            meta
+        };
+        let good = insert("good", b"fine");
+        insert("bad", b"lost body");
+        db.put(DEFAULT_CF, b"bad", b"not a flatbuffer").unwrap();
+        db.put(REVISIONS_CF, &revision_key("good", 7), b"\xff").unwrap();
+        db.put(REVISIONS_CF, &revision_key("gone", 0), &good).unwrap();
+
+        assert_eq!(get_entry("bad", &db).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(get_entry_data("bad", None, &db).err().map(|e| e.kind()), Some(io::ErrorKind::InvalidData));
+        assert_eq!(get_entry_data("good", Some(7), &db).err().map(|e| e.kind()), Some(io::ErrorKind::NotFound));
+        assert_eq!(list_entries(None, 10, &db).unwrap().len(), 1);
+        assert_eq!(stats(&db).unwrap().pastes, 1);
+        purge_expired(&db).unwrap();
+        let keep = compaction_filter_expired_entries(0, b"bad", b"not a flatbuffer");
+        assert!(matches!(keep, compaction_filter::Decision::Keep));
+
+        let broken = verify(None, &db).unwrap();
+        let found: Vec<_> = broken.iter().map(|record| (record.id.as_str(), record.revision)).collect();
+        assert_eq!(found, [("bad", None), ("gone", Some(0)), ("good", Some(7))]);
+        assert_eq!(verify(Some(Repair::Quarantine), &db).unwrap(), broken);
+        assert!(verify(None, &db).unwrap().is_empty());
+
+        assert_eq!(db.get(QUARANTINE_CF, &quarantine_key(DEFAULT_CF, b"bad")).unwrap().unwrap(), b"not a flatbuffer");
+        assert_eq!(db.scan(QUARANTINE_CF, b"").count(), 3);
+        assert!(get_entry("bad", &db).unwrap().is_none());
+        assert!(db.get(BLOBS_CF, &content_digest(b"lost body")).unwrap().is_none());
+        assert_eq!(get_entry_data("good", None, &db).unwrap().data, b"fine");
+        assert_eq!(stats(&db).unwrap().stored_bytes, 4);
+    }
+
+    #[test]
+    fn make_room_evicts_the_oldest_unpinned_pastes() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let db = open_db(tmp.path().join("database").to_str().unwrap()).unwrap();
+        // created within the same second, so they sort by ID
+        for id in ["a", "b", "c"] {
+            let mut meta = vec![];
+            new_entry(&mut meta, b"0123456789", &EntryOptions::default());
+            insert_entry(id, &meta, b"0123456789", &db).unwrap();
+        }
+        assert!(set_pinned("a", true, &db).unwrap());
+        assert_eq!(stored_bytes(&db).unwrap(), 30);
+
+        assert_eq!(make_room(15, 40, None, &db).unwrap(), Some(vec!["b".to_string()]));
+        assert_eq!(stored_bytes(&db).unwrap(), 20);
+        assert_eq!(make_room(5, 40, None, &db).unwrap(), Some(vec![]));
+
+        // never evicting anything unless it makes enough room
+        assert_eq!(make_room(35, 40, None, &db).unwrap(), None);
+        assert_eq!(make_room(41, 40, None, &db).unwrap(), None);
+        assert_eq!(make_room(25, 40, Some("c"), &db).unwrap(), None);
+        assert!(get_entry("c", &db).unwrap().is_some());
+
+        delete_entry("c", &db).unwrap();
+        assert_eq!(stored_bytes(&db).unwrap(), 10);
+        let ages = db.scan(STORAGE_CF, b"age\0").filter(|item| item.as_ref().unwrap().0.starts_with(b"age\0"));
+        assert_eq!(ages.count(), 1);
+    }
+
+    #[test]
+    fn max_views_paste_is_removed_by_its_last_read() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let db = open_db(tmp.path().join("database").to_str().unwrap()).unwrap();
+        let mut meta = vec![];
+        new_entry(&mut meta, b"incident notes", &EntryOptions { max_views: 3, ..Default::default() });
+        insert_entry("notes", &meta, b"incident notes", &db).unwrap();
+
+        let views_left = |rev| get_entry_data("notes", rev, &db).map(|paste| paste.views_left);
+        assert_eq!(views_left(None).unwrap(), Some(2));
+        assert_eq!(views_left(Some(0)).unwrap(), Some(1));
+        let root = get_entry("notes", &db).unwrap().unwrap();
+        assert_eq!(views_left(None).unwrap(), Some(0));
+        assert_eq!(views_left(None).unwrap_err().kind(), io::ErrorKind::NotFound);
+        // a reader losing the race for the last view leaves no count behind
+        let late = read_counted("notes", None, root, &db).err().map(|e| e.kind());
+        assert_eq!(late, Some(io::ErrorKind::NotFound));
+
+        assert!(get_entry("notes", &db).unwrap().is_none());
+        assert_eq!(db.scan(VIEWS_CF, b"").count(), 0);
+        assert_eq!(db.scan(BLOBS_CF, b"").count(), 0);
+    }
+
+    #[test]
+    fn burn_paste_survives_a_failed_read_and_is_not_brought_back_by_an_edit() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let db = open_db(tmp.path().join("database").to_str().unwrap()).unwrap();
+        let mut meta = vec![];
+        new_entry(&mut meta, b"secret", &EntryOptions { burn: true, ..Default::default() });
+        insert_entry("secret", &meta, b"secret", &db).unwrap();
+
+        // the body cannot be read for now, the paste stays
+        let blob = db.take(BLOBS_CF, &content_digest(b"secret")).unwrap().unwrap();
+        assert_eq!(get_entry_data("secret", None, &db).err().map(|e| e.kind()), Some(io::ErrorKind::NotFound));
+        assert!(get_entry("secret", &db).unwrap().is_some());
+        db.put(BLOBS_CF, &content_digest(b"secret"), &blob).unwrap();
+
+        // an edit started before the paste was read does not bring it back
+        let current = get_entry("secret", &db).unwrap().unwrap();
+        assert_eq!(get_entry_data("secret", None, &db).unwrap().data, b"secret");
+        let mut next = vec![];
+        new_revision(&mut next, &current, None, Some("rust"), None);
+        let edit = save_revision("secret", &current, &next, None, &db).unwrap_err();
+        assert_eq!(edit.kind(), io::ErrorKind::NotFound);
+        assert!(get_entry("secret", &db).unwrap().is_none());
+    }
+
+    #[test]
+    fn compaction_filter_drops_view_counters_of_expired_pastes() {
+        let keep = |expiry| matches!(compaction_filter_expired_views(0, &views_key("abc", expiry), &[]), Decision::Keep);
+
+        assert!(keep(0));
+        assert!(keep(now() + 60));
+        assert!(!keep(now() - 60));
+    }
+
+    // ── compression ───────────────────────────────────────────────────────────
+
+    #[test]
+    fn compress_round_trips_and_respects_threshold() {
+        let zstd = Compression { codec: Codec::Zstd, level: 3, threshold: 64 };
+        let log = "INFO request served in 3ms\n".repeat(100);
+
+        let (stored, codec) = compress(log.as_bytes(), &zstd);
+        assert_eq!(codec, Codec::Zstd);
+        assert!(stored.len() < log.len() / 5);
+        assert_eq!(decompress(&stored, codec).unwrap(), log.as_bytes());
+
+        assert_eq!(compress(b"short", &zstd), (Cow::Borrowed(&b"short"[..]), Codec::None));
+        let none = Compression { codec: Codec::None, ..zstd };
+        assert_eq!(compress(log.as_bytes(), &none).1, Codec::None);
+    }
+
+    // ── admin filters ─────────────────────────────────────────────────────────
+
+    #[test]
+    fn entry_filter_requires_every_criterion() {
+        let mut buf = vec![];
+        new_entry(&mut buf, b"0123456789", &EntryOptions { lang: "rust", ..Default::default() });
+        let info = EntryInfo::new("id", &buf);
+
+        assert!(EntryFilter::default().is_empty());
+        assert!(EntryFilter { larger_than: Some(9), lang: Some("Rust"), ..Default::default() }.matches(&info));
+        assert!(!EntryFilter { larger_than: Some(10), lang: Some("rust"), ..Default::default() }.matches(&info));
+        assert!(!EntryFilter { older_than: Some(3600), ..Default::default() }.matches(&info));
+    }
+
+    // ── API key counters ──────────────────────────────────────────────────────
+
+    #[test]
+    fn compaction_filter_drops_only_past_hourly_counters() {
+        let hour = now() / 3600;
+        let keep = |key: Vec<u8>| matches!(compaction_filter_stale_counters(0, &key, &[]), Decision::Keep);
+
+        assert!(keep(usage_hour_key("ci", hour)));
+        assert!(!keep(usage_hour_key("ci", hour - 1)));
+        assert!(keep(usage_bytes_key("ci")));
+    }
+
     // ── compaction_filter_expired_entries ──────────────────────────────────────
 
     fn make_entry_with_expiry(expiry_timestamp: u64) -> Vec<u8> {
@@ -196,6 +2572,7 @@ mod tests {
             lang: Some(lang),
             burn: false,
             encrypted: false,
+            ..Default::default()
         };
         let offset = Entry::create(&mut bldr, &args);
         finish_entry_buffer(&mut bldr, offset);
@@ -1,30 +1,63 @@
 use std::io;
-use std::io::Cursor;
+use std::io::{Cursor, Read, Write};
 use std::net::IpAddr;
+use std::pin::Pin;
 use std::str::FromStr;
+use std::task::{ready, Context, Poll};
 
 use rocket::config::{Config, LogLevel};
 use rocket::data::{Data, ToByteUnit};
 use rocket::http::{ContentType, Status};
 use rocket::request::{FromRequest, Outcome, Request};
 use rocket::response::{self, Responder, Response};
-use rocket::{delete, get, post, routes};
+use rocket::{catch, catchers, delete, get, patch, post, put, routes, tokio};
+use rocket::tokio::io::{AsyncRead, AsyncReadExt, ReadBuf};
 use rocket::State;
 
 use chrono::DateTime;
-use clap::Parser;
+use clap::{ArgAction, Parser};
 use handlebars::Handlebars;
 use humantime::parse_duration;
 use nanoid::nanoid;
 use regex::Regex;
-use rocksdb::{Options, DB};
 use serde_json::json;
 
 mod formatter;
 
 #[macro_use]
 mod lib;
-use lib::{compaction_filter_expired_entries, get_entry_data, get_extension, new_entry, sanitize_lang};
+use lib::{
+    compress, delete_entry, get_entry, get_entry_data, get_extension, has_counted_reads, hash_token, insert_body,
+    is_burn, is_expired, is_owned_by, is_owner, is_previewer, list_revisions, make_room, new_entry, new_revision,
+    open_secondary, open_store, peek_entry_data, sanitize_lang, save_revision, ChunkWriter, Codec, Compression,
+    EntryOptions, Paste, StoredBody, CHUNK_SIZE,
+};
+use lib::crypto::Keyring;
+use lib::store::{StorageKind, Store};
+
+mod admin;
+use admin::AdminToken;
+
+mod api_keys;
+use api_keys::{ApiCaller, ApiKey, ApiKeys};
+
+mod commands;
+use commands::Command;
+
+mod auth;
+use auth::{Identity, JwtVerifier, ProtectedRoute};
+
+mod proxy;
+use proxy::{forwarded_host, parse_cidr, ClientIp, ForwardedHeaders, TrustedProxies};
+
+mod rate_limit;
+use rate_limit::{RateLimiter, ReadLimit, RetryAfter, WriteLimit};
+
+mod sweeper;
+
+mod backup;
+
+mod secondary;
 
 mod plugins;
 use plugins::plugin::{Plugin, PluginManager};
@@ -34,6 +67,14 @@ use api_generated::api::root_as_entry;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
+/// Header carrying the owner token, both when minted by `create` and when presented back.
+const TOKEN_HEADER: &str = "X-Paste-Token";
+/// Response header of `create` with the creator's preview URL of a "burn" paste.
+const PREVIEW_HEADER: &str = "X-Paste-Preview";
+
+/// Header carrying the revision number written by an edit.
+const REVISION_HEADER: &str = "X-Paste-Revision";
+
 /// Newtype wrapper so we can return `rocket::Response` from route handlers.
 struct CustomResponse<'r>(Response<'r>);
 
@@ -43,6 +84,21 @@ impl<'r> Responder<'r, 'r> for CustomResponse<'r> {
     }
 }
 
+/// Command line: the server options, or one of the offline maintenance commands.
+#[derive(Parser, Debug)]
+#[command(
+    name = "pastebin",
+    about = "Simple, standalone and fast pastebin service.",
+    args_conflicts_with_subcommands = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    serve: PastebinConfig,
+}
+
 #[derive(Parser, Debug)]
 #[command(name = "pastebin", about = "Simple, standalone and fast pastebin service.")]
 struct PastebinConfig {
@@ -67,6 +123,9 @@ struct PastebinConfig {
     #[arg(long = "db", help = "Database file path", default_value = "./pastebin.db")]
     db_path: String,
 
+    #[arg(long = "storage", help = "Storage backend", value_enum, default_value_t = StorageKind::Rocksdb)]
+    storage: StorageKind,
+
     #[arg(long = "tls-certs", help = "Path to certificate chain in PEM format")]
     tls_certs: Option<String>,
 
@@ -109,9 +168,196 @@ struct PastebinConfig {
         default_values = &["prism"],
     )]
     plugins: Vec<String>,
+
+    #[arg(long = "api-keys", help = "Path to a JSON file with API keys and their quotas")]
+    api_keys: Option<String>,
+
+    #[arg(
+        long = "anonymous-create",
+        help = "Allow creating pastes without an API key or login",
+        default_value_t = true,
+        action = ArgAction::Set
+    )]
+    anonymous_create: bool,
+
+    #[arg(long = "jwt-jwks", help = "Path to a JWKS file with the keys used to verify bearer JWTs")]
+    jwt_jwks: Option<String>,
+
+    #[arg(long = "jwt-pem", help = "Path to a PEM public key used to verify bearer JWTs (repeatable)")]
+    jwt_pem: Vec<String>,
+
+    #[arg(long = "jwt-issuer", help = "Required `iss` claim of bearer JWTs")]
+    jwt_issuer: Option<String>,
+
+    #[arg(long = "jwt-audience", help = "Required `aud` claim of bearer JWTs")]
+    jwt_audience: Option<String>,
+
+    #[arg(
+        long = "auth-required",
+        help = "Routes that require a bearer JWT login",
+        value_enum,
+        value_delimiter = ','
+    )]
+    auth_required: Vec<ProtectedRoute>,
+
+    #[arg(
+        long = "trusted-proxies",
+        help = "Comma separated CIDR ranges of the reverse proxies allowed to set forwarding and identity headers \
+                (forwarding headers only: 127.0.0.0/8,::1 when left out)",
+        value_parser = parse_cidr,
+        value_delimiter = ','
+    )]
+    trusted_proxies: Vec<ipnet::IpNet>,
+
+    #[arg(
+        long = "forwarded-headers",
+        help = "Headers the trusted proxies forward the client host, scheme and address in, the other ones are ignored",
+        value_enum,
+        default_value_t = ForwardedHeaders::XForwarded
+    )]
+    forwarded_headers: ForwardedHeaders,
+
+    #[arg(
+        long = "identity-header",
+        help = "Header carrying the user authenticated by a trusted proxy (ie. X-Remote-User)"
+    )]
+    identity_header: Option<String>,
+
+    #[arg(
+        long = "write-rate-limit",
+        help = "Requests creating, editing or removing pastes allowed per minute and client IP (0 = unlimited)",
+        default_value_t = 0
+    )]
+    write_rate_limit: u32,
+
+    #[arg(
+        long = "read-rate-limit",
+        help = "Requests reading pastes allowed per minute and client IP (0 = unlimited)",
+        default_value_t = 0
+    )]
+    read_rate_limit: u32,
+
+    #[arg(
+        long = "rate-limit-clients",
+        help = "Max number of client IPs tracked by the rate limiter",
+        default_value_t = 100_000
+    )]
+    rate_limit_clients: usize,
+
+    #[arg(long = "admin-token", help = "Token enabling the /admin API, passed in the X-Admin-Token header")]
+    admin_token: Option<String>,
+
+    #[arg(long = "compression", help = "Codec used to store new paste bodies", value_enum, default_value_t = Codec::None)]
+    compression: Codec,
+
+    #[arg(long = "compression-level", help = "Compression level (zstd: 1 to 22)", default_value_t = 3)]
+    compression_level: i32,
+
+    #[arg(
+        long = "compression-threshold",
+        help = "Paste bodies smaller than this many bytes are stored uncompressed",
+        default_value_t = 1024
+    )]
+    compression_threshold: usize,
+
+    #[arg(
+        long = "sweep-interval",
+        help = "Interval between background removals of expired pastes, followed by a compaction (0 = never)",
+        value_parser = parse_duration,
+        default_value = "1h"
+    )]
+    sweep_interval: std::time::Duration,
+
+    #[arg(
+        long = "max-storage",
+        help = "Content size in bytes of all pastes and revisions, beyond which the oldest pastes are evicted (0 = unlimited)",
+        default_value_t = 0
+    )]
+    max_storage: u64,
+
+    #[arg(
+        long = "max-paste-size",
+        help = "Largest paste body accepted, in bytes (API keys may set their own limit)",
+        default_value_t = 8 << 20
+    )]
+    max_paste_size: u64,
+
+    #[arg(
+        long = "encryption-key",
+        value_name = "FILE",
+        help = "Encrypt paste bodies at rest with the hex encoded key of this file; repeat to keep older keys for decryption, the first one encrypts"
+    )]
+    encryption_keys: Vec<String>,
+
+    #[arg(long = "backup-dir", value_name = "DIR", help = "Directory of the backups, see POST /admin/backups")]
+    backup_dir: Option<String>,
+
+    #[arg(
+        long = "backup-interval",
+        help = "Interval between scheduled backups to --backup-dir (0 = never)",
+        value_parser = parse_duration,
+        default_value = "0"
+    )]
+    backup_interval: std::time::Duration,
+
+    #[arg(long = "backup-keep", help = "Number of backups kept in --backup-dir (0 = all)", default_value_t = 7)]
+    backup_keep: usize,
+
+    #[arg(
+        long = "secondary-of",
+        value_name = "PRIMARY_DB",
+        help = "Serve only the reads, from the database of a primary server opened as a RocksDB secondary \
+                (--db is then a directory of the secondary's own)"
+    )]
+    secondary_of: Option<String>,
+
+    #[arg(
+        long = "primary-url",
+        help = "URL of the primary server, where a secondary redirects the requests it cannot serve (503 otherwise)"
+    )]
+    primary_url: Option<String>,
+
+    #[arg(
+        long = "catch-up-interval",
+        help = "Interval between the catch-ups of a secondary with the writes of its primary (0 = never)",
+        value_parser = parse_duration,
+        default_value = "5s"
+    )]
+    catch_up_interval: std::time::Duration,
+}
+
+impl PastebinConfig {
+    fn compression(&self) -> Compression {
+        Compression {
+            codec: self.compression,
+            level: self.compression_level,
+            threshold: self.compression_threshold,
+        }
+    }
+}
+
+/// Whether the client accepts zstd encoded bodies (`Accept-Encoding: zstd`), in which case
+/// compressed pastes are served without decoding them.
+struct AcceptsZstd(bool);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AcceptsZstd {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let accepts = request.headers().get("Accept-Encoding").flat_map(|value| value.split(',')).any(|coding| {
+            let mut params = coding.split(';').map(str::trim);
+            params.next().is_some_and(|name| name.eq_ignore_ascii_case("zstd"))
+                && !params.any(|param| {
+                    param.strip_prefix("q=").and_then(|q| q.trim().parse::<f32>().ok()) == Some(0.0)
+                })
+        });
+        Outcome::Success(AcceptsZstd(accepts))
+    }
 }
 
 /// Carries the effective public host and scheme derived from reverse-proxy headers.
+/// Those headers are only honored on requests coming from `--trusted-proxies`.
 struct RequestHost {
     scheme: String,
     host: String,
@@ -122,16 +368,12 @@ impl<'r> FromRequest<'r> for RequestHost {
     type Error = ();
 
     async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
-        let host = request
-            .headers()
-            .get_one("X-Forwarded-Host")
-            .or_else(|| request.headers().get_one("Host"))
-            .map(|s| s.to_string());
+        let (forwarded_host, scheme) = match request.rocket().state::<TrustedProxies>() {
+            Some(proxies) => forwarded_host(request, proxies),
+            None => (None, None),
+        };
 
-        let scheme = request
-            .headers()
-            .get_one("X-Forwarded-Proto")
-            .map(|s| s.to_string());
+        let host = forwarded_host.or_else(|| request.headers().get_one("Host").map(|s| s.to_string()));
 
         match host {
             Some(host) => Outcome::Success(RequestHost {
@@ -143,6 +385,21 @@ impl<'r> FromRequest<'r> for RequestHost {
     }
 }
 
+/// Owner token presented in the `X-Paste-Token` header.
+struct PasteToken(String);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for PasteToken {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match request.headers().get_one(TOKEN_HEADER) {
+            Some(token) => Outcome::Success(PasteToken(token.to_string())),
+            None => Outcome::Forward(Status::Unauthorized),
+        }
+    }
+}
+
 fn get_url(cfg: &PastebinConfig, req_host: Option<RequestHost>) -> String {
     if let Some(uri) = &cfg.uri {
         return uri.clone();
@@ -159,6 +416,40 @@ fn get_url(cfg: &PastebinConfig, req_host: Option<RequestHost>) -> String {
     format!("{scheme}://{}{port}", cfg.address)
 }
 
+/// Reject anonymous requests to routes listed in `--auth-required`.
+fn require_login(cfg: &PastebinConfig, route: ProtectedRoute, identity: &Identity) -> Result<(), Status> {
+    if identity.0.is_none() && cfg.auth_required.contains(&route) {
+        Err(Status::Unauthorized)
+    } else {
+        Ok(())
+    }
+}
+
+/// Authorize a management request. The logged in user who created the paste is
+/// always allowed, anyone else needs the paste's owner token, taken from the
+/// `X-Paste-Token` header or the `token` query parameter.
+fn check_owner(
+    root: &[u8],
+    identity: &Identity,
+    header_token: Option<PasteToken>,
+    token: Option<&str>,
+) -> Result<(), Status> {
+    if identity.0.as_deref().is_some_and(|subject| is_owned_by(root, subject)) {
+        return Ok(());
+    }
+
+    let token = match header_token {
+        Some(PasteToken(token)) => token,
+        None => token.ok_or(Status::Unauthorized)?.to_string(),
+    };
+
+    if is_owner(root, &token) {
+        Ok(())
+    } else {
+        Err(Status::Forbidden)
+    }
+}
+
 fn get_error_response<'r>(
     handlebars: &Handlebars,
     uri_prefix: String,
@@ -182,175 +473,638 @@ fn get_error_response<'r>(
     )
 }
 
-#[post("/?<lang>&<ttl>&<burn>&<encrypted>", data = "<paste>")]
+#[allow(clippy::too_many_arguments)]
+#[post("/?<lang>&<ttl>&<burn>&<max_views>&<encrypted>", data = "<paste>")]
 async fn create(
+    _limit: WriteLimit,
     req_host: Option<RequestHost>,
+    client_ip: Option<ClientIp>,
+    caller: ApiCaller<'_>,
+    identity: Identity,
     paste: Data<'_>,
-    state: &State<DB>,
+    state: &State<Store>,
     cfg: &State<PastebinConfig>,
     alphabet: &State<Vec<char>>,
     lang: Option<&str>,
     ttl: Option<u64>,
     burn: Option<bool>,
+    max_views: Option<u32>,
     encrypted: Option<bool>,
-) -> Result<String, io::Error> {
+) -> Result<CustomResponse<'static>, Status> {
+    let api_key = caller.0;
+    if api_key.is_none() {
+        require_login(cfg, ProtectedRoute::Create, &identity)?;
+        if identity.0.is_none() && !cfg.anonymous_create {
+            return Err(Status::Unauthorized);
+        }
+    }
+    // "burn" already is a limit of one view
+    if burn.unwrap_or(false) && max_views.unwrap_or(0) > 0 {
+        return Err(Status::BadRequest);
+    }
+
     let slug_len = cfg.slug_len;
     let id = nanoid!(slug_len, alphabet.inner());
+    let token = nanoid!(32);
     let base_url = get_url(cfg, req_host);
     let url = format!("{base_url}/{id}");
 
-    let bytes = paste
-        .open(8.mebibytes())
-        .into_bytes()
-        .await
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?
-        .into_inner();
+    let limit = api_key.and_then(|key| key.max_paste_size).unwrap_or(cfg.max_paste_size);
+    let body = receive_body(paste, limit, &cfg.compression(), state).await?;
+    // held until the paste is stored, or not
+    let quota = api_key.map(|key| key.reserve_quota(body.size, state)).transpose();
+    let _quota = match quota.and_then(|quota| fit_storage(body.size, None, state, cfg).map(|_| quota)) {
+        Ok(quota) => quota,
+        Err(status) => {
+            body.discard(state).ok();
+            return Err(status);
+        }
+    };
 
+    let token_hash = hash_token(&token);
+    let preview = burn.unwrap_or(false).then(|| nanoid!(32));
+    let preview_hash = preview.as_deref().map(hash_token);
     let mut writer: Vec<u8> = vec![];
+    // the content is described by `body`, see `insert_body`
     new_entry(
         &mut writer,
-        &bytes,
-        lang.unwrap_or("markup"),
-        ttl.unwrap_or(cfg.ttl),
-        burn.unwrap_or(false),
-        encrypted.unwrap_or(false),
+        &[],
+        &EntryOptions {
+            lang: lang.unwrap_or("markup"),
+            ttl: ttl.unwrap_or(cfg.ttl),
+            burn: burn.unwrap_or(false),
+            max_views: max_views.unwrap_or(0),
+            encrypted: encrypted.unwrap_or(false),
+            owner_token: Some(&token_hash),
+            preview_token: preview_hash.as_deref(),
+            api_key: api_key.map(|key| key.name.as_str()),
+            owner: identity.0.as_deref(),
+            codec: body.codec,
+        },
     );
 
-    state.put(id, writer).unwrap();
+    insert_body(&id, &writer, &body, state).map_err(|_| Status::InternalServerError)?;
+    if let Some(ClientIp(ip)) = client_ip {
+        rocket::info_!("paste {id} created by {ip}");
+    }
 
-    Ok(url)
+    let mut response = Response::build();
+    response.status(Status::Ok).header(ContentType::Plain).raw_header(TOKEN_HEADER, token);
+    if let Some(preview) = preview {
+        response.raw_header(PREVIEW_HEADER, format!("{url}?preview={preview}"));
+    }
+    Ok(CustomResponse(response.sized_body(url.len(), Cursor::new(url)).finalize()))
 }
 
-#[delete("/<id>")]
-async fn remove(id: &str, state: &State<DB>) -> Status {
-    match state.delete(id) {
-        Ok(_) => Status::Ok,
+#[allow(clippy::too_many_arguments)]
+#[delete("/<id>?<token>")]
+async fn remove(
+    _limit: WriteLimit,
+    id: &str,
+    token: Option<&str>,
+    header_token: Option<PasteToken>,
+    identity: Identity,
+    client_ip: Option<ClientIp>,
+    state: &State<Store>,
+    cfg: &State<PastebinConfig>,
+) -> Status {
+    if let Err(status) = require_login(cfg, ProtectedRoute::Remove, &identity) {
+        return status;
+    }
+
+    let root = match get_entry(id, state) {
+        Ok(Some(root)) => root,
+        Ok(None) => return Status::NotFound,
+        Err(e) => return store_error(e),
+    };
+
+    if let Err(status) = check_owner(&root, &identity, header_token, token) {
+        return status;
+    }
+
+    match delete_entry(id, state) {
+        Ok(_) => {
+            if let Some(ClientIp(ip)) = client_ip {
+                rocket::info_!("paste {id} removed by {ip}");
+            }
+            Status::Ok
+        }
         Err(_) => Status::InternalServerError,
     }
 }
 
-#[allow(clippy::too_many_arguments)]
-#[get("/<id>?<lang>")]
-async fn view_paste<'r>(
-    id: &'r str,
-    lang: Option<&'r str>,
-    state: &'r State<DB>,
-    handlebars: &'r State<Handlebars<'static>>,
-    plugin_manager: &'r State<PluginManager>,
-    ui_expiry_times: &'r State<Vec<(String, u64)>>,
-    ui_expiry_default: &'r State<String>,
-    cfg: &'r State<PastebinConfig>,
-) -> CustomResponse<'r> {
-    let resources = plugin_manager.static_resources();
-    let html = String::from_utf8_lossy(resources.get("/static/index.html").unwrap()).into_owned();
+/// Read a paste body of at most `limit` bytes, or fail with 413. A body fitting in a chunk
+/// is kept in memory, a larger one is streamed to the store chunk by chunk as it arrives.
+async fn receive_body(
+    paste: Data<'_>,
+    limit: u64,
+    compression: &Compression,
+    state: &Store,
+) -> Result<StoredBody<'static>, Status> {
+    // a byte more than the limit tells a body at the limit from a larger one
+    let mut stream = paste.open((limit + 1).bytes());
+    let mut buf = vec![];
+    let head = (&mut stream).take(CHUNK_SIZE as u64 + 1).read_to_end(&mut buf).await;
+    head.map_err(|_| Status::InternalServerError)?;
+    if buf.len() as u64 > limit {
+        return Err(Status::PayloadTooLarge);
+    }
+    if buf.len() <= CHUNK_SIZE {
+        let (stored, codec) = compress(&buf, compression);
+        return Ok(StoredBody::inline(stored.into_owned(), buf.len() as u64, codec, state));
+    }
 
-    let root = match get_entry_data(id, state) {
-        Ok(x) => x,
-        Err(e) => {
-            let status = match e.kind() {
-                io::ErrorKind::NotFound => Status::NotFound,
-                _ => Status::InternalServerError,
-            };
-            let map = json!({
-                "version": VERSION,
-                "is_error": "true",
-                "uri_prefix": cfg.uri_prefix,
-                "js_imports": plugin_manager.js_imports(),
-                "css_imports": plugin_manager.css_imports(),
-                "js_init": plugin_manager.js_init(),
-            });
-            let content = handlebars.render_template(&html, &map).unwrap();
-            return CustomResponse(
-                Response::build()
-                    .status(status)
-                    .header(ContentType::HTML)
-                    .sized_body(content.len(), Cursor::new(content))
-                    .finalize(),
-            );
+    let mut writer = ChunkWriter::new(compression, state).map_err(|_| Status::InternalServerError)?;
+    let mut received = buf.len() as u64;
+    let written = loop {
+        if received > limit {
+            break Err(Status::PayloadTooLarge);
+        }
+        if writer.write_all(&buf).is_err() {
+            break Err(Status::InternalServerError);
+        }
+        buf.resize(CHUNK_SIZE, 0);
+        match stream.read(&mut buf).await {
+            Ok(0) => break Ok(()),
+            Ok(read) => {
+                buf.truncate(read);
+                received += read as u64;
+            }
+            Err(_) => break Err(Status::InternalServerError),
         }
     };
 
-    let entry = root_as_entry(&root).unwrap();
-    let lowercased = lang
-        .unwrap_or_else(|| entry.lang().unwrap_or("markup"))
-        .to_lowercase();
-    let selected_lang = sanitize_lang(&lowercased);
+    match written {
+        Ok(()) => writer.finish().map_err(|_| Status::InternalServerError),
+        Err(status) => {
+            writer.discard().ok();
+            Err(status)
+        }
+    }
+}
 
-    let mut pastebin_cls = Vec::new();
-    if cfg.ui_line_numbers {
-        pastebin_cls.push("line-numbers".to_string());
+/// Evict the oldest pastes (but `keep`) so that `size` more bytes fit within
+/// `--max-storage`, or fail with 507 when they cannot.
+fn fit_storage(size: u64, keep: Option<&str>, state: &Store, cfg: &PastebinConfig) -> Result<(), Status> {
+    if cfg.max_storage == 0 {
+        return Ok(());
     }
-    pastebin_cls.push(format!("language-{selected_lang}"));
+    match make_room(size, cfg.max_storage, keep, state) {
+        Ok(Some(evicted)) => {
+            if !evicted.is_empty() {
+                rocket::info_!("evicted {} pastes to stay within --max-storage", evicted.len());
+            }
+            Ok(())
+        }
+        Ok(None) => Err(Status::InsufficientStorage),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
 
-    let mut map = json!({
-        "is_created": "true",
-        "pastebin_code": String::from_utf8_lossy(entry.data().unwrap().bytes()),
-        "pastebin_id": id,
-        "pastebin_cls": pastebin_cls.join(" "),
-        "version": VERSION,
-        "uri_prefix": cfg.uri_prefix,
-        "ui_expiry_times": ui_expiry_times.inner(),
-        "ui_expiry_default": ui_expiry_default.inner(),
-        "js_imports": plugin_manager.js_imports(),
-        "css_imports": plugin_manager.css_imports(),
-        "js_init": plugin_manager.js_init(),
-    });
+/// Current entry of paste `id`, once the caller is checked to own it: done before the body
+/// of an edit is received, so that only owners get to upload it.
+fn owned_entry(
+    id: &str,
+    token: Option<&str>,
+    header_token: Option<PasteToken>,
+    identity: &Identity,
+    state: &Store,
+    cfg: &PastebinConfig,
+) -> Result<Vec<u8>, Status> {
+    require_login(cfg, ProtectedRoute::Remove, identity)?;
+
+    let current = match get_entry(id, state) {
+        Ok(Some(root)) if !is_expired(&root) => root,
+        Ok(_) => return Err(Status::NotFound),
+        Err(e) => return Err(store_error(e)),
+    };
 
-    if entry.burn() {
-        map["msg"] = json!("FOR YOUR EYES ONLY. The paste is gone, after you close this window.");
-        map["level"] = json!("warning");
-        map["is_burned"] = json!("true");
-        map["glyph"] = json!("fa fa-fire");
-    } else if entry.expiry_timestamp() != 0 {
-        let time = DateTime::from_timestamp(entry.expiry_timestamp() as i64, 0)
-            .unwrap()
-            .naive_utc()
-            .format("%Y-%m-%d %H:%M:%S");
-        map["msg"] = json!(format!("This paste will expire on {time}."));
-        map["level"] = json!("info");
-        map["glyph"] = json!("far fa-clock");
-    }
+    check_owner(&current, identity, header_token, token)?;
+    Ok(current)
+}
 
-    if entry.encrypted() {
-        map["is_encrypted"] = json!("true");
-    }
+/// The API key which created the paste whose metadata is `root`, if it is still configured:
+/// its limits apply to the edits of the paste.
+fn paste_api_key<'k>(root: &[u8], keys: &'k ApiKeys) -> Option<&'k ApiKey> {
+    root_as_entry(root).unwrap().api_key().and_then(|name| keys.named(name))
+}
 
-    let content = handlebars.render_template(&html, &map).unwrap();
+/// Write a new revision of paste `id`, keeping the `current` one (see `owned_entry`) in
+/// the revision history. `data` of `None` keeps the current content.
+#[allow(clippy::too_many_arguments)]
+fn update_paste(
+    id: &str,
+    current: &[u8],
+    api_key: Option<&ApiKey>,
+    data: Option<&StoredBody>,
+    lang: Option<&str>,
+    encrypted: Option<bool>,
+    req_host: Option<RequestHost>,
+    state: &State<Store>,
+    cfg: &State<PastebinConfig>,
+) -> Result<CustomResponse<'static>, Status> {
+    // the previous content is kept as a revision, an unchanged one counts again
+    let size = data.map_or(root_as_entry(current).unwrap().size(), |body| body.size);
+    // held until the revision is stored, or not
+    let _quota = api_key.map(|key| key.reserve_revision(size, state)).transpose()?;
+    fit_storage(size, Some(id), state, cfg)?;
 
-    CustomResponse(
+    let mut writer: Vec<u8> = vec![];
+    new_revision(&mut writer, current, data, lang, encrypted);
+    save_revision(id, current, &writer, data, state).map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => Status::NotFound,
+        io::ErrorKind::ResourceBusy => Status::Conflict,
+        _ => store_error(e),
+    })?;
+
+    let revision = root_as_entry(&writer).unwrap().revision();
+    let url = format!("{}/{id}", get_url(cfg, req_host));
+
+    Ok(CustomResponse(
         Response::build()
             .status(Status::Ok)
-            .header(ContentType::HTML)
-            .sized_body(content.len(), Cursor::new(content))
+            .header(ContentType::Plain)
+            .raw_header(REVISION_HEADER, revision.to_string())
+            .sized_body(url.len(), Cursor::new(url))
             .finalize(),
-    )
+    ))
 }
 
 #[allow(clippy::too_many_arguments)]
-#[get("/new?<id>&<level>&<msg>&<glyph>&<url>")]
-async fn get_new<'r>(
-    state: &'r State<DB>,
+#[put("/<id>?<lang>&<encrypted>&<token>", data = "<paste>")]
+async fn update(
+    _limit: WriteLimit,
+    id: &str,
+    req_host: Option<RequestHost>,
+    header_token: Option<PasteToken>,
+    identity: Identity,
+    paste: Data<'_>,
+    state: &State<Store>,
+    cfg: &State<PastebinConfig>,
+    api_keys: &State<ApiKeys>,
+    lang: Option<&str>,
+    encrypted: Option<bool>,
+    token: Option<&str>,
+) -> Result<CustomResponse<'static>, Status> {
+    let current = owned_entry(id, token, header_token, &identity, state, cfg)?;
+    let api_key = paste_api_key(&current, api_keys);
+    let limit = api_key.and_then(|key| key.max_paste_size).unwrap_or(cfg.max_paste_size);
+    let body = receive_body(paste, limit, &cfg.compression(), state).await?;
+    let updated = update_paste(id, &current, api_key, Some(&body), lang, encrypted, req_host, state, cfg);
+    if updated.is_err() {
+        body.discard(state).ok();
+    }
+    updated
+}
+
+/// Like `update`, but an empty body keeps the current content (ie. to change only `lang`).
+#[allow(clippy::too_many_arguments)]
+#[patch("/<id>?<lang>&<encrypted>&<token>", data = "<paste>")]
+async fn amend(
+    _limit: WriteLimit,
+    id: &str,
+    req_host: Option<RequestHost>,
+    header_token: Option<PasteToken>,
+    identity: Identity,
+    paste: Data<'_>,
+    state: &State<Store>,
+    cfg: &State<PastebinConfig>,
+    api_keys: &State<ApiKeys>,
+    lang: Option<&str>,
+    encrypted: Option<bool>,
+    token: Option<&str>,
+) -> Result<CustomResponse<'static>, Status> {
+    let current = owned_entry(id, token, header_token, &identity, state, cfg)?;
+    let api_key = paste_api_key(&current, api_keys);
+    let limit = api_key.and_then(|key| key.max_paste_size).unwrap_or(cfg.max_paste_size);
+    let body = receive_body(paste, limit, &cfg.compression(), state).await?;
+    let data = (body.size > 0).then_some(&body);
+    let updated = update_paste(id, &current, api_key, data, lang, encrypted, req_host, state, cfg);
+    if updated.is_err() {
+        body.discard(state).ok();
+    }
+    updated
+}
+
+#[allow(clippy::too_many_arguments)]
+#[get("/<id>?<lang>&<preview>")]
+async fn view_paste<'r>(
+    _limit: ReadLimit,
+    id: &'r str,
+    lang: Option<&'r str>,
+    preview: Option<&'r str>,
+    identity: Identity,
+    state: &'r State<Store>,
     handlebars: &'r State<Handlebars<'static>>,
+    plugin_manager: &'r State<PluginManager>,
+    ui_expiry_times: &'r State<Vec<(String, u64)>>,
+    ui_expiry_default: &'r State<String>,
     cfg: &'r State<PastebinConfig>,
+) -> CustomResponse<'r> {
+    let reading = preview.map_or(Reading::Get, Reading::Preview);
+    render_paste(id, None, lang, reading, &identity, state, handlebars, plugin_manager, ui_expiry_times, ui_expiry_default, cfg)
+}
+
+#[allow(clippy::too_many_arguments)]
+#[post("/<id>?<lang>")]
+async fn reveal_paste<'r>(
+    _limit: ReadLimit,
+    id: &'r str,
+    lang: Option<&'r str>,
+    identity: Identity,
+    state: &'r State<Store>,
+    handlebars: &'r State<Handlebars<'static>>,
     plugin_manager: &'r State<PluginManager>,
     ui_expiry_times: &'r State<Vec<(String, u64)>>,
     ui_expiry_default: &'r State<String>,
-    id: Option<&'r str>,
-    level: Option<&'r str>,
-    glyph: Option<&'r str>,
-    msg: Option<&'r str>,
-    url: Option<&'r str>,
+    cfg: &'r State<PastebinConfig>,
 ) -> CustomResponse<'r> {
-    let resources = plugin_manager.static_resources();
-    let html = String::from_utf8_lossy(resources.get("/static/index.html").unwrap()).into_owned();
+    render_paste(id, None, lang, Reading::Reveal, &identity, state, handlebars, plugin_manager, ui_expiry_times, ui_expiry_default, cfg)
+}
 
-    let mut map = json!({
-        "is_editable": "true",
-        "version": VERSION,
-        "msg": msg.unwrap_or_default(),
-        "level": level.unwrap_or("secondary"),
-        "glyph": glyph.unwrap_or_default(),
-        "url": url.unwrap_or_default(),
+#[allow(clippy::too_many_arguments)]
+#[get("/<id>/rev/<rev>?<lang>")]
+async fn view_revision<'r>(
+    _limit: ReadLimit,
+    id: &'r str,
+    rev: u32,
+    lang: Option<&'r str>,
+    identity: Identity,
+    state: &'r State<Store>,
+    handlebars: &'r State<Handlebars<'static>>,
+    plugin_manager: &'r State<PluginManager>,
+    ui_expiry_times: &'r State<Vec<(String, u64)>>,
+    ui_expiry_default: &'r State<String>,
+    cfg: &'r State<PastebinConfig>,
+) -> CustomResponse<'r> {
+    render_paste(id, Some(rev), lang, Reading::Get, &identity, state, handlebars, plugin_manager, ui_expiry_times, ui_expiry_default, cfg)
+}
+
+#[allow(clippy::too_many_arguments)]
+#[post("/<id>/rev/<rev>?<lang>")]
+async fn reveal_revision<'r>(
+    _limit: ReadLimit,
+    id: &'r str,
+    rev: u32,
+    lang: Option<&'r str>,
+    identity: Identity,
+    state: &'r State<Store>,
+    handlebars: &'r State<Handlebars<'static>>,
+    plugin_manager: &'r State<PluginManager>,
+    ui_expiry_times: &'r State<Vec<(String, u64)>>,
+    ui_expiry_default: &'r State<String>,
+    cfg: &'r State<PastebinConfig>,
+) -> CustomResponse<'r> {
+    render_paste(id, Some(rev), lang, Reading::Reveal, &identity, state, handlebars, plugin_manager, ui_expiry_times, ui_expiry_default, cfg)
+}
+
+/// How a paste is requested. Link previewers and mail scanners follow links with GET
+/// requests, so a GET never consumes a "burn" paste: it gets the "click to reveal" page,
+/// whose button sends the same request as a POST.
+#[derive(Clone, Copy)]
+enum Reading<'a> {
+    Get,
+    /// The POST of the "click to reveal" page.
+    Reveal,
+    /// The creator's preview of a "burn" paste, with the preview token.
+    Preview(&'a str),
+}
+
+/// Status of a failed read or write of the store, logged since it points at a broken
+/// record (see `verify`) or disk rather than at the request.
+fn store_error(e: io::Error) -> Status {
+    rocket::error_!("store error: {e}");
+    Status::InternalServerError
+}
+
+/// Status of a failed read of a paste: missing, or a `store_error`.
+fn read_status(e: io::Error) -> Status {
+    match e.kind() {
+        io::ErrorKind::NotFound => Status::NotFound,
+        // a "burn" or `max_views` paste on a secondary, see `secondary::redirect`
+        io::ErrorKind::ReadOnlyFilesystem => Status::ServiceUnavailable,
+        _ => store_error(e),
+    }
+}
+
+/// Read a paste as requested, `None` when the "click to reveal" page is due instead.
+fn read_paste(id: &str, rev: Option<u32>, reading: Reading, state: &Store) -> Result<Option<Paste>, Status> {
+    let root = || get_entry(id, state).map_err(read_status)?.filter(|root| !is_expired(root)).ok_or(Status::NotFound);
+
+    match reading {
+        Reading::Get if is_burn(&root()?) => Ok(None),
+        Reading::Preview(token) if !is_previewer(&root()?, token) => Err(Status::NotFound),
+        Reading::Preview(_) => peek_entry_data(id, rev, state).map(Some).map_err(read_status),
+        _ => get_entry_data(id, rev, state).map(Some).map_err(read_status),
+    }
+}
+
+/// The "click to reveal" page of a "burn" paste.
+fn reveal_page(handlebars: &Handlebars<'static>, plugin_manager: &PluginManager, cfg: &PastebinConfig) -> CustomResponse<'static> {
+    let resources = plugin_manager.static_resources();
+    let html = String::from_utf8_lossy(resources.get("/static/index.html").unwrap()).into_owned();
+    let map = json!({
+        "version": VERSION,
+        "is_reveal": "true",
+        "uri_prefix": cfg.uri_prefix,
+        "js_imports": plugin_manager.js_imports(),
+        "css_imports": plugin_manager.css_imports(),
+        "js_init": plugin_manager.js_init(),
+    });
+    let content = handlebars.render_template(&html, &map).unwrap();
+    CustomResponse(
+        Response::build()
+            .status(Status::Ok)
+            .header(ContentType::HTML)
+            .raw_header("Cache-Control", "no-store")
+            .sized_body(content.len(), Cursor::new(content))
+            .finalize(),
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn render_paste<'r>(
+    id: &'r str,
+    rev: Option<u32>,
+    lang: Option<&'r str>,
+    reading: Reading<'r>,
+    identity: &Identity,
+    state: &'r State<Store>,
+    handlebars: &'r State<Handlebars<'static>>,
+    plugin_manager: &'r State<PluginManager>,
+    ui_expiry_times: &'r State<Vec<(String, u64)>>,
+    ui_expiry_default: &'r State<String>,
+    cfg: &'r State<PastebinConfig>,
+) -> CustomResponse<'r> {
+    let resources = plugin_manager.static_resources();
+    let html = String::from_utf8_lossy(resources.get("/static/index.html").unwrap()).into_owned();
+
+    let error_page = |status: Status| {
+        let map = json!({
+            "version": VERSION,
+            "is_error": "true",
+            "uri_prefix": cfg.uri_prefix,
+            "js_imports": plugin_manager.js_imports(),
+            "css_imports": plugin_manager.css_imports(),
+            "js_init": plugin_manager.js_init(),
+        });
+        let content = handlebars.render_template(&html, &map).unwrap();
+        CustomResponse(
+            Response::build()
+                .status(status)
+                .header(ContentType::HTML)
+                .sized_body(content.len(), Cursor::new(content))
+                .finalize(),
+        )
+    };
+
+    // check the login first, so that an anonymous request does not burn the paste
+    let paste = require_login(cfg, ProtectedRoute::View, identity).and_then(|_| read_paste(id, rev, reading, state));
+    let paste = match paste {
+        Ok(Some(x)) => x,
+        Ok(None) => return reveal_page(handlebars, plugin_manager, cfg),
+        Err(status) => return error_page(status),
+    };
+    let content = match paste.content() {
+        Ok(content) => content,
+        Err(_) => return error_page(Status::InternalServerError),
+    };
+
+    let entry = paste.entry();
+    let lowercased = lang
+        .unwrap_or_else(|| entry.lang().unwrap_or("markup"))
+        .to_lowercase();
+    let selected_lang = sanitize_lang(&lowercased);
+
+    let mut pastebin_cls = Vec::new();
+    if cfg.ui_line_numbers {
+        pastebin_cls.push("line-numbers".to_string());
+    }
+    pastebin_cls.push(format!("language-{selected_lang}"));
+
+    let mut map = json!({
+        "is_created": "true",
+        "pastebin_code": String::from_utf8_lossy(&content),
+        "pastebin_id": id,
+        "pastebin_cls": pastebin_cls.join(" "),
+        "version": VERSION,
+        "uri_prefix": cfg.uri_prefix,
+        "ui_expiry_times": ui_expiry_times.inner(),
+        "ui_expiry_default": ui_expiry_default.inner(),
+        "js_imports": plugin_manager.js_imports(),
+        "css_imports": plugin_manager.css_imports(),
+        "js_init": plugin_manager.js_init(),
+    });
+
+    if let Reading::Preview(_) = reading {
+        map["msg"] = json!("PREVIEW. The paste is deleted when it is read, this page does not count.");
+        map["level"] = json!("info");
+        map["is_burned"] = json!("true");
+        map["glyph"] = json!("fa fa-fire");
+    } else if entry.burn() {
+        map["msg"] = json!("FOR YOUR EYES ONLY. The paste is gone, after you close this window.");
+        map["level"] = json!("warning");
+        map["is_burned"] = json!("true");
+        map["glyph"] = json!("fa fa-fire");
+    } else if paste.views_left == Some(0) {
+        map["msg"] = json!("FOR YOUR EYES ONLY. This was the last view, the paste is gone after you close this window.");
+        map["level"] = json!("warning");
+        map["is_burned"] = json!("true");
+        map["glyph"] = json!("fa fa-fire");
+    } else if let Some(views) = paste.views_left {
+        let times = if views == 1 { "time" } else { "times" };
+        map["msg"] = json!(format!("This paste can be viewed {views} more {times}."));
+        map["level"] = json!("warning");
+        map["glyph"] = json!("far fa-eye");
+    } else if entry.expiry_timestamp() != 0 {
+        let time = DateTime::from_timestamp(entry.expiry_timestamp() as i64, 0)
+            .unwrap()
+            .naive_utc()
+            .format("%Y-%m-%d %H:%M:%S");
+        map["msg"] = json!(format!("This paste will expire on {time}."));
+        map["level"] = json!("info");
+        map["glyph"] = json!("far fa-clock");
+    }
+
+    if entry.encrypted() {
+        map["is_encrypted"] = json!("true");
+    }
+
+    // the current entry is gone after a "burn", so there is no history to show
+    if let Ok(Some(current)) = get_entry(id, state) {
+        let revisions = match list_revisions(id, &current, state) {
+            Ok(revisions) => revisions,
+            Err(e) => return error_page(store_error(e)),
+        };
+        let latest = revisions.last().map(|(n, _)| *n).unwrap_or_default();
+
+        if revisions.len() > 1 {
+            map["revisions"] = json!(revisions
+                .iter()
+                .rev()
+                .map(|(n, timestamp)| {
+                    let time = DateTime::from_timestamp(*timestamp as i64, 0)
+                        .unwrap()
+                        .naive_utc()
+                        .format("%Y-%m-%d %H:%M:%S");
+                    let path = if *n == latest { id.to_string() } else { format!("{id}/rev/{n}") };
+                    json!({"n": n, "time": time.to_string(), "path": path})
+                })
+                .collect::<Vec<_>>());
+        }
+
+        if entry.revision() != latest {
+            map["msg"] = json!(format!(
+                "You are viewing revision {} of this paste. The latest version is available at",
+                entry.revision()
+            ));
+            map["url"] = json!(format!("{}/{id}", cfg.uri_prefix));
+            map["level"] = json!("secondary");
+            map["glyph"] = json!("fas fa-history");
+        }
+    }
+
+    let content = handlebars.render_template(&html, &map).unwrap();
+
+    CustomResponse(
+        Response::build()
+            .status(Status::Ok)
+            .header(ContentType::HTML)
+            .sized_body(content.len(), Cursor::new(content))
+            .finalize(),
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+#[get("/new?<id>&<edit>&<level>&<msg>&<glyph>&<url>&<preview>")]
+async fn get_new<'r>(
+    _limit: ReadLimit,
+    state: &'r State<Store>,
+    handlebars: &'r State<Handlebars<'static>>,
+    cfg: &'r State<PastebinConfig>,
+    plugin_manager: &'r State<PluginManager>,
+    ui_expiry_times: &'r State<Vec<(String, u64)>>,
+    ui_expiry_default: &'r State<String>,
+    identity: Identity,
+    id: Option<&'r str>,
+    edit: Option<bool>,
+    level: Option<&'r str>,
+    glyph: Option<&'r str>,
+    msg: Option<&'r str>,
+    url: Option<&'r str>,
+    preview: Option<&'r str>,
+) -> CustomResponse<'r> {
+    let resources = plugin_manager.static_resources();
+    let html = String::from_utf8_lossy(resources.get("/static/index.html").unwrap()).into_owned();
+
+    let mut map = json!({
+        "is_editable": "true",
+        "version": VERSION,
+        "msg": msg.unwrap_or_default(),
+        "level": level.unwrap_or("secondary"),
+        "glyph": glyph.unwrap_or_default(),
+        "url": url.unwrap_or_default(),
+        "preview": preview.unwrap_or_default(),
         "uri_prefix": cfg.uri_prefix,
         "ui_expiry_times": ui_expiry_times.inner(),
         "ui_expiry_default": ui_expiry_default.inner(),
@@ -360,25 +1114,46 @@ async fn get_new<'r>(
     });
 
     if let Some(id) = id {
-        let root = match get_entry_data(id, state) {
+        // the editor is not a reader of "burn" and `max_views` pastes, opening it must not
+        // consume them, so these are not cloned
+        let paste = require_login(cfg, ProtectedRoute::View, &identity).and_then(|_| {
+            match get_entry(id, state).map_err(read_status)? {
+                Some(root) if has_counted_reads(&root) => Err(Status::Forbidden),
+                _ => read_paste(id, None, Reading::Get, state)?.ok_or(Status::Forbidden),
+            }
+        });
+        let paste = match paste {
             Ok(r) => r,
+            Err(status) => {
+                return CustomResponse(
+                    Response::build()
+                        .status(status)
+                        .header(ContentType::HTML)
+                        .sized_body(0, Cursor::new(""))
+                        .finalize(),
+                );
+            }
+        };
+        if paste.entry().encrypted() {
+            map["is_encrypted"] = json!("true");
+        }
+        let content = match paste.content() {
+            Ok(content) => content,
             Err(_) => {
                 return CustomResponse(
                     Response::build()
-                        .status(Status::NotFound)
+                        .status(Status::InternalServerError)
                         .header(ContentType::HTML)
                         .sized_body(0, Cursor::new(""))
                         .finalize(),
                 );
             }
         };
-        let entry = root_as_entry(&root).unwrap();
+        map["pastebin_code"] = json!(String::from_utf8_lossy(&content));
 
-        if entry.encrypted() {
-            map["is_encrypted"] = json!("true");
+        if edit.unwrap_or(false) {
+            map["edit_id"] = json!(id);
         }
-        map["pastebin_code"] =
-            json!(String::from_utf8_lossy(entry.data().unwrap().bytes()));
     }
 
     let content = handlebars.render_template(&html, &map).unwrap();
@@ -392,39 +1167,208 @@ async fn get_new<'r>(
     )
 }
 
+#[allow(clippy::too_many_arguments)]
 #[get("/raw/<id>")]
-async fn get_raw(id: &str, state: &State<DB>) -> CustomResponse<'static> {
-    let root = match get_entry_data(id, state) {
-        Ok(x) => x,
-        Err(e) => {
-            let status = match e.kind() {
-                io::ErrorKind::NotFound => Status::NotFound,
-                _ => Status::InternalServerError,
-            };
-            return CustomResponse(Response::build().status(status).finalize());
+async fn get_raw(
+    _limit: ReadLimit,
+    id: &str,
+    identity: Identity,
+    accepts_zstd: AcceptsZstd,
+    state: &State<Store>,
+    handlebars: &State<Handlebars<'static>>,
+    plugin_manager: &State<PluginManager>,
+    cfg: &State<PastebinConfig>,
+) -> CustomResponse<'static> {
+    raw_response(id, Reading::Get, ContentType::Plain, &identity, accepts_zstd, state, handlebars, plugin_manager, cfg)
+}
+
+#[allow(clippy::too_many_arguments)]
+#[post("/raw/<id>")]
+async fn reveal_raw(
+    _limit: ReadLimit,
+    id: &str,
+    identity: Identity,
+    accepts_zstd: AcceptsZstd,
+    state: &State<Store>,
+    handlebars: &State<Handlebars<'static>>,
+    plugin_manager: &State<PluginManager>,
+    cfg: &State<PastebinConfig>,
+) -> CustomResponse<'static> {
+    raw_response(id, Reading::Reveal, ContentType::Plain, &identity, accepts_zstd, state, handlebars, plugin_manager, cfg)
+}
+
+#[allow(clippy::too_many_arguments)]
+#[get("/download/<id>")]
+async fn get_binary(
+    _limit: ReadLimit,
+    id: &str,
+    identity: Identity,
+    accepts_zstd: AcceptsZstd,
+    state: &State<Store>,
+    handlebars: &State<Handlebars<'static>>,
+    plugin_manager: &State<PluginManager>,
+    cfg: &State<PastebinConfig>,
+) -> CustomResponse<'static> {
+    raw_response(id, Reading::Get, ContentType::Binary, &identity, accepts_zstd, state, handlebars, plugin_manager, cfg)
+}
+
+#[allow(clippy::too_many_arguments)]
+#[post("/download/<id>")]
+async fn reveal_binary(
+    _limit: ReadLimit,
+    id: &str,
+    identity: Identity,
+    accepts_zstd: AcceptsZstd,
+    state: &State<Store>,
+    handlebars: &State<Handlebars<'static>>,
+    plugin_manager: &State<PluginManager>,
+    cfg: &State<PastebinConfig>,
+) -> CustomResponse<'static> {
+    raw_response(id, Reading::Reveal, ContentType::Binary, &identity, accepts_zstd, state, handlebars, plugin_manager, cfg)
+}
+
+/// The content of a paste alone, served as `content_type`.
+#[allow(clippy::too_many_arguments)]
+fn raw_response(
+    id: &str,
+    reading: Reading,
+    content_type: ContentType,
+    identity: &Identity,
+    accepts_zstd: AcceptsZstd,
+    state: &Store,
+    handlebars: &Handlebars<'static>,
+    plugin_manager: &PluginManager,
+    cfg: &PastebinConfig,
+) -> CustomResponse<'static> {
+    if let Err(status) = require_login(cfg, ProtectedRoute::View, identity) {
+        return CustomResponse(Response::build().status(status).finalize());
+    }
+
+    let paste = match read_paste(id, None, reading, state) {
+        Ok(Some(paste)) => paste,
+        Ok(None) => return reveal_page(handlebars, plugin_manager, cfg),
+        Err(status) => return CustomResponse(Response::build().status(status).finalize()),
+    };
+
+    let mut response = Response::build();
+    response.status(Status::Ok).header(content_type).raw_header("Vary", "Accept-Encoding");
+    if paste.is_chunked() {
+        let reader = match paste.codec() {
+            Ok(Codec::Zstd) if accepts_zstd.0 => {
+                response.raw_header("Content-Encoding", "zstd");
+                Ok(paste.stored_reader())
+            }
+            _ => paste.content_reader(),
+        };
+        return match reader {
+            Ok(reader) => CustomResponse(response.streamed_body(StoreReader::new(reader)).finalize()),
+            Err(_) => CustomResponse(Response::build().status(Status::InternalServerError).finalize()),
+        };
+    }
+    let data = match paste.codec() {
+        Ok(Codec::Zstd) if accepts_zstd.0 => {
+            response.raw_header("Content-Encoding", "zstd");
+            paste.data
         }
+        Ok(Codec::None) => paste.data,
+        _ => match paste.content() {
+            Ok(content) => content.into_owned(),
+            Err(_) => return CustomResponse(Response::build().status(Status::InternalServerError).finalize()),
+        },
     };
 
-    let entry = root_as_entry(&root).unwrap();
-    let data = entry.data().unwrap().bytes().to_vec();
+    CustomResponse(response.sized_body(data.len(), Cursor::new(data)).finalize())
+}
 
-    CustomResponse(
-        Response::build()
-            .status(Status::Ok)
-            .header(ContentType::Plain)
-            .sized_body(data.len(), Cursor::new(data))
-            .finalize(),
-    )
+/// A paste read from the store a chunk at a time, as a response body. The chunks are
+/// fetched (and decrypted or decompressed) by a blocking task, off the async executor,
+/// which stays one chunk ahead of the client.
+pub struct StoreReader {
+    chunks: tokio::sync::mpsc::Receiver<io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    pos: usize,
 }
 
-#[get("/download/<id>")]
-async fn get_binary(id: &str, state: &State<DB>) -> CustomResponse<'static> {
-    let inner = get_raw(id, state).await;
-    CustomResponse(
-        Response::build_from(inner.0)
-            .header(ContentType::Binary)
-            .finalize(),
-    )
+impl StoreReader {
+    fn new(mut reader: Box<dyn Read + Send>) -> Self {
+        let (sender, chunks) = tokio::sync::mpsc::channel(1);
+        tokio::task::spawn_blocking(move || {
+            let mut buf = vec![0; CHUNK_SIZE];
+            loop {
+                let chunk = match reader.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(read) => Ok(buf[..read].to_vec()),
+                    Err(e) => Err(e),
+                };
+                let failed = chunk.is_err();
+                // an error ends the body, as does a client gone away
+                if sender.blocking_send(chunk).is_err() || failed {
+                    break;
+                }
+            }
+        });
+        StoreReader { chunks, chunk: vec![], pos: 0 }
+    }
+
+    /// The output of `write`, run by a blocking task (ie. an export), sent a chunk at a time
+    /// as it is written. A failure past the first chunk can only cut the response short.
+    pub fn writing(write: impl FnOnce(&mut dyn Write) -> io::Result<()> + Send + 'static) -> Self {
+        let (sender, chunks) = tokio::sync::mpsc::channel(1);
+        tokio::task::spawn_blocking(move || {
+            let mut writer = ChunkSender { sender, chunk: vec![] };
+            if let Err(e) = write(&mut writer).and_then(|_| writer.flush()) {
+                if e.kind() != io::ErrorKind::BrokenPipe {
+                    rocket::error!("writing a response failed: {e}");
+                    writer.sender.blocking_send(Err(e)).ok();
+                }
+            }
+        });
+        StoreReader { chunks, chunk: vec![], pos: 0 }
+    }
+}
+
+/// Writer of `StoreReader::writing`, handing the chunks over to the response.
+struct ChunkSender {
+    sender: tokio::sync::mpsc::Sender<io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+}
+
+impl Write for ChunkSender {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.chunk.extend_from_slice(data);
+        if self.chunk.len() >= CHUNK_SIZE {
+            self.flush()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.chunk.is_empty() {
+            return Ok(());
+        }
+        let chunk = std::mem::take(&mut self.chunk);
+        // the client went away
+        self.sender.blocking_send(Ok(chunk)).map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))
+    }
+}
+
+impl AsyncRead for StoreReader {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        while this.pos == this.chunk.len() {
+            match ready!(this.chunks.poll_recv(cx)) {
+                Some(chunk) => {
+                    this.chunk = chunk?;
+                    this.pos = 0;
+                }
+                None => return Poll::Ready(Ok(())),
+            }
+        }
+        let read = buf.remaining().min(this.chunk.len() - this.pos);
+        buf.put_slice(&this.chunk[this.pos..this.pos + read]);
+        this.pos += read;
+        Poll::Ready(Ok(()))
+    }
 }
 
 #[get("/static/<resource>")]
@@ -458,6 +1402,16 @@ async fn get_static<'r>(
     )
 }
 
+#[catch(429)]
+fn too_many_requests(request: &Request<'_>) -> CustomResponse<'static> {
+    let mut response = Response::build();
+    response.status(Status::TooManyRequests);
+    if let RetryAfter(Some(seconds)) = request.local_cache(|| RetryAfter(None)) {
+        response.raw_header("Retry-After", seconds.to_string());
+    }
+    CustomResponse(response.finalize())
+}
+
 #[get("/")]
 fn index(cfg: &State<PastebinConfig>) -> rocket::response::Redirect {
     rocket::response::Redirect::to(format!("{}/new", cfg.uri_prefix))
@@ -482,6 +1436,8 @@ fn rocket_instance(pastebin_config: PastebinConfig) -> rocket::Rocket<rocket::Bu
         workers,
         keep_alive: pastebin_config.keep_alive,
         log_level: pastebin_config.log,
+        // the client address is resolved through the trusted proxies instead
+        ip_header: None,
         ..Config::default()
     };
 
@@ -490,11 +1446,60 @@ fn rocket_instance(pastebin_config: PastebinConfig) -> rocket::Rocket<rocket::Bu
             Some(rocket::config::TlsConfig::from_paths(certs, key));
     }
 
-    // Setup DB — options must be created before opening so the compaction filter is applied.
-    let mut db_opts = Options::default();
-    db_opts.create_if_missing(true);
-    db_opts.set_compaction_filter("ttl_entries", compaction_filter_expired_entries);
-    let db = DB::open(&db_opts, &pastebin_config.db_path).unwrap();
+    // a primary also upgrades the records of an older schema, or refuses a newer one
+    let db = match &pastebin_config.secondary_of {
+        Some(_) if pastebin_config.storage != StorageKind::Rocksdb => {
+            panic!("--secondary-of needs the rocksdb storage backend")
+        }
+        Some(primary) if *primary == pastebin_config.db_path => panic!("--secondary-of needs a --db of its own"),
+        Some(primary) => open_secondary(primary, &pastebin_config.db_path)
+            .unwrap_or_else(|e| panic!("cannot open {primary} as a secondary: {e}")),
+        None => open_store(pastebin_config.storage, &pastebin_config.db_path)
+            .unwrap_or_else(|e| panic!("cannot open {}: {e}", pastebin_config.db_path)),
+    };
+    let keyring = Keyring::load(&pastebin_config.encryption_keys).unwrap_or_else(|e| panic!("invalid encryption key {e}"));
+    let db = match keyring {
+        Some(keyring) => db.with_keyring(keyring),
+        None => db,
+    };
+
+    let api_keys = match &pastebin_config.api_keys {
+        Some(path) => ApiKeys::load(path).unwrap_or_else(|e| panic!("invalid API keys file {e}")),
+        None => ApiKeys::default(),
+    };
+
+    let jwt_verifier = JwtVerifier::new(
+        pastebin_config.jwt_jwks.as_deref(),
+        &pastebin_config.jwt_pem,
+        pastebin_config.jwt_issuer.clone(),
+        pastebin_config.jwt_audience.clone(),
+    )
+    .unwrap_or_else(|e| panic!("invalid JWT verification key {e}"));
+
+    // the identity header is only taken from proxies listed explicitly, not from any local process
+    if pastebin_config.identity_header.is_some() && pastebin_config.trusted_proxies.is_empty() {
+        panic!("--identity-header needs --trusted-proxies");
+    }
+    // without a list, forwarding headers are taken from a proxy on the same host
+    let proxies = match &pastebin_config.trusted_proxies {
+        proxies if proxies.is_empty() => ["127.0.0.0/8", "::1"].map(|net| parse_cidr(net).unwrap()).to_vec(),
+        proxies => proxies.clone(),
+    };
+    let trusted_proxies = TrustedProxies::new(proxies, pastebin_config.forwarded_headers);
+    if !pastebin_config.auth_required.is_empty()
+        && !jwt_verifier.is_enabled()
+        && pastebin_config.identity_header.is_none()
+    {
+        panic!("--auth-required needs --jwt-jwks, --jwt-pem or --identity-header");
+    }
+
+    let admin_token = AdminToken(pastebin_config.admin_token.as_deref().map(hash_token));
+
+    let rate_limiter = RateLimiter::new(
+        pastebin_config.write_rate_limit,
+        pastebin_config.read_rate_limit,
+        pastebin_config.rate_limit_clients,
+    );
 
     // Build the URL slug alphabet from the configured charset regex.
     let alphabet = {
@@ -548,24 +1553,82 @@ fn rocket_instance(pastebin_config: PastebinConfig) -> rocket::Rocket<rocket::Bu
 
     let plugin_manager = plugins::new(plugins);
     let uri_prefix = pastebin_config.uri_prefix.clone();
+    let base = if uri_prefix.is_empty() { "/".to_string() } else { uri_prefix.clone() };
+    let sweep_interval = pastebin_config.sweep_interval;
+    let backups = backup::fairing(
+        pastebin_config.backup_dir.clone(),
+        pastebin_config.backup_interval,
+        pastebin_config.backup_keep,
+    );
+    let secondary = pastebin_config.secondary_of.is_some();
+    let catch_up = secondary::fairing(pastebin_config.catch_up_interval);
+    let primary_redirects = secondary::redirect(pastebin_config.primary_url.clone());
 
-    rocket::custom(rocket_config)
+    let rocket = rocket::custom(rocket_config)
         .manage(pastebin_config)
         .manage(db)
+        .manage(api_keys)
+        .manage(jwt_verifier)
+        .manage(trusted_proxies)
+        .manage(rate_limiter)
+        .manage(admin_token)
         .manage(formatter::new())
         .manage(plugin_manager)
         .manage(alphabet)
         .manage(ui_expiry_times)
         .manage(ui_expiry_default)
+        .register(&base, catchers![too_many_requests]);
+
+    // a secondary serves the reads it can, and leaves the rest (and the upkeep) to the primary
+    if secondary {
+        return rocket
+            .attach(catch_up)
+            .attach(primary_redirects)
+            .mount(&base, routes![view_paste, view_revision, get_raw, get_binary, get_static])
+            .mount(&base, secondary::routes());
+    }
+    rocket
+        .attach(sweeper::fairing(sweep_interval))
+        .attach(backups)
         .mount(
-            if uri_prefix.is_empty() { "/" } else { &uri_prefix },
-            routes![index, create, remove, view_paste, get_new, get_raw, get_binary, get_static],
+            &base,
+            routes![
+                index,
+                create,
+                remove,
+                update,
+                amend,
+                view_paste,
+                reveal_paste,
+                view_revision,
+                reveal_revision,
+                get_new,
+                get_raw,
+                reveal_raw,
+                get_binary,
+                reveal_binary,
+                get_static
+            ],
         )
+        .mount(format!("{uri_prefix}/admin"), admin::routes())
 }
 
 #[rocket::main]
 async fn main() {
-    rocket_instance(PastebinConfig::parse())
+    let cli = Cli::parse();
+    let config = match cli.command {
+        None => cli.serve,
+        Some(Command::Serve(config)) => config,
+        Some(command) => {
+            if let Err(e) = commands::run(command, &mut io::stdout()) {
+                eprintln!("error: {e}");
+                std::process::exit(1);
+            }
+            return;
+        }
+    };
+
+    rocket_instance(config)
         .launch()
         .await
         .expect("rocket failed to launch");
@@ -574,23 +1637,59 @@ async fn main() {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use clap::ValueEnum;
     use rocket::http::{ContentType, Status};
     use rocket::local::blocking::Client;
     use tempfile::TempDir;
 
-    fn create_client() -> (Client, TempDir) {
-        let tmp_dir = TempDir::new().unwrap();
-        let mut config = PastebinConfig::parse_from(["pastebin"]);
-        config.db_path = tmp_dir.path().join("database").to_str().unwrap().to_string();
-        let client = Client::tracked(rocket_instance(config)).expect("invalid rocket instance");
-        (client, tmp_dir)
+    /// Backend of `create_client`, RocksDB unless `PASTEBIN_TEST_STORAGE` names another one
+    /// (i.e. `PASTEBIN_TEST_STORAGE=fs cargo test`).
+    fn test_storage() -> StorageKind {
+        match std::env::var("PASTEBIN_TEST_STORAGE") {
+            Ok(name) => StorageKind::from_str(&name, true).expect("invalid PASTEBIN_TEST_STORAGE"),
+            Err(_) => StorageKind::Rocksdb,
+        }
+    }
+
+    fn create_client() -> (Client, TempDir) {
+        create_client_with_storage(test_storage())
+    }
+
+    fn create_client_with_storage(storage: StorageKind) -> (Client, TempDir) {
+        create_client_with(&["--storage", storage.to_possible_value().unwrap().get_name()])
+    }
+
+    /// A client of a server run with `args`, on the `test_storage` backend unless they name
+    /// one, its database in a new temporary directory.
+    fn create_client_with(args: &[&str]) -> (Client, TempDir) {
+        create_client_in(TempDir::new().unwrap(), args)
+    }
+
+    /// Like `create_client_with`, in `tmp_dir`: ie. one holding files named by `args`.
+    fn create_client_in(tmp_dir: TempDir, args: &[&str]) -> (Client, TempDir) {
+        let client = Client::tracked(rocket_instance(test_config(&tmp_dir, args))).expect("invalid rocket instance");
+        (client, tmp_dir)
+    }
+
+    fn test_config(tmp_dir: &TempDir, args: &[&str]) -> PastebinConfig {
+        let mut config = PastebinConfig::parse_from([&["pastebin"], args].concat());
+        config.db_path = tmp_dir.path().join("database").to_str().unwrap().to_string();
+        if !args.contains(&"--storage") {
+            config.storage = test_storage();
+        }
+        config
     }
 
     fn insert_paste(client: &Client, data: &str, path: &str) -> String {
+        insert_paste_with_token(client, data, path).0
+    }
+
+    fn insert_paste_with_token(client: &Client, data: &str, path: &str) -> (String, String) {
         let response = client.post(path).body(data).dispatch();
         assert_eq!(response.status(), Status::Ok);
+        let token = response.headers().get_one(TOKEN_HEADER).unwrap().to_string();
         let url = response.into_string().unwrap();
-        url.split('/').next_back().unwrap().to_string()
+        (url.split('/').next_back().unwrap().to_string(), token)
     }
 
     fn get_paste<'c>(client: &'c Client, path: &str) -> rocket::local::blocking::LocalResponse<'c> {
@@ -607,22 +1706,169 @@ mod tests {
         assert!(response.into_string().unwrap().contains("random_test_data_to_be_checked"));
     }
 
+    #[test]
+    fn corrupt_record_is_an_error_response() {
+        let (client, _tmp) = create_client();
+        let (id, token) = insert_paste_with_token(&client, "soon to be damaged", "/");
+        let store = client.rocket().state::<Store>().unwrap();
+        store.put(lib::store::DEFAULT_CF, id.as_bytes(), b"not a flatbuffer").unwrap();
+
+        assert_eq!(get_paste(&client, &id).status(), Status::InternalServerError);
+        assert_eq!(get_paste(&client, &format!("raw/{id}")).status(), Status::InternalServerError);
+        assert_eq!(edit_paste(&client, &id, &token, "edited").status(), Status::InternalServerError);
+        // the server keeps serving the other pastes
+        let other = insert_paste(&client, "still fine", "/");
+        assert_eq!(get_paste(&client, &format!("raw/{other}")).into_string().unwrap(), "still fine");
+    }
+
+    #[test]
+    fn every_storage_backend_serves_pastes() {
+        for storage in StorageKind::value_variants() {
+            let (client, _tmp) = create_client_with_storage(*storage);
+            let (id, token) = insert_paste_with_token(&client, "first_revision_data", "/");
+            assert_eq!(edit_paste(&client, &id, &token, "second_revision_data").status(), Status::Ok);
+            assert_eq!(client.get(format!("/raw/{id}")).dispatch().into_string().unwrap(), "second_revision_data");
+            assert!(get_paste(&client, &format!("{id}/rev/0")).into_string().unwrap().contains("first_revision_data"));
+
+            let burn = insert_paste(&client, "burn_data", "/?burn=true");
+            assert_eq!(client.post(format!("/{burn}")).dispatch().status(), Status::Ok);
+            assert_eq!(client.post(format!("/{burn}")).dispatch().status(), Status::NotFound, "{storage:?}");
+
+            let response = client
+                .delete(format!("/{id}"))
+                .header(rocket::http::Header::new(TOKEN_HEADER, token))
+                .dispatch();
+            assert_eq!(response.status(), Status::Ok);
+            assert_eq!(get_paste(&client, &id).status(), Status::NotFound, "{storage:?}");
+        }
+    }
+
     #[test]
     fn remove_paste_by_id() {
         let (client, _tmp) = create_client();
-        client.delete("/some_id").dispatch();
-        let response = get_paste(&client, "some_id");
-        assert_eq!(response.status(), Status::NotFound);
+        let (id, token) = insert_paste_with_token(&client, "random_test_data_to_be_checked", "/");
+        let response = client
+            .delete(format!("/{id}"))
+            .header(rocket::http::Header::new(TOKEN_HEADER, token))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(get_paste(&client, &id).status(), Status::NotFound);
+    }
+
+    #[test]
+    fn remove_paste_by_id_with_query_token() {
+        let (client, _tmp) = create_client();
+        let (id, token) = insert_paste_with_token(&client, "random_test_data_to_be_checked", "/");
+        let response = client.delete(format!("/{id}?token={token}")).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(get_paste(&client, &id).status(), Status::NotFound);
+    }
+
+    #[test]
+    fn remove_paste_by_id_without_token_is_rejected() {
+        let (client, _tmp) = create_client();
+        let id = insert_paste(&client, "random_test_data_to_be_checked", "/");
+        assert_eq!(client.delete(format!("/{id}")).dispatch().status(), Status::Unauthorized);
+        assert_eq!(get_paste(&client, &id).status(), Status::Ok);
+    }
+
+    #[test]
+    fn remove_paste_by_id_with_wrong_token_is_rejected() {
+        let (client, _tmp) = create_client();
+        let (id, _) = insert_paste_with_token(&client, "random_test_data_to_be_checked", "/");
+        let (_, other_token) = insert_paste_with_token(&client, "another paste", "/");
+        let response = client
+            .delete(format!("/{id}"))
+            .header(rocket::http::Header::new(TOKEN_HEADER, other_token))
+            .dispatch();
+        assert_eq!(response.status(), Status::Forbidden);
+        assert_eq!(get_paste(&client, &id).status(), Status::Ok);
     }
 
     #[test]
     fn remove_nonexistent_paste() {
         let (client, _tmp) = create_client();
         assert_eq!(get_paste(&client, "fake_id").status(), Status::NotFound);
-        client.delete("/fake_id").dispatch();
+        let response = client.delete("/fake_id?token=whatever").dispatch();
+        assert_eq!(response.status(), Status::NotFound);
         assert_eq!(get_paste(&client, "fake_id").status(), Status::NotFound);
     }
 
+    fn edit_paste<'c>(
+        client: &'c Client,
+        id: &str,
+        token: &str,
+        data: &str,
+    ) -> rocket::local::blocking::LocalResponse<'c> {
+        client
+            .put(format!("/{id}"))
+            .header(rocket::http::Header::new(TOKEN_HEADER, token.to_string()))
+            .body(data)
+            .dispatch()
+    }
+
+    #[test]
+    fn edit_paste_keeps_previous_revision() {
+        let (client, _tmp) = create_client();
+        let (id, token) = insert_paste_with_token(&client, "first_revision_data", "/");
+
+        let response = edit_paste(&client, &id, &token, "second_revision_data");
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.headers().get_one(REVISION_HEADER), Some("1"));
+
+        let body = get_paste(&client, &id).into_string().unwrap();
+        assert!(body.contains("second_revision_data"));
+        assert!(body.contains(&format!("{id}/rev/0")));
+
+        let response = get_paste(&client, &format!("{id}/rev/0"));
+        assert_eq!(response.status(), Status::Ok);
+        assert!(response.into_string().unwrap().contains("first_revision_data"));
+
+        assert_eq!(get_paste(&client, &format!("{id}/rev/2")).status(), Status::NotFound);
+    }
+
+    #[test]
+    fn edit_paste_requires_owner_token() {
+        let (client, _tmp) = create_client();
+        let id = insert_paste(&client, "first_revision_data", "/");
+
+        let response = client.put(format!("/{id}")).body("changed").dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+        assert_eq!(edit_paste(&client, &id, "wrong", "changed").status(), Status::Forbidden);
+        assert!(get_paste(&client, &id).into_string().unwrap().contains("first_revision_data"));
+
+        // the owner is checked before the body is received, which would be too large
+        let too_large = "x".repeat(8 * 1024 * 1024 + 1);
+        assert_eq!(edit_paste(&client, &id, "wrong", &too_large).status(), Status::Forbidden);
+        assert_eq!(client.patch(format!("/{id}")).body(&too_large).dispatch().status(), Status::Unauthorized);
+    }
+
+    #[test]
+    fn patch_paste_changes_only_language() {
+        let (client, _tmp) = create_client();
+        let (id, token) = insert_paste_with_token(&client, "fn main() {}", "/?lang=markup");
+
+        let response = client
+            .patch(format!("/{id}?lang=rust&token={token}"))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        let body = get_paste(&client, &id).into_string().unwrap();
+        assert!(body.contains("language-rust"));
+        assert!(body.contains("fn main() {}"));
+    }
+
+    #[test]
+    fn remove_paste_drops_revisions() {
+        let (client, _tmp) = create_client();
+        let (id, token) = insert_paste_with_token(&client, "first_revision_data", "/");
+        edit_paste(&client, &id, &token, "second_revision_data");
+
+        let response = client.delete(format!("/{id}?token={token}")).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(get_paste(&client, &format!("{id}/rev/0")).status(), Status::NotFound);
+    }
+
     #[test]
     fn get_raw_contents() {
         let (client, _tmp) = create_client();
@@ -657,10 +1903,119 @@ mod tests {
     fn burned_paste_not_accessible_after_first_read() {
         let (client, _tmp) = create_client();
         let id = insert_paste(&client, "random_test_data_to_be_checked", "/?burn=true");
-        assert_eq!(get_paste(&client, &id).status(), Status::Ok);
+        let response = client.post(format!("/{id}")).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert!(response.into_string().unwrap().contains("random_test_data_to_be_checked"));
+        assert_eq!(client.post(format!("/{id}")).dispatch().status(), Status::NotFound);
         assert_eq!(get_paste(&client, &id).status(), Status::NotFound);
     }
 
+    #[test]
+    fn burned_paste_is_revealed_by_post_only() {
+        let (client, _tmp) = create_client();
+        let id = insert_paste(&client, "random_test_data_to_be_checked", "/?burn=true");
+
+        // what a link previewer sees, as many times as it likes
+        for path in [id.clone(), format!("raw/{id}"), format!("download/{id}"), format!("{id}/rev/0")] {
+            let response = get_paste(&client, &path);
+            assert_eq!(response.status(), Status::Ok);
+            assert_eq!(response.content_type(), Some(ContentType::HTML));
+            let body = response.into_string().unwrap();
+            assert!(body.contains("Reveal the paste"), "{path}");
+            assert!(!body.contains("random_test_data_to_be_checked"), "{path}");
+        }
+
+        let response = client.post(format!("/download/{id}")).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::Binary));
+        assert_eq!(response.into_string().unwrap(), "random_test_data_to_be_checked");
+        assert_eq!(client.post(format!("/raw/{id}")).dispatch().status(), Status::NotFound);
+        assert_eq!(get_paste(&client, &format!("raw/{id}")).status(), Status::NotFound);
+    }
+
+    #[test]
+    fn creator_preview_does_not_consume_burned_paste() {
+        let (client, _tmp) = create_client();
+        let response = client.post("/?burn=true").body("random_test_data_to_be_checked").dispatch();
+        let preview = response.headers().get_one(PREVIEW_HEADER).unwrap().to_string();
+        let id = response.into_string().unwrap().split('/').next_back().unwrap().to_string();
+        let preview = &preview[preview.find(&id).unwrap()..];
+
+        for _ in 0..2 {
+            let response = get_paste(&client, preview);
+            assert_eq!(response.status(), Status::Ok);
+            assert!(response.into_string().unwrap().contains("random_test_data_to_be_checked"));
+        }
+        assert_eq!(get_paste(&client, &format!("{id}?preview=wrong")).status(), Status::NotFound);
+
+        assert_eq!(client.post(format!("/raw/{id}")).dispatch().into_string().unwrap(), "random_test_data_to_be_checked");
+        assert_eq!(get_paste(&client, preview).status(), Status::NotFound);
+
+        // only "burn" pastes get a preview URL
+        let response = client.post("/").body("data").dispatch();
+        assert!(response.headers().get_one(PREVIEW_HEADER).is_none());
+    }
+
+    #[test]
+    fn max_views_paste_counts_every_kind_of_read() {
+        let (client, _tmp) = create_client();
+        let id = insert_paste(&client, "random_test_data_to_be_checked", "/?max_views=3");
+
+        let response = get_paste(&client, &id);
+        assert_eq!(response.status(), Status::Ok);
+        assert!(response.into_string().unwrap().contains("This paste can be viewed 2 more times."));
+        assert_eq!(get_paste(&client, &format!("raw/{id}")).status(), Status::Ok);
+        assert_eq!(get_paste(&client, &format!("download/{id}")).status(), Status::Ok);
+        assert_eq!(get_paste(&client, &id).status(), Status::NotFound);
+        assert_eq!(get_paste(&client, &format!("raw/{id}")).status(), Status::NotFound);
+    }
+
+    #[test]
+    fn max_views_paste_banner_announces_last_view() {
+        let (client, _tmp) = create_client();
+        let id = insert_paste(&client, "random_test_data_to_be_checked", "/?max_views=1");
+        let body = get_paste(&client, &id).into_string().unwrap();
+        assert!(body.contains("This was the last view"));
+        assert_eq!(get_paste(&client, &id).status(), Status::NotFound);
+    }
+
+    #[test]
+    fn max_views_cannot_be_combined_with_burn() {
+        let (client, _tmp) = create_client();
+        let response = client.post("/?burn=true&max_views=2").body("data").dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+    }
+
+    #[test]
+    fn concurrent_reads_of_burned_paste_succeed_once() {
+        use rocket::local::asynchronous::Client;
+        use std::sync::Arc;
+
+        let tmp_dir = TempDir::new().unwrap();
+        let config = test_config(&tmp_dir, &[]);
+
+        // the reads run on several worker threads, so they really race for the paste
+        let runtime = rocket::tokio::runtime::Builder::new_multi_thread().worker_threads(8).enable_all().build().unwrap();
+        runtime.block_on(async {
+            let client = Arc::new(Client::tracked(rocket_instance(config)).await.unwrap());
+            let url = client.post("/?burn=true").body("burn_data".repeat(100_000)).dispatch().await.into_string().await.unwrap();
+            let id = url.split('/').next_back().unwrap().to_string();
+
+            let reads: Vec<_> = (0..64)
+                .map(|_| {
+                    let (client, id) = (client.clone(), id.clone());
+                    rocket::tokio::spawn(async move { client.post(format!("/raw/{id}")).dispatch().await.status() })
+                })
+                .collect();
+            let mut statuses = vec![];
+            for read in reads {
+                statuses.push(read.await.unwrap());
+            }
+            assert_eq!(statuses.iter().filter(|status| **status == Status::Ok).count(), 1);
+            assert!(statuses.iter().all(|status| *status == Status::Ok || *status == Status::NotFound));
+        });
+    }
+
     #[test]
     fn expired_paste_returns_not_found() {
         use std::{thread, time};
@@ -680,6 +2035,537 @@ mod tests {
         assert_eq!(response.into_bytes(), Some(contents));
     }
 
+    // ── API keys ──────────────────────────────────────────────────────────────
+
+    fn create_client_with_api_keys(keys: serde_json::Value, anonymous_create: bool) -> (Client, TempDir) {
+        let tmp_dir = TempDir::new().unwrap();
+        let keys_path = tmp_dir.path().join("api_keys.json");
+        std::fs::write(&keys_path, keys.to_string()).unwrap();
+        let anonymous_create = anonymous_create.to_string();
+        let args = ["--api-keys", keys_path.to_str().unwrap(), "--anonymous-create", &anonymous_create];
+        create_client_in(tmp_dir, &args)
+    }
+
+    fn post_with_key<'c>(client: &'c Client, key: &str, data: &str) -> rocket::local::blocking::LocalResponse<'c> {
+        client
+            .post("/")
+            .header(rocket::http::Header::new("Authorization", format!("Bearer {key}")))
+            .body(data)
+            .dispatch()
+    }
+
+    #[test]
+    fn anonymous_create_can_be_disabled() {
+        let (client, _tmp) = create_client_with_api_keys(json!([{"name": "ci", "key": "ci-secret"}]), false);
+        assert_eq!(client.post("/").body("data").dispatch().status(), Status::Unauthorized);
+        assert_eq!(post_with_key(&client, "ci-secret", "data").status(), Status::Ok);
+        assert_eq!(post_with_key(&client, "wrong-secret", "data").status(), Status::Unauthorized);
+    }
+
+    #[test]
+    fn other_authorization_schemes_are_anonymous() {
+        let (client, _tmp) = create_client_with_api_keys(json!([{"name": "ci", "key": "ci-secret"}]), true);
+        let basic = rocket::http::Header::new("Authorization", "Basic dXNlcjpwYXNz");
+        assert_eq!(client.post("/").header(basic).body("data").dispatch().status(), Status::Ok);
+    }
+
+    #[test]
+    fn bearer_tokens_are_anonymous_without_api_keys() {
+        let (client, _tmp) = create_client();
+        for token in ["opaque-token", "header.payload.signature"] {
+            let response = client.post("/").header(bearer(token)).body("data").dispatch();
+            assert_eq!(response.status(), Status::Ok);
+        }
+    }
+
+    #[test]
+    fn api_key_limits_paste_size_and_rate() {
+        let keys = json!([{"name": "ci", "key": "ci-secret", "max_paste_size": 8, "pastes_per_hour": 2}]);
+        let (client, _tmp) = create_client_with_api_keys(keys, true);
+
+        assert_eq!(post_with_key(&client, "ci-secret", "too large data").status(), Status::PayloadTooLarge);
+        assert_eq!(post_with_key(&client, "ci-secret", "first").status(), Status::Ok);
+        assert_eq!(post_with_key(&client, "ci-secret", "second").status(), Status::Ok);
+        assert_eq!(post_with_key(&client, "ci-secret", "third").status(), Status::TooManyRequests);
+        // anonymous creation is not affected by the key's limits
+        assert_eq!(client.post("/").body("anonymous data").dispatch().status(), Status::Ok);
+    }
+
+    #[test]
+    fn api_key_stored_bytes_are_released_on_delete() {
+        let keys = json!([{"name": "ci", "key": "ci-secret", "max_stored_bytes": 10}]);
+        let (client, _tmp) = create_client_with_api_keys(keys, true);
+
+        let response = post_with_key(&client, "ci-secret", "0123456789");
+        assert_eq!(response.status(), Status::Ok);
+        let token = response.headers().get_one(TOKEN_HEADER).unwrap().to_string();
+        let id = response.into_string().unwrap().split('/').next_back().unwrap().to_string();
+
+        assert_eq!(post_with_key(&client, "ci-secret", "x").status(), Status::InsufficientStorage);
+        client.delete(format!("/{id}?token={token}")).dispatch();
+        assert_eq!(post_with_key(&client, "ci-secret", "x").status(), Status::Ok);
+    }
+
+    #[test]
+    fn api_key_limits_apply_to_edits() {
+        let keys = json!([{"name": "ci", "key": "ci-secret", "max_paste_size": 12, "max_stored_bytes": 20}]);
+        let (client, _tmp) = create_client_with_api_keys(keys, true);
+
+        let response = post_with_key(&client, "ci-secret", "0123456789");
+        let token = response.headers().get_one(TOKEN_HEADER).unwrap().to_string();
+        let id = response.into_string().unwrap().split('/').next_back().unwrap().to_string();
+        let edit = |data: &str| client.put(format!("/{id}?token={token}")).body(data).dispatch().status();
+
+        assert_eq!(edit("larger than the key allows"), Status::PayloadTooLarge);
+        assert_eq!(edit("0123456789"), Status::Ok);
+        // the revision kept counts too, so another one is over the stored bytes
+        assert_eq!(edit("x"), Status::InsufficientStorage);
+        let amend = client.patch(format!("/{id}?token={token}&lang=rust")).dispatch();
+        assert_eq!(amend.status(), Status::InsufficientStorage);
+    }
+
+    fn create_client_with_jwt(auth_required: &[ProtectedRoute]) -> (Client, TempDir) {
+        let tmp_dir = TempDir::new().unwrap();
+        let jwks_path = tmp_dir.path().join("jwks.json");
+        auth::tests::write_jwks(&jwks_path);
+        let routes: Vec<_> = auth_required.iter().map(|route| route.to_possible_value().unwrap()).collect();
+        let routes = routes.iter().map(|route| route.get_name()).collect::<Vec<_>>().join(",");
+        let mut args = vec!["--jwt-jwks", jwks_path.to_str().unwrap()];
+        if !routes.is_empty() {
+            args.extend(["--auth-required", &routes]);
+        }
+        create_client_in(tmp_dir, &args)
+    }
+
+    fn bearer(token: &str) -> rocket::http::Header<'static> {
+        rocket::http::Header::new("Authorization", format!("Bearer {token}"))
+    }
+
+    #[test]
+    fn jwt_login_can_be_required_only_for_create() {
+        let (client, _tmp) = create_client_with_jwt(&[ProtectedRoute::Create]);
+        assert_eq!(client.post("/").body("data").dispatch().status(), Status::Unauthorized);
+        assert_eq!(
+            client.post("/").header(bearer("not.a.jwt")).body("data").dispatch().status(),
+            Status::Unauthorized
+        );
+
+        let response = client.post("/").header(bearer(&auth::tests::login("alice"))).body("data").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let id = response.into_string().unwrap().split('/').next_back().unwrap().to_string();
+
+        assert_eq!(get_paste(&client, &id).status(), Status::Ok);
+        assert_eq!(client.get(format!("/raw/{id}")).dispatch().status(), Status::Ok);
+    }
+
+    #[test]
+    fn pastes_consumed_by_reads_are_not_cloned() {
+        let (client, _tmp) = create_client();
+        let burn = insert_paste(&client, "burn_data", "/?burn=true");
+        let counted = insert_paste(&client, "counted_data", "/?max_views=1");
+
+        for id in [&burn, &counted] {
+            let response = client.get(format!("/new?id={id}")).dispatch();
+            assert_eq!(response.status(), Status::Forbidden);
+            assert!(!response.into_string().unwrap().contains("_data"));
+        }
+
+        // opening the editor consumed neither of them
+        assert_eq!(client.post(format!("/raw/{burn}")).dispatch().into_string().unwrap(), "burn_data");
+        assert_eq!(client.get(format!("/raw/{counted}")).dispatch().into_string().unwrap(), "counted_data");
+    }
+
+    #[test]
+    fn jwt_login_can_be_required_for_view() {
+        let (client, _tmp) = create_client_with_jwt(&[ProtectedRoute::View]);
+        let id = insert_paste(&client, "data", "/?burn=true");
+
+        assert_eq!(get_paste(&client, &id).status(), Status::Unauthorized);
+        assert_eq!(client.get(format!("/raw/{id}")).dispatch().status(), Status::Unauthorized);
+        assert_eq!(client.get(format!("/new?id={id}")).dispatch().status(), Status::Unauthorized);
+
+        // rejected requests did not burn the paste
+        let response = client.post(format!("/raw/{id}")).header(bearer(&auth::tests::login("bob"))).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.into_string().unwrap(), "data");
+    }
+
+    #[test]
+    fn jwt_owner_can_remove_without_token() {
+        let (client, _tmp) = create_client_with_jwt(&[]);
+        let alice = auth::tests::login("alice");
+        let response = client.post("/").header(bearer(&alice)).body("data").dispatch();
+        let token = response.headers().get_one(TOKEN_HEADER).unwrap().to_string();
+        let id = response.into_string().unwrap().split('/').next_back().unwrap().to_string();
+
+        let bob = auth::tests::login("bob");
+        assert_eq!(client.delete(format!("/{id}")).header(bearer(&bob)).dispatch().status(), Status::Unauthorized);
+        assert_eq!(client.delete(format!("/{id}")).header(bearer(&alice)).dispatch().status(), Status::Ok);
+
+        // pastes created while logged in still get an owner token
+        assert!(!token.is_empty());
+    }
+
+    fn create_client_with_identity_header() -> (Client, TempDir) {
+        create_client_with(&["--trusted-proxies", "10.0.0.0/8,192.168.1.1", "--identity-header", "X-Remote-User"])
+    }
+
+    #[test]
+    #[should_panic(expected = "--identity-header needs --trusted-proxies")]
+    fn identity_header_needs_explicit_trusted_proxies() {
+        let mut config = PastebinConfig::parse_from(["pastebin", "--identity-header", "X-Remote-User"]);
+        config.storage = StorageKind::Memory;
+        rocket_instance(config);
+    }
+
+    #[test]
+    fn identity_header_owner_can_remove_only_own_pastes() {
+        let (client, _tmp) = create_client_with_identity_header();
+        let proxy: std::net::SocketAddr = "10.0.0.2:4000".parse().unwrap();
+        let remote_user = |user: &str| rocket::http::Header::new("X-Remote-User", user.to_string());
+
+        let response = client.post("/").remote(proxy).header(remote_user("alice")).body("data").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let id = response.into_string().unwrap().split('/').next_back().unwrap().to_string();
+
+        let delete = |user: &str, peer: std::net::SocketAddr| {
+            client.delete(format!("/{id}")).remote(peer).header(remote_user(user)).dispatch().status()
+        };
+        assert_eq!(delete("bob", proxy), Status::Unauthorized);
+        // the header is ignored when not set by a trusted proxy
+        assert_eq!(delete("alice", "203.0.113.7:4000".parse().unwrap()), Status::Unauthorized);
+        assert_eq!(delete("alice", "192.168.1.1:4000".parse().unwrap()), Status::Ok);
+    }
+
+    #[test]
+    fn write_rate_limit_throttles_per_client_ip() {
+        let (client, _tmp) = create_client_with(&["--write-rate-limit", "2"]);
+
+        let post = |peer: &str, forwarded_for: Option<&'static str>| {
+            let mut request = client.post("/").remote(peer.parse().unwrap()).body("data");
+            if let Some(ip) = forwarded_for {
+                request = request.header(rocket::http::Header::new("X-Forwarded-For", ip));
+            }
+            request.dispatch()
+        };
+
+        let id = post("192.0.2.1:4000", None).into_string().unwrap().split('/').next_back().unwrap().to_string();
+        assert_eq!(post("192.0.2.1:4001", None).status(), Status::Ok);
+        let response = post("192.0.2.1:4002", None);
+        assert_eq!(response.status(), Status::TooManyRequests);
+        assert_eq!(response.headers().get_one("Retry-After"), Some("30"));
+
+        // reads have their own (unlimited) budget, other clients their own buckets
+        let response = client.get(format!("/raw/{id}")).remote("192.0.2.1:4003".parse().unwrap()).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(post("192.0.2.2:4000", None).status(), Status::Ok);
+
+        // clients behind a trusted proxy are told apart by their forwarded address
+        assert_eq!(post("127.0.0.1:4000", Some("198.51.100.1")).status(), Status::Ok);
+        assert_eq!(post("127.0.0.1:4000", Some("198.51.100.1")).status(), Status::Ok);
+        assert_eq!(post("127.0.0.1:4000", Some("198.51.100.1")).status(), Status::TooManyRequests);
+        assert_eq!(post("127.0.0.1:4000", Some("198.51.100.2")).status(), Status::Ok);
+    }
+
+    fn create_client_with_admin_token() -> (Client, TempDir) {
+        create_client_with(&["--admin-token", "admin-secret"])
+    }
+
+    fn admin_get(client: &Client, path: &str) -> serde_json::Value {
+        let response = client
+            .get(path.to_string())
+            .header(rocket::http::Header::new(admin::ADMIN_TOKEN_HEADER, "admin-secret"))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        serde_json::from_str(&response.into_string().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn admin_api_requires_token() {
+        let (client, _tmp) = create_client();
+        assert_eq!(client.get("/admin/stats").dispatch().status(), Status::NotFound);
+
+        let (client, _tmp) = create_client_with_admin_token();
+        assert_eq!(client.get("/admin/stats").dispatch().status(), Status::Unauthorized);
+        let response = client
+            .get("/admin/stats")
+            .header(rocket::http::Header::new(admin::ADMIN_TOKEN_HEADER, "wrong"))
+            .dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+
+    #[test]
+    fn admin_api_lists_pastes_page_by_page() {
+        let (client, _tmp) = create_client_with_admin_token();
+        let mut ids: Vec<String> = (0..5).map(|i| insert_paste(&client, &"x".repeat(i + 1), "/?lang=rust")).collect();
+        ids.sort();
+
+        let page = admin_get(&client, "/admin/pastes?limit=3");
+        let listed: Vec<&str> = page["pastes"].as_array().unwrap().iter().map(|p| p["id"].as_str().unwrap()).collect();
+        assert_eq!(listed, ids[..3]);
+        assert_eq!(page["pastes"][0]["lang"], "rust");
+        assert!(page["pastes"][0].get("data").is_none());
+
+        let next = page["next"].as_str().unwrap();
+        let page = admin_get(&client, &format!("/admin/pastes?limit=3&after={next}"));
+        let listed: Vec<&str> = page["pastes"].as_array().unwrap().iter().map(|p| p["id"].as_str().unwrap()).collect();
+        assert_eq!(listed, ids[3..]);
+        assert!(page["next"].is_null());
+
+        let info = admin_get(&client, &format!("/admin/pastes/{}", ids[0]));
+        assert_eq!(info["revisions"].as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn admin_api_purges_by_filter_and_reports_stats() {
+        let (client, _tmp) = create_client_with_admin_token();
+        let small = insert_paste(&client, "small", "/?lang=rust");
+        let large = insert_paste(&client, "large content", "/?lang=python");
+        let other = insert_paste(&client, "other large content", "/?lang=rust");
+        let admin = rocket::http::Header::new(admin::ADMIN_TOKEN_HEADER, "admin-secret");
+
+        let stats = admin_get(&client, "/admin/stats");
+        assert_eq!(stats["pastes"], 3);
+        assert_eq!(stats["bytes"], 37);
+        assert_eq!(stats["langs"]["rust"], 2);
+
+        // a purge without any criterion is refused
+        assert_eq!(client.delete("/admin/pastes").header(admin.clone()).dispatch().status(), Status::BadRequest);
+
+        let response = client.delete("/admin/pastes?larger_than=10&lang=rust").header(admin.clone()).dispatch();
+        let deleted: serde_json::Value = serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert_eq!(deleted["deleted"], json!([other]));
+
+        assert_eq!(client.delete(format!("/admin/pastes/{large}")).header(admin).dispatch().status(), Status::Ok);
+        assert_eq!(get_paste(&client, &large).status(), Status::NotFound);
+        assert_eq!(get_paste(&client, &small).status(), Status::Ok);
+        assert_eq!(admin_get(&client, "/admin/stats")["pastes"], 1);
+    }
+
+    #[test]
+    fn admin_api_writes_backups_to_the_backup_dir() {
+        let (client, _tmp) = create_client_with_admin_token();
+        let admin = rocket::http::Header::new(admin::ADMIN_TOKEN_HEADER, "admin-secret");
+        assert_eq!(client.post("/admin/backups").header(admin.clone()).dispatch().status(), Status::NotFound);
+
+        let tmp_dir = TempDir::new().unwrap();
+        let backups = tmp_dir.path().join("backups");
+        let args = ["--admin-token", "admin-secret", "--backup-dir", backups.to_str().unwrap()];
+        let (client, _tmp) = create_client_in(tmp_dir, &args);
+        let id = insert_paste(&client, "backed up", "/");
+
+        let response = client.post("/admin/backups").header(admin).dispatch();
+        if test_storage() != StorageKind::Rocksdb {
+            assert_eq!(response.status(), Status::NotImplemented);
+            return;
+        }
+        let written: serde_json::Value = serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        let backup = lib::open_db(written["path"].as_str().unwrap()).unwrap();
+        assert_eq!(lib::get_entry_data(&id, None, &backup).unwrap().data, b"backed up");
+    }
+
+    #[test]
+    fn admin_api_exports_and_imports_pastes() {
+        let (client, _tmp) = create_client_with_admin_token();
+        let admin = rocket::http::Header::new(admin::ADMIN_TOKEN_HEADER, "admin-secret");
+        let (id, token) = insert_paste_with_token(&client, "exported", "/?lang=rust");
+
+        let response = client.get("/admin/export?format=tar").header(admin.clone()).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let archive = response.into_bytes().unwrap();
+        let response = client.get("/admin/export?format=zip").header(admin.clone()).dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+
+        let (copy, _copy_tmp) = create_client_with_admin_token();
+        let import = |query: &str| {
+            let response = copy.post(format!("/admin/import?{query}")).header(admin.clone()).body(&archive).dispatch();
+            assert_eq!(response.status(), Status::Ok);
+            serde_json::from_str::<serde_json::Value>(&response.into_string().unwrap()).unwrap()
+        };
+        assert_eq!(import("format=tar")["imported"], 1);
+        assert_eq!(import("format=tar")["skipped"], json!([id]));
+        assert_eq!(import("format=tar&on_conflict=rename")["renamed"][&id].as_str().unwrap().len(), id.len());
+
+        // the owner token of the original keeps working on the copy
+        let response = copy.get(format!("/raw/{id}")).dispatch();
+        assert_eq!(response.into_string().unwrap(), "exported");
+        let response = copy.delete(format!("/{id}?token={token}")).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        let response = copy.post("/admin/import").header(admin).body("not json").dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+    }
+
+    #[test]
+    fn secondary_serves_reads_and_sends_writes_to_the_primary() {
+        let (primary, tmp_dir) = create_client_with(&["--storage", "rocksdb"]);
+        let primary_db = tmp_dir.path().join("database").to_str().unwrap().to_string();
+        let id = insert_paste(&primary, "replicated", "/");
+
+        let secondary_config = |args: &[&str]| {
+            let mut config = PastebinConfig::parse_from([&["pastebin", "--secondary-of", &primary_db], args].concat());
+            config.db_path = tmp_dir.path().join("secondary").to_str().unwrap().to_string();
+            Client::tracked(rocket_instance(config)).expect("invalid rocket instance")
+        };
+        let secondary = secondary_config(&["--primary-url", "https://primary.example/", "--catch-up-interval", "0"]);
+        assert_eq!(secondary.get(format!("/raw/{id}")).dispatch().into_string().unwrap(), "replicated");
+        assert_eq!(secondary.get(format!("/{id}")).dispatch().status(), Status::Ok);
+
+        let counted = insert_paste(&primary, "counted", "/?max_views=2");
+        let store = secondary.rocket().state::<Store>().unwrap();
+        assert_eq!(secondary.get(format!("/raw/{counted}")).dispatch().status(), Status::NotFound);
+        store.catch_up().unwrap();
+
+        let location = |response: rocket::local::blocking::LocalResponse| {
+            assert_eq!(response.status(), Status::TemporaryRedirect);
+            response.headers().get_one("Location").unwrap().to_string()
+        };
+        // counted reads, writes and the editor are the primary's
+        let response = secondary.get(format!("/raw/{counted}")).dispatch();
+        assert_eq!(location(response), format!("https://primary.example/raw/{counted}"));
+        let response = secondary.post("/?lang=rust").body("new").dispatch();
+        assert_eq!(location(response), "https://primary.example/?lang=rust");
+        assert_eq!(location(secondary.delete(format!("/{id}")).dispatch()), format!("https://primary.example/{id}"));
+        assert_eq!(location(secondary.get("/new").dispatch()), "https://primary.example/new");
+        assert_eq!(location(secondary.get("/admin/stats").dispatch()), "https://primary.example/admin/stats");
+        assert_eq!(primary.get(format!("/raw/{id}")).dispatch().status(), Status::Ok);
+
+        let secondary = secondary_config(&[]);
+        assert_eq!(secondary.put(format!("/{id}")).body("edit").dispatch().status(), Status::ServiceUnavailable);
+    }
+
+    fn create_client_with_compression() -> (Client, TempDir) {
+        create_client_with(&["--compression", "zstd", "--compression-threshold", "16"])
+    }
+
+    #[test]
+    fn compressed_paste_is_served_decoded_or_as_zstd() {
+        let (client, _tmp) = create_client_with_compression();
+        let data = "compressible line\n".repeat(100);
+        let (id, token) = insert_paste_with_token(&client, &data, "/");
+
+        let response = client.get(format!("/raw/{id}")).dispatch();
+        assert_eq!(response.headers().get_one("Content-Encoding"), None);
+        assert_eq!(response.into_string().unwrap(), data);
+
+        let response = client
+            .get(format!("/raw/{id}"))
+            .header(rocket::http::Header::new("Accept-Encoding", "gzip, zstd"))
+            .dispatch();
+        assert_eq!(response.headers().get_one("Content-Encoding"), Some("zstd"));
+        let body = response.into_bytes().unwrap();
+        assert!(body.len() < data.len());
+        assert_eq!(zstd::stream::decode_all(&body[..]).unwrap(), data.as_bytes());
+
+        let response = client
+            .get(format!("/raw/{id}"))
+            .header(rocket::http::Header::new("Accept-Encoding", "zstd;q=0"))
+            .dispatch();
+        assert_eq!(response.headers().get_one("Content-Encoding"), None);
+
+        assert!(get_paste(&client, &id).into_string().unwrap().contains("compressible line"));
+
+        // small bodies are stored as is, and so are never served encoded
+        assert_eq!(edit_paste(&client, &id, &token, "tiny").status(), Status::Ok);
+        let response = client
+            .get(format!("/raw/{id}"))
+            .header(rocket::http::Header::new("Accept-Encoding", "zstd"))
+            .dispatch();
+        assert_eq!(response.headers().get_one("Content-Encoding"), None);
+        assert_eq!(response.into_string().unwrap(), "tiny");
+        assert!(get_paste(&client, &format!("{id}/rev/0")).into_string().unwrap().contains("compressible line"));
+    }
+
+    // ── large pastes ──────────────────────────────────────────────────────────
+
+    #[test]
+    fn large_paste_is_stored_in_chunks_and_streamed() {
+        let (client, _tmp) = create_client_with_compression();
+        let data = "build log line\n".repeat(CHUNK_SIZE / 5);
+        let (id, token) = insert_paste_with_token(&client, &data, "/");
+
+        assert_eq!(client.get(format!("/raw/{id}")).dispatch().into_string().unwrap(), data);
+        let response = client
+            .get(format!("/download/{id}"))
+            .header(rocket::http::Header::new("Accept-Encoding", "zstd"))
+            .dispatch();
+        assert_eq!(response.headers().get_one("Content-Encoding"), Some("zstd"));
+        assert_eq!(zstd::stream::decode_all(&response.into_bytes().unwrap()[..]).unwrap(), data.as_bytes());
+
+        let edited = data.replace("line", "LINE");
+        assert_eq!(edit_paste(&client, &id, &token, &edited).status(), Status::Ok);
+        assert_eq!(client.get(format!("/raw/{id}")).dispatch().into_string().unwrap(), edited);
+        assert!(get_paste(&client, &format!("{id}/rev/0")).into_string().unwrap().contains("build log line"));
+    }
+
+    #[test]
+    fn paste_size_limit_is_set_per_instance_and_per_api_key() {
+        let tmp_dir = TempDir::new().unwrap();
+        let keys_path = tmp_dir.path().join("api_keys.json");
+        let keys = json!([{"name": "ci", "key": "ci-secret", "max_paste_size": 3 << 20}]);
+        std::fs::write(&keys_path, keys.to_string()).unwrap();
+        let args = ["--max-paste-size", "2097152", "--api-keys", keys_path.to_str().unwrap()];
+        let (client, _tmp) = create_client_in(tmp_dir, &args);
+
+        let large = "x".repeat(5 << 19);
+        assert_eq!(client.post("/").body(&large).dispatch().status(), Status::PayloadTooLarge);
+        assert_eq!(client.post("/").body("x".repeat(2 << 20)).dispatch().status(), Status::Ok);
+        assert_eq!(post_with_key(&client, "ci-secret", &large).status(), Status::Ok);
+        assert_eq!(post_with_key(&client, "ci-secret", &"x".repeat(4 << 20)).status(), Status::PayloadTooLarge);
+    }
+
+    #[test]
+    #[should_panic(expected = "newer than this release supports")]
+    fn server_refuses_a_database_of_a_newer_schema() {
+        let tmp_dir = TempDir::new().unwrap();
+        let mut config = PastebinConfig::parse_from(["pastebin"]);
+        config.db_path = tmp_dir.path().join("database").to_str().unwrap().to_string();
+        let db = lib::open_db(&config.db_path).unwrap();
+        db.put(lib::META_CF, lib::SCHEMA_VERSION_KEY, &(lib::SCHEMA_VERSION + 1).to_be_bytes()).unwrap();
+        drop(db);
+
+        rocket_instance(config);
+    }
+
+    #[test]
+    fn cli_defaults_to_serve() {
+        let cli = Cli::parse_from(["pastebin", "--port", "9000"]);
+        assert!(cli.command.is_none());
+        assert_eq!(cli.serve.port, 9000);
+
+        let cli = Cli::parse_from(["pastebin", "serve", "--port", "9000"]);
+        assert!(matches!(cli.command, Some(Command::Serve(config)) if config.port == 9000));
+        assert!(Cli::try_parse_from(["pastebin", "--port", "9000", "stats"]).is_err());
+    }
+
+    // ── storage quota ─────────────────────────────────────────────────────────
+
+    #[test]
+    fn max_storage_evicts_oldest_unpinned_pastes() {
+        let (client, _tmp) = create_client_with(&["--max-storage", "30", "--admin-token", "admin-secret"]);
+        let admin = rocket::http::Header::new(admin::ADMIN_TOKEN_HEADER, "admin-secret");
+
+        let pinned = insert_paste(&client, "0123456789", "/");
+        let oldest = insert_paste(&client, "0123456789", "/");
+        let (newest, token) = insert_paste_with_token(&client, "0123456789", "/");
+        assert_eq!(client.put(format!("/admin/pastes/{pinned}/pin")).header(admin.clone()).dispatch().status(), Status::Ok);
+
+        // an edit keeps the previous content as a revision: 10 more bytes, the oldest goes
+        assert_eq!(edit_paste(&client, &newest, &token, "abcdefghij").status(), Status::Ok);
+        assert_eq!(get_paste(&client, &oldest).status(), Status::NotFound);
+        assert_eq!(get_paste(&client, &pinned).status(), Status::Ok);
+
+        // more than the unpinned pastes can free, nothing is evicted
+        assert_eq!(client.post("/").body("x".repeat(25)).dispatch().status(), Status::InsufficientStorage);
+        assert_eq!(client.post("/").body("x".repeat(31)).dispatch().status(), Status::InsufficientStorage);
+        assert_eq!(get_paste(&client, &newest).status(), Status::Ok);
+
+        let created = insert_paste(&client, "0123456789", "/");
+        assert_eq!(get_paste(&client, &newest).status(), Status::NotFound);
+        assert_eq!(get_paste(&client, &pinned).status(), Status::Ok);
+        assert_eq!(get_paste(&client, &created).status(), Status::Ok);
+    }
+
     // ── get_url unit tests ────────────────────────────────────────────────────
 
     #[test]
@@ -738,6 +2624,7 @@ mod tests {
         let (client, _tmp) = create_client();
         let response = client
             .post("/")
+            .remote("127.0.0.1:4000".parse().unwrap())
             .header(rocket::http::Header::new("X-Forwarded-Host", "public.example.com"))
             .header(rocket::http::Header::new("X-Forwarded-Proto", "https"))
             .body("test data")
@@ -760,15 +2647,56 @@ mod tests {
         assert!(url.starts_with("http://direct.example.com/"), "unexpected url: {url}");
     }
 
+    #[test]
+    fn create_paste_url_ignores_forwarded_headers_from_untrusted_peers() {
+        let (client, _tmp) = create_client();
+        let response = client
+            .post("/")
+            .remote("203.0.113.7:4000".parse().unwrap())
+            .header(rocket::http::Header::new("Host", "direct.example.com"))
+            .header(rocket::http::Header::new("X-Forwarded-Host", "evil.example.com"))
+            .header(rocket::http::Header::new("Forwarded", "host=evil.example.com;proto=https"))
+            .body("test data")
+            .dispatch();
+        let url = response.into_string().unwrap();
+        assert!(url.starts_with("http://direct.example.com/"), "unexpected url: {url}");
+    }
+
+    #[test]
+    fn create_paste_url_ignores_forwarded_header_by_default() {
+        let (client, _tmp) = create_client();
+        let response = client
+            .post("/")
+            .remote("127.0.0.1:4000".parse().unwrap())
+            .header(rocket::http::Header::new("X-Forwarded-Host", "public.example.com"))
+            .header(rocket::http::Header::new("Forwarded", "host=evil.example.com;proto=https"))
+            .body("test data")
+            .dispatch();
+        let url = response.into_string().unwrap();
+        assert!(url.starts_with("http://public.example.com/"), "unexpected url: {url}");
+    }
+
+    #[test]
+    fn create_paste_url_uses_forwarded_header_set_by_closest_proxy() {
+        let (client, _tmp) = create_client_with(&["--forwarded-headers", "forwarded"]);
+        let response = client
+            .post("/")
+            .remote("127.0.0.1:4000".parse().unwrap())
+            .header(rocket::http::Header::new("X-Forwarded-Host", "ignored.example.com"))
+            .header(rocket::http::Header::new(
+                "Forwarded",
+                "host=evil.example.com, for=198.51.100.1;host=public.example.com;proto=https",
+            ))
+            .body("test data")
+            .dispatch();
+        let url = response.into_string().unwrap();
+        assert!(url.starts_with("https://public.example.com/"), "unexpected url: {url}");
+    }
+
     // ── uri_prefix integration ────────────────────────────────────────────────
 
     fn create_client_with_prefix(prefix: &str) -> (Client, TempDir) {
-        let tmp_dir = TempDir::new().unwrap();
-        let mut config = PastebinConfig::parse_from(["pastebin"]);
-        config.db_path = tmp_dir.path().join("database").to_str().unwrap().to_string();
-        config.uri_prefix = prefix.to_string();
-        let client = Client::tracked(rocket_instance(config)).expect("invalid rocket instance");
-        (client, tmp_dir)
+        create_client_with(&["--uri-prefix", prefix])
     }
 
     #[test]
@@ -802,6 +2730,13 @@ mod tests {
         assert_eq!(response.status(), Status::NotFound);
     }
 
+    #[test]
+    fn success_message_shows_the_preview_link_of_a_burn_paste() {
+        let (client, _tmp) = create_client();
+        let response = client.get("/new?msg=created&url=http://localhost/abc&preview=secret").dispatch();
+        assert!(response.into_string().unwrap().contains("http://localhost/abc?preview=secret"));
+    }
+
     #[test]
     fn static_unknown_extension_falls_back_to_octet_stream() {
         // Regression: ContentType::from_extension().unwrap() would panic for extensions
@@ -0,0 +1,298 @@
+use std::net::{IpAddr, Ipv4Addr};
+
+use ipnet::IpNet;
+use rocket::request::{FromRequest, Outcome, Request};
+
+/// Parse a `--trusted-proxies` entry, either a CIDR range or a single address.
+pub fn parse_cidr(value: &str) -> Result<IpNet, String> {
+    value
+        .parse::<IpNet>()
+        .or_else(|_| value.parse::<IpAddr>().map(IpNet::from))
+        .map_err(|_| format!("invalid CIDR or IP address: {value}"))
+}
+
+/// Header family the trusted proxies forward the client's host, scheme and address in.
+/// Only that one is read: the other may be passed through unchanged from the client.
+#[derive(Clone, Copy, Debug, Default, PartialEq, clap::ValueEnum)]
+pub enum ForwardedHeaders {
+    /// `X-Forwarded-Host`, `X-Forwarded-Proto` and `X-Forwarded-For`
+    #[default]
+    XForwarded,
+    /// RFC 7239 `Forwarded`
+    Forwarded,
+}
+
+/// Peers allowed to set headers on behalf of the client (ie. a reverse proxy).
+#[derive(Default)]
+pub struct TrustedProxies {
+    nets: Vec<IpNet>,
+    headers: ForwardedHeaders,
+}
+
+impl TrustedProxies {
+    pub fn new(nets: Vec<IpNet>, headers: ForwardedHeaders) -> Self {
+        TrustedProxies { nets, headers }
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+            ip => ip,
+        };
+        self.nets.iter().any(|net| net.contains(&ip))
+    }
+
+    /// Whether the request comes directly from a trusted proxy.
+    pub fn is_trusted(&self, request: &Request<'_>) -> bool {
+        request.remote().is_some_and(|peer| self.contains(peer.ip()))
+    }
+}
+
+/// One element of a RFC 7239 `Forwarded` header, ie. `for=192.0.2.43;proto=https;host=a.com`.
+#[derive(Default, Debug, PartialEq)]
+pub struct ForwardedElement {
+    pub node: Option<String>,
+    pub host: Option<String>,
+    pub proto: Option<String>,
+}
+
+/// Split `value` on `sep`, ignoring separators within quoted strings.
+fn split_unquoted(value: &str, sep: char) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut quoted = false;
+    let mut start = 0;
+
+    for (i, c) in value.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c == sep && !quoted => {
+                parts.push(&value[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&value[start..]);
+    parts
+}
+
+/// Parse the value of a `Forwarded` header into its elements, one per proxy.
+pub fn parse_forwarded(value: &str) -> Vec<ForwardedElement> {
+    split_unquoted(value, ',')
+        .into_iter()
+        .map(|element| {
+            let mut parsed = ForwardedElement::default();
+            for pair in split_unquoted(element, ';') {
+                let Some((name, value)) = pair.split_once('=') else { continue };
+                let value = value.trim().trim_matches('"').to_string();
+                match name.trim().to_ascii_lowercase().as_str() {
+                    "for" => parsed.node = Some(value),
+                    "host" => parsed.host = Some(value),
+                    "proto" => parsed.proto = Some(value.to_ascii_lowercase()),
+                    _ => {}
+                }
+            }
+            parsed
+        })
+        .collect()
+}
+
+/// Address of a `for=` node or `X-Forwarded-For` entry, with or without a port.
+/// `unknown` and obfuscated identifiers (ie. `_hidden`) have none.
+pub fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Some(rest) = node.strip_prefix('[') {
+        return rest.split_once(']')?.0.parse().ok();
+    }
+    node.parse::<IpAddr>().ok().or_else(|| {
+        let (ip, port) = node.rsplit_once(':')?;
+        port.parse::<u16>().ok()?;
+        ip.parse::<Ipv4Addr>().ok().map(IpAddr::V4)
+    })
+}
+
+fn forwarded_elements(request: &Request<'_>) -> Vec<ForwardedElement> {
+    request.headers().get("Forwarded").flat_map(parse_forwarded).collect()
+}
+
+/// Values of a comma separated `X-Forwarded-*` header, over all its occurrences.
+fn forwarded_list<'r>(request: &'r Request<'_>, name: &str) -> Vec<&'r str> {
+    request
+        .headers()
+        .get(name)
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .collect()
+}
+
+/// Public host and scheme announced by a trusted proxy in the configured header family.
+/// Proxies append to these headers, so only the last value, set by the proxy closest to
+/// us, is used; earlier values may come from the client itself. Nothing is returned for
+/// requests not coming from a trusted proxy.
+pub fn forwarded_host(request: &Request<'_>, proxies: &TrustedProxies) -> (Option<String>, Option<String>) {
+    if !proxies.is_trusted(request) {
+        return (None, None);
+    }
+
+    match proxies.headers {
+        ForwardedHeaders::Forwarded => match forwarded_elements(request).pop() {
+            Some(last) => (last.host, last.proto),
+            None => (None, None),
+        },
+        ForwardedHeaders::XForwarded => {
+            let host = forwarded_list(request, "X-Forwarded-Host").pop().map(str::to_string);
+            let proto = forwarded_list(request, "X-Forwarded-Proto")
+                .pop()
+                .map(|proto| proto.to_ascii_lowercase());
+            (host, proto)
+        }
+    }
+}
+
+/// Resolve the address of the client. Starting from the peer, the chain of addresses
+/// recorded in the configured header family (`Forwarded` or `X-Forwarded-For`) is walked
+/// back for as long as the hops are trusted proxies; the first untrusted hop is the client.
+pub fn client_ip(request: &Request<'_>, proxies: &TrustedProxies) -> Option<IpAddr> {
+    let mut ip = request.remote()?.ip();
+
+    let hops: Vec<Option<IpAddr>> = match proxies.headers {
+        ForwardedHeaders::Forwarded => forwarded_elements(request)
+            .iter()
+            .map(|e| e.node.as_deref().and_then(parse_node))
+            .collect(),
+        ForwardedHeaders::XForwarded => {
+            forwarded_list(request, "X-Forwarded-For").into_iter().map(parse_node).collect()
+        }
+    };
+
+    for hop in hops.into_iter().rev() {
+        if !proxies.contains(ip) {
+            break;
+        }
+        match hop {
+            Some(hop) => ip = hop,
+            // unknown or obfuscated hop, the last proxy is as far as we can tell
+            None => break,
+        }
+    }
+
+    Some(ip)
+}
+
+/// Address of the client, resolved through the trusted proxies.
+#[derive(Clone, Copy, Debug)]
+pub struct ClientIp(pub IpAddr);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ClientIp {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let ip = request.local_cache(|| {
+            let proxies = request.rocket().state::<TrustedProxies>();
+            client_ip(request, proxies.unwrap_or(&TrustedProxies::default()))
+        });
+
+        match ip {
+            Some(ip) => Outcome::Success(ClientIp(*ip)),
+            None => Outcome::Forward(rocket::http::Status::BadRequest),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trusted_proxies_accept_cidrs_and_addresses() {
+        let nets = vec![parse_cidr("10.0.0.0/8").unwrap(), parse_cidr("::1").unwrap()];
+        let proxies = TrustedProxies::new(nets, ForwardedHeaders::XForwarded);
+        assert!(proxies.contains("10.1.2.3".parse().unwrap()));
+        assert!(proxies.contains("::ffff:10.1.2.3".parse().unwrap()));
+        assert!(proxies.contains("::1".parse().unwrap()));
+        assert!(!proxies.contains("11.0.0.1".parse().unwrap()));
+        assert!(parse_cidr("10.0.0.0/33").is_err());
+    }
+
+    #[test]
+    fn parse_forwarded_handles_quotes_and_multiple_elements() {
+        let elements = parse_forwarded(r#"for="[2001:db8::1]:4711";proto=HTTPS, For=192.0.2.43;host="a.com;b""#);
+        assert_eq!(
+            elements,
+            vec![
+                ForwardedElement {
+                    node: Some("[2001:db8::1]:4711".to_string()),
+                    host: None,
+                    proto: Some("https".to_string()),
+                },
+                ForwardedElement {
+                    node: Some("192.0.2.43".to_string()),
+                    host: Some("a.com;b".to_string()),
+                    proto: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_node_accepts_ports_and_rejects_obfuscated_nodes() {
+        assert_eq!(parse_node("192.0.2.43"), Some("192.0.2.43".parse().unwrap()));
+        assert_eq!(parse_node("\"192.0.2.43:8080\""), Some("192.0.2.43".parse().unwrap()));
+        assert_eq!(parse_node("[2001:db8::1]:4711"), Some("2001:db8::1".parse().unwrap()));
+        assert_eq!(parse_node("2001:db8::1"), Some("2001:db8::1".parse().unwrap()));
+        assert_eq!(parse_node("unknown"), None);
+        assert_eq!(parse_node("_hidden"), None);
+    }
+
+    #[test]
+    fn client_ip_walks_back_through_trusted_hops() {
+        let client = rocket::local::blocking::Client::debug_with(vec![]).unwrap();
+        let nets = vec![parse_cidr("10.0.0.0/8").unwrap()];
+        let x_forwarded = TrustedProxies::new(nets.clone(), ForwardedHeaders::XForwarded);
+        let forwarded = TrustedProxies::new(nets, ForwardedHeaders::Forwarded);
+        let resolve = |proxies: &TrustedProxies, peer: &str, headers: &[(&'static str, &'static str)]| {
+            let mut request = client.get("/").remote(peer.parse().unwrap());
+            for (name, value) in headers {
+                request = request.header(rocket::http::Header::new(*name, *value));
+            }
+            client_ip(request.inner(), proxies).unwrap().to_string()
+        };
+
+        // untrusted peers cannot claim another address
+        assert_eq!(resolve(&x_forwarded, "203.0.113.7:1", &[("X-Forwarded-For", "198.51.100.1")]), "203.0.113.7");
+        // a spoofed entry left of the first untrusted hop is ignored
+        let spoofed = [("X-Forwarded-For", "1.1.1.1, 198.51.100.1, 10.0.0.2")];
+        assert_eq!(resolve(&x_forwarded, "10.0.0.1:1", &spoofed), "198.51.100.1");
+        let chain = [("Forwarded", "for=\"[2001:db8::1]:4711\", for=10.0.0.2")];
+        assert_eq!(resolve(&forwarded, "10.0.0.1:1", &chain), "2001:db8::1");
+        // obfuscated hops stop the walk at the last known proxy
+        assert_eq!(resolve(&forwarded, "10.0.0.1:1", &[("Forwarded", "for=198.51.100.1, for=_hidden")]), "10.0.0.1");
+        // the other header family is never read, whatever the client put in it
+        let mixed = [("Forwarded", "for=1.1.1.1"), ("X-Forwarded-For", "198.51.100.1")];
+        assert_eq!(resolve(&x_forwarded, "10.0.0.1:1", &mixed), "198.51.100.1");
+        assert_eq!(resolve(&forwarded, "10.0.0.1:1", &mixed), "1.1.1.1");
+        assert_eq!(resolve(&forwarded, "10.0.0.1:1", &[("X-Forwarded-For", "198.51.100.1")]), "10.0.0.1");
+    }
+
+    #[test]
+    fn forwarded_host_reads_only_the_last_element_of_the_configured_family() {
+        let client = rocket::local::blocking::Client::debug_with(vec![]).unwrap();
+        let nets = vec![parse_cidr("10.0.0.0/8").unwrap()];
+        let x_forwarded = TrustedProxies::new(nets.clone(), ForwardedHeaders::XForwarded);
+        let forwarded = TrustedProxies::new(nets, ForwardedHeaders::Forwarded);
+        let request = client
+            .get("/")
+            .remote("10.0.0.1:1".parse().unwrap())
+            .header(rocket::http::Header::new("X-Forwarded-Host", "evil.example.com, public.example.com"))
+            .header(rocket::http::Header::new("Forwarded", "host=evil.example.com;proto=https, for=198.51.100.1"));
+
+        assert_eq!(
+            forwarded_host(request.inner(), &x_forwarded),
+            (Some("public.example.com".to_string()), None)
+        );
+        // the last element carries no host, earlier ones added by the client are not used
+        assert_eq!(forwarded_host(request.inner(), &forwarded), (None, None));
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, Ipv6Addr};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+
+use crate::proxy::ClientIp;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Budget {
+    Write,
+    Read,
+}
+
+type Key = (Budget, IpAddr);
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    /// Position of the bucket in `Buckets::by_use`.
+    used: u64,
+}
+
+/// The buckets, along with their order of use so that the least recently used one is
+/// found without going through all of them.
+#[derive(Default)]
+struct Buckets {
+    buckets: HashMap<Key, Bucket>,
+    by_use: BTreeMap<u64, Key>,
+    uses: u64,
+}
+
+/// Per client token buckets. Every bucket holds up to a minute worth of requests and
+/// refills continuously, so short bursts are allowed while the average rate is capped.
+/// Clients are told apart by IPv4 address or IPv6 /64 network, which is what a single
+/// IPv6 host usually gets to pick its addresses from.
+pub struct RateLimiter {
+    write_per_minute: u32,
+    read_per_minute: u32,
+    max_clients: usize,
+    buckets: Mutex<Buckets>,
+}
+
+/// The address or network a client is limited by.
+fn client_key(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => IpAddr::V4(v4),
+            None => IpAddr::V6(Ipv6Addr::from(u128::from(v6) & (u128::MAX << 64))),
+        },
+        ip => ip,
+    }
+}
+
+impl RateLimiter {
+    /// A rate of 0 disables the limit. At most `max_clients` buckets are kept in memory.
+    pub fn new(write_per_minute: u32, read_per_minute: u32, max_clients: usize) -> Self {
+        RateLimiter {
+            write_per_minute,
+            read_per_minute,
+            max_clients: max_clients.max(1),
+            buckets: Mutex::new(Buckets::default()),
+        }
+    }
+
+    fn rate(&self, budget: Budget) -> u32 {
+        match budget {
+            Budget::Write => self.write_per_minute,
+            Budget::Read => self.read_per_minute,
+        }
+    }
+
+    /// Take a token from the bucket of `ip`, or return the seconds to wait for the next one.
+    pub fn acquire(&self, budget: Budget, ip: IpAddr, now: Instant) -> Result<(), u64> {
+        let rate = self.rate(budget);
+        if rate == 0 {
+            return Ok(());
+        }
+        let capacity = rate as f64;
+        let per_second = capacity / 60.0;
+
+        let key = (budget, client_key(ip));
+        let mut buckets = self.buckets.lock().unwrap();
+        let Buckets { buckets, by_use, uses } = &mut *buckets;
+        if buckets.len() >= self.max_clients && !buckets.contains_key(&key) {
+            // make room for the new client: the least recently used bucket goes, as the
+            // one most likely to have refilled already
+            if let Some((_, oldest)) = by_use.pop_first() {
+                buckets.remove(&oldest);
+            }
+        }
+
+        *uses += 1;
+        let bucket = buckets.entry(key).or_insert(Bucket { tokens: capacity, updated: now, used: *uses });
+        by_use.remove(&bucket.used);
+        bucket.used = *uses;
+        by_use.insert(bucket.used, key);
+
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * per_second).min(capacity);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            // rounded to the millisecond first, so float noise does not add a second
+            let wait = ((1.0 - bucket.tokens) / per_second * 1000.0).round() / 1000.0;
+            Err(wait.ceil() as u64)
+        }
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.buckets.lock().unwrap().buckets.len()
+    }
+}
+
+/// Seconds a throttled client should wait, sent back in the `Retry-After` header.
+pub struct RetryAfter(pub Option<u64>);
+
+async fn limit(request: &Request<'_>, budget: Budget) -> Outcome<(), ()> {
+    let limiter = match request.rocket().state::<RateLimiter>() {
+        Some(limiter) => limiter,
+        None => return Outcome::Success(()),
+    };
+    let ip = match request.guard::<ClientIp>().await {
+        Outcome::Success(ClientIp(ip)) => ip,
+        _ => return Outcome::Success(()),
+    };
+
+    match limiter.acquire(budget, ip, Instant::now()) {
+        Ok(()) => Outcome::Success(()),
+        Err(seconds) => {
+            request.local_cache(|| RetryAfter(Some(seconds)));
+            Outcome::Error((Status::TooManyRequests, ()))
+        }
+    }
+}
+
+/// Counts the request against the client's write budget (`--write-rate-limit`).
+pub struct WriteLimit;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for WriteLimit {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        limit(request, Budget::Write).await.map(|_| WriteLimit)
+    }
+}
+
+/// Counts the request against the client's read budget (`--read-rate-limit`).
+pub struct ReadLimit;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ReadLimit {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        limit(request, Budget::Read).await.map(|_| ReadLimit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn acquire_allows_bursts_and_refills_over_time() {
+        let limiter = RateLimiter::new(2, 0, 10);
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        let start = Instant::now();
+
+        assert_eq!(limiter.acquire(Budget::Write, ip, start), Ok(()));
+        assert_eq!(limiter.acquire(Budget::Write, ip, start), Ok(()));
+        assert_eq!(limiter.acquire(Budget::Write, ip, start), Err(30));
+        assert_eq!(limiter.acquire(Budget::Write, ip, start + Duration::from_secs(20)), Err(10));
+        assert_eq!(limiter.acquire(Budget::Write, ip, start + Duration::from_secs(30)), Ok(()));
+
+        // reads are unlimited and other clients have their own budget
+        assert_eq!(limiter.acquire(Budget::Read, ip, start), Ok(()));
+        assert_eq!(limiter.acquire(Budget::Write, "192.0.2.2".parse().unwrap(), start), Ok(()));
+    }
+
+    #[test]
+    fn acquire_keeps_a_bounded_number_of_clients() {
+        let limiter = RateLimiter::new(1, 0, 3);
+        let start = Instant::now();
+
+        for i in 0..100u8 {
+            let ip = IpAddr::from([198, 51, 100, i]);
+            assert_eq!(limiter.acquire(Budget::Write, ip, start + Duration::from_millis(i as u64)), Ok(()));
+            assert!(limiter.len() <= 3);
+        }
+
+        // the most recent clients are still throttled
+        let ip = IpAddr::from([198, 51, 100, 99]);
+        assert!(limiter.acquire(Budget::Write, ip, start + Duration::from_millis(100)).is_err());
+
+        // the least recently used client is the one forgotten, even if it was created first
+        let now = start + Duration::from_millis(200);
+        let [a, b, c, d] = [1, 2, 3, 4].map(|i| IpAddr::from([203, 0, 113, i]));
+        for ip in [a, b, c, a] {
+            let _ = limiter.acquire(Budget::Write, ip, now);
+        }
+        let _ = limiter.acquire(Budget::Write, d, now);
+        assert!(limiter.acquire(Budget::Write, a, now).is_err());
+        assert_eq!(limiter.acquire(Budget::Write, b, now), Ok(()));
+    }
+
+    #[test]
+    fn ipv6_clients_are_limited_by_their_64_network() {
+        let limiter = RateLimiter::new(1, 0, 10);
+        let start = Instant::now();
+
+        assert_eq!(limiter.acquire(Budget::Write, "2001:db8:1:2::1".parse().unwrap(), start), Ok(()));
+        assert_eq!(limiter.acquire(Budget::Write, "2001:db8:1:2:ffff::9".parse().unwrap(), start), Err(60));
+        assert_eq!(limiter.acquire(Budget::Write, "2001:db8:1:3::1".parse().unwrap(), start), Ok(()));
+        assert_eq!(limiter.len(), 2);
+
+        // IPv4-mapped addresses are their IPv4 address
+        assert_eq!(limiter.acquire(Budget::Write, "192.0.2.1".parse().unwrap(), start), Ok(()));
+        assert_eq!(limiter.acquire(Budget::Write, "::ffff:192.0.2.1".parse().unwrap(), start), Err(60));
+    }
+}
@@ -0,0 +1,106 @@
+use std::io::Cursor;
+use std::time::Duration;
+
+use rocket::fairing::AdHoc;
+use rocket::http::{Header, Status};
+use rocket::{delete, get, patch, post, put, routes, tokio, Route};
+
+use crate::lib::store::Store;
+
+/// Fairing bringing a secondary instance (see `--secondary-of`) up to date with the writes
+/// of its primary every `interval` once the server is up. A zero interval leaves the
+/// secondary with the content of the primary at startup.
+pub fn fairing(interval: Duration) -> AdHoc {
+    AdHoc::on_liftoff("Secondary catch-up", move |rocket| {
+        Box::pin(async move {
+            let store = match rocket.state::<Store>() {
+                Some(store) if !interval.is_zero() => store.clone(),
+                _ => return,
+            };
+
+            tokio::spawn(async move {
+                let mut ticks = tokio::time::interval(interval);
+                loop {
+                    ticks.tick().await;
+                    let store = store.clone();
+                    match tokio::task::spawn_blocking(move || store.catch_up()).await {
+                        Ok(Ok(())) => {}
+                        Ok(Err(e)) => rocket::error!("catching up with the primary failed: {e}"),
+                        Err(e) => rocket::error!("catching up with the primary panicked: {e}"),
+                    }
+                }
+            });
+        })
+    })
+}
+
+/// Fairing sending the requests a secondary cannot serve, answered with `503 Service
+/// Unavailable`, to the same path on the primary at `primary_url` with a `307 Temporary
+/// Redirect` (which keeps the method and body of the request). Without a URL they are
+/// left with the 503.
+pub fn redirect(primary_url: Option<String>) -> AdHoc {
+    AdHoc::on_response("Primary redirects", move |request, response| {
+        let primary_url = primary_url.clone();
+        Box::pin(async move {
+            if response.status() != Status::ServiceUnavailable {
+                return;
+            }
+            if let Some(primary_url) = primary_url {
+                let location = format!("{}{}", primary_url.trim_end_matches('/'), request.uri());
+                response.set_status(Status::TemporaryRedirect);
+                response.set_header(Header::new("Location", location));
+                response.set_sized_body(0, Cursor::new(""));
+            }
+        })
+    })
+}
+
+// Only the read routes are mounted on a secondary, these take the requests of the others.
+
+#[post("/<_..>")]
+fn post_to_primary() -> Status {
+    Status::ServiceUnavailable
+}
+
+#[put("/<_..>")]
+fn put_to_primary() -> Status {
+    Status::ServiceUnavailable
+}
+
+#[patch("/<_..>")]
+fn patch_to_primary() -> Status {
+    Status::ServiceUnavailable
+}
+
+#[delete("/<_..>")]
+fn delete_to_primary() -> Status {
+    Status::ServiceUnavailable
+}
+
+/// The editor, which only writes.
+#[get("/")]
+fn index_to_primary() -> Status {
+    Status::ServiceUnavailable
+}
+
+#[get("/new")]
+fn new_to_primary() -> Status {
+    Status::ServiceUnavailable
+}
+
+#[get("/admin/<_..>", rank = 1)]
+fn admin_to_primary() -> Status {
+    Status::ServiceUnavailable
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        post_to_primary,
+        put_to_primary,
+        patch_to_primary,
+        delete_to_primary,
+        index_to_primary,
+        new_to_primary,
+        admin_to_primary
+    ]
+}
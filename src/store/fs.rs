@@ -0,0 +1,282 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::io::{self, Write};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::{RwLock, RwLockWriteGuard};
+
+use super::{decode_counter, Batch, Op, Pairs, PasteStore};
+
+/// Batch being applied, replayed by `FsStore::open` if the process stopped halfway, or by
+/// the next write if applying it failed.
+const JOURNAL: &str = "journal";
+
+/// Longest key: the hex encoded key, with the extension of the temporary file of
+/// `write_file`, must fit in the 255 bytes of a file name.
+const MAX_KEY_LEN: usize = 125;
+
+/// Keys listed by `scan` at a time, so that the lock is never held while the caller
+/// works through the results.
+const SCAN_PAGE: usize = 256;
+
+/// The keys of every namespace, in order.
+type Index = HashMap<String, BTreeSet<Vec<u8>>>;
+
+/// Filesystem backend: a directory per namespace and a file per record, named after the
+/// hex encoded key (so keys are limited to `MAX_KEY_LEN` bytes). Batches are written to a
+/// journal first, so they survive a crash as a whole. The keys are listed once by `open`
+/// and kept in memory, in order, for `scan`.
+pub struct FsStore {
+    root: PathBuf,
+    /// Writers take it exclusively, so readers never see half of a batch.
+    lock: RwLock<Index>,
+}
+
+fn hex(key: &[u8]) -> String {
+    key.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn unhex(name: &str) -> Option<Vec<u8>> {
+    if !name.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..name.len()).step_by(2).map(|i| u8::from_str_radix(name.get(i..i + 2)?, 16).ok()).collect()
+}
+
+fn read_optional(path: &Path) -> io::Result<Option<Vec<u8>>> {
+    match fs::read(path) {
+        Ok(value) => Ok(Some(value)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Replace `path` with `data` through a temporary file, so a reader never sees it half written.
+fn write_file(path: &Path, data: &[u8]) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut file = fs::File::create(&tmp)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    fs::rename(tmp, path)
+}
+
+/// A resolved change: `Some` value to write, `None` to delete.
+type Change = (String, Vec<u8>, Option<Vec<u8>>);
+
+fn encode_journal(changes: &[Change]) -> Vec<u8> {
+    let mut out = vec![];
+    for (cf, key, value) in changes {
+        for field in [cf.as_bytes(), key.as_slice()] {
+            out.extend_from_slice(&(field.len() as u32).to_le_bytes());
+            out.extend_from_slice(field);
+        }
+        match value {
+            Some(value) => {
+                out.push(1);
+                out.extend_from_slice(&(value.len() as u32).to_le_bytes());
+                out.extend_from_slice(value);
+            }
+            None => out.push(0),
+        }
+    }
+    out
+}
+
+fn decode_journal(mut data: &[u8]) -> Option<Vec<Change>> {
+    fn field<'a>(data: &mut &'a [u8]) -> Option<&'a [u8]> {
+        let len = u32::from_le_bytes(data.get(..4)?.try_into().ok()?) as usize;
+        let value = data.get(4..4 + len)?;
+        *data = &data[4 + len..];
+        Some(value)
+    }
+
+    let mut changes = vec![];
+    while !data.is_empty() {
+        let cf = String::from_utf8(field(&mut data)?.to_vec()).ok()?;
+        let key = field(&mut data)?.to_vec();
+        let (&present, rest) = data.split_first()?;
+        data = rest;
+        let value = if present == 1 { Some(field(&mut data)?.to_vec()) } else { None };
+        changes.push((cf, key, value));
+    }
+    Some(changes)
+}
+
+impl FsStore {
+    /// Open (or create) the store in directory `root`, finishing a batch interrupted by a crash.
+    pub fn open(root: impl Into<PathBuf>) -> io::Result<Self> {
+        let store = FsStore { root: root.into(), lock: RwLock::new(Index::new()) };
+        fs::create_dir_all(&store.root)?;
+
+        let mut index = Index::new();
+        store.replay(&mut index)?;
+
+        for dir in fs::read_dir(&store.root)? {
+            let dir = dir?;
+            let Some(cf) = dir.file_name().to_str().map(str::to_string) else { continue };
+            if !dir.file_type()?.is_dir() {
+                continue;
+            }
+            let mut keys = BTreeSet::new();
+            for entry in fs::read_dir(dir.path())? {
+                // temporary files of `write_file` are no keys
+                keys.extend(entry?.file_name().to_str().and_then(unhex));
+            }
+            index.insert(cf, keys);
+        }
+        *store.lock.write().unwrap() = index;
+        Ok(store)
+    }
+
+    fn path(&self, cf: &str, key: &[u8]) -> io::Result<PathBuf> {
+        if key.len() > MAX_KEY_LEN {
+            let message = format!("keys of the fs backend are limited to {MAX_KEY_LEN} bytes");
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+        }
+        Ok(self.root.join(cf).join(hex(key)))
+    }
+
+    /// Finish the batch of a leftover journal, if any.
+    fn replay(&self, index: &mut Index) -> io::Result<()> {
+        let journal = self.root.join(JOURNAL);
+        if let Some(data) = read_optional(&journal)? {
+            let changes = decode_journal(&data)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "corrupt journal"))?;
+            self.apply(&changes, index)?;
+            fs::remove_file(journal)?;
+        }
+        Ok(())
+    }
+
+    /// The write lock, once a batch left halfway by a failed write is finished: until it
+    /// is, every write fails rather than overwrite its journal.
+    fn lock_for_write(&self) -> io::Result<RwLockWriteGuard<'_, Index>> {
+        let mut index = self.lock.write().unwrap();
+        self.replay(&mut index)?;
+        Ok(index)
+    }
+
+    /// `write`, with the write lock held.
+    fn write_locked(&self, batch: Batch, index: &mut Index) -> io::Result<()> {
+        // counters are resolved to their new value, so that replaying the journal is idempotent
+        let mut pending: HashMap<(&str, Vec<u8>), Option<Vec<u8>>> = HashMap::new();
+        let mut changes: Vec<Change> = vec![];
+        for op in batch.into_ops() {
+            let (cf, key, value) = match op {
+                Op::Put { cf, key, value } => (cf, key, Some(value)),
+                Op::Delete { cf, key } => (cf, key, None),
+                Op::Add { cf, key, delta } => {
+                    let current = match pending.get(&(cf, key.clone())) {
+                        Some(value) => value.clone(),
+                        None => read_optional(&self.path(cf, &key)?)?,
+                    };
+                    let total = current.as_deref().map(decode_counter).unwrap_or(0) + delta;
+                    (cf, key, Some(total.to_le_bytes().to_vec()))
+                }
+            };
+            // checked before anything is written
+            self.path(cf, &key)?;
+            pending.insert((cf, key.clone()), value.clone());
+            changes.push((cf.to_string(), key, value));
+        }
+
+        let journal = self.root.join(JOURNAL);
+        write_file(&journal, &encode_journal(&changes))?;
+        // on failure, the journal stays for `lock_for_write` to replay
+        self.apply(&changes, index)?;
+        fs::remove_file(journal)
+    }
+
+    fn apply(&self, changes: &[Change], index: &mut Index) -> io::Result<()> {
+        for (cf, key, value) in changes {
+            let path = self.path(cf, key)?;
+            match value {
+                Some(value) => {
+                    fs::create_dir_all(path.parent().unwrap())?;
+                    write_file(&path, value)?;
+                    index.entry(cf.clone()).or_default().insert(key.clone());
+                }
+                None => {
+                    match fs::remove_file(&path) {
+                        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                        _ => {}
+                    }
+                    index.entry(cf.clone()).or_default().remove(key);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl PasteStore for FsStore {
+    fn get(&self, cf: &str, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        let _read = self.lock.read().unwrap();
+        read_optional(&self.path(cf, key)?)
+    }
+
+    fn write(&self, batch: Batch) -> io::Result<()> {
+        let mut index = self.lock_for_write()?;
+        self.write_locked(batch, &mut index)
+    }
+
+    fn scan(&self, cf: &str, from: &[u8]) -> Pairs<'_> {
+        let cf = cf.to_string();
+        let mut next = Bound::Included(from.to_vec());
+        let mut page = Vec::new().into_iter();
+
+        Box::new(std::iter::from_fn(move || loop {
+            if page.len() == 0 {
+                let index = self.lock.read().unwrap();
+                let keys: Vec<Vec<u8>> = match index.get(&cf) {
+                    Some(keys) => keys.range((next.clone(), Bound::Unbounded)).take(SCAN_PAGE).cloned().collect(),
+                    None => vec![],
+                };
+                if let Some(key) = keys.last() {
+                    next = Bound::Excluded(key.clone());
+                }
+                page = keys.into_iter();
+            }
+
+            let key = page.next()?;
+            let _read = self.lock.read().unwrap();
+            // `None` when deleted since the page was listed
+            match self.path(&cf, &key).and_then(|path| read_optional(&path)) {
+                Ok(Some(value)) => return Some(Ok((key, value))),
+                Ok(None) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }))
+    }
+
+    fn take(&self, cf: &'static str, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        let mut index = self.lock_for_write()?;
+        let path = self.path(cf, key)?;
+        let value = read_optional(&path)?;
+        if value.is_some() {
+            fs::remove_file(path)?;
+            index.entry(cf.to_string()).or_default().remove(key);
+        }
+        Ok(value)
+    }
+
+    fn add(&self, cf: &'static str, key: &[u8], delta: i64) -> io::Result<i64> {
+        let mut index = self.lock_for_write()?;
+        let path = self.path(cf, key)?;
+        let total = read_optional(&path)?.as_deref().map(decode_counter).unwrap_or(0) + delta;
+        // a single file, replaced at once: no journal needed
+        fs::create_dir_all(path.parent().unwrap())?;
+        write_file(&path, &total.to_le_bytes())?;
+        index.entry(cf.to_string()).or_default().insert(key.to_vec());
+        Ok(total)
+    }
+
+    fn write_if(&self, cf: &'static str, key: &[u8], expected: &[u8], batch: Batch) -> io::Result<bool> {
+        let mut index = self.lock_for_write()?;
+        if read_optional(&self.path(cf, key)?)?.as_deref() != Some(expected) {
+            return Ok(false);
+        }
+        self.write_locked(batch, &mut index)?;
+        Ok(true)
+    }
+}
@@ -0,0 +1,92 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::ops::Bound;
+use std::sync::Mutex;
+
+use super::{decode_counter, Batch, Op, Pairs, PasteStore};
+
+type Map = BTreeMap<Vec<u8>, Vec<u8>>;
+
+/// Pairs copied out of the map at a time by `scan`, so that the lock is never held
+/// while the caller works through the results.
+const SCAN_PAGE: usize = 256;
+
+/// In-memory backend, for tests and ephemeral deployments.
+#[derive(Default)]
+pub struct MemoryStore {
+    cfs: Mutex<HashMap<String, Map>>,
+}
+
+fn apply(cfs: &mut HashMap<String, Map>, batch: Batch) {
+    for op in batch.into_ops() {
+        match op {
+            Op::Put { cf, key, value } => {
+                cfs.entry(cf.to_string()).or_default().insert(key, value);
+            }
+            Op::Delete { cf, key } => {
+                cfs.entry(cf.to_string()).or_default().remove(&key);
+            }
+            Op::Add { cf, key, delta } => {
+                let counter = cfs.entry(cf.to_string()).or_default().entry(key).or_default();
+                *counter = (decode_counter(counter) + delta).to_le_bytes().to_vec();
+            }
+        }
+    }
+}
+
+impl PasteStore for MemoryStore {
+    fn get(&self, cf: &str, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.cfs.lock().unwrap().get(cf).and_then(|map| map.get(key).cloned()))
+    }
+
+    fn write(&self, batch: Batch) -> io::Result<()> {
+        apply(&mut self.cfs.lock().unwrap(), batch);
+        Ok(())
+    }
+
+    fn scan(&self, cf: &str, from: &[u8]) -> Pairs<'_> {
+        let cf = cf.to_string();
+        let mut next = Bound::Included(from.to_vec());
+        let mut page = Vec::new().into_iter();
+
+        Box::new(std::iter::from_fn(move || {
+            if page.len() == 0 {
+                let cfs = self.cfs.lock().unwrap();
+                let pairs: Vec<_> = match cfs.get(&cf) {
+                    Some(map) => map
+                        .range((next.clone(), Bound::Unbounded))
+                        .take(SCAN_PAGE)
+                        .map(|(key, value)| (key.clone(), value.clone()))
+                        .collect(),
+                    None => vec![],
+                };
+                if let Some((key, _)) = pairs.last() {
+                    next = Bound::Excluded(key.clone());
+                }
+                page = pairs.into_iter();
+            }
+            page.next().map(Ok)
+        }))
+    }
+
+    fn take(&self, cf: &'static str, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.cfs.lock().unwrap().get_mut(cf).and_then(|map| map.remove(key)))
+    }
+
+    fn add(&self, cf: &'static str, key: &[u8], delta: i64) -> io::Result<i64> {
+        let mut cfs = self.cfs.lock().unwrap();
+        let counter = cfs.entry(cf.to_string()).or_default().entry(key.to_vec()).or_default();
+        let total = decode_counter(counter) + delta;
+        *counter = total.to_le_bytes().to_vec();
+        Ok(total)
+    }
+
+    fn write_if(&self, cf: &'static str, key: &[u8], expected: &[u8], batch: Batch) -> io::Result<bool> {
+        let mut cfs = self.cfs.lock().unwrap();
+        if cfs.get(cf).and_then(|map| map.get(key)).map(Vec::as_slice) != Some(expected) {
+            return Ok(false);
+        }
+        apply(&mut cfs, batch);
+        Ok(true)
+    }
+}
@@ -0,0 +1,260 @@
+//! Storage backends. A store keeps a few namespaces ("column families", as in RocksDB)
+//! of key-value pairs sorted by key; the paste logic in the parent module only relies on
+//! the operations of `PasteStore`, so every backend behaves the same.
+
+use std::io;
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::Arc;
+
+mod fs;
+mod memory;
+mod rocks;
+
+pub use self::fs::FsStore;
+pub use self::memory::MemoryStore;
+pub use self::rocks::RocksStore;
+
+use super::crypto::Keyring;
+
+/// Name of the namespace holding paste metadata.
+pub const DEFAULT_CF: &str = "default";
+
+/// Backend selected with `--storage`.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StorageKind {
+    /// RocksDB database, expired pastes are dropped by compaction
+    Rocksdb,
+    /// Process memory, everything is lost on restart
+    Memory,
+    /// Plain files, one directory per namespace and one file per record
+    Fs,
+}
+
+/// A change made by `PasteStore::write`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Op {
+    Put { cf: &'static str, key: Vec<u8>, value: Vec<u8> },
+    Delete { cf: &'static str, key: Vec<u8> },
+    /// Add `delta` to a counter, see `decode_counter`.
+    Add { cf: &'static str, key: Vec<u8>, delta: i64 },
+}
+
+/// Changes applied together by `PasteStore::write`.
+#[derive(Default, Debug)]
+pub struct Batch {
+    ops: Vec<Op>,
+}
+
+impl Batch {
+    pub fn put(&mut self, cf: &'static str, key: impl AsRef<[u8]>, value: impl AsRef<[u8]>) {
+        let (key, value) = (key.as_ref().to_vec(), value.as_ref().to_vec());
+        self.ops.push(Op::Put { cf, key, value });
+    }
+
+    pub fn delete(&mut self, cf: &'static str, key: impl AsRef<[u8]>) {
+        self.ops.push(Op::Delete { cf, key: key.as_ref().to_vec() });
+    }
+
+    /// Add `delta` to the counter `key`, a missing counter counting as zero.
+    pub fn add(&mut self, cf: &'static str, key: impl AsRef<[u8]>, delta: i64) {
+        self.ops.push(Op::Add { cf, key: key.as_ref().to_vec(), delta });
+    }
+
+    pub fn into_ops(self) -> Vec<Op> {
+        self.ops
+    }
+}
+
+/// Counters are stored as little-endian `i64`s.
+pub fn decode_counter(value: &[u8]) -> i64 {
+    value.try_into().map(i64::from_le_bytes).unwrap_or(0)
+}
+
+/// Key-value pairs returned by `PasteStore::scan`.
+pub type Pairs<'a> = Box<dyn Iterator<Item = io::Result<(Vec<u8>, Vec<u8>)>> + 'a>;
+
+/// The storage operations the pastebin relies on.
+// the binary compiles this module as well, without using every helper
+#[allow(dead_code)]
+pub trait PasteStore: Send + Sync {
+    fn get(&self, cf: &str, key: &[u8]) -> io::Result<Option<Vec<u8>>>;
+
+    /// Apply every change of `batch`, atomically: readers see all of them or none.
+    fn write(&self, batch: Batch) -> io::Result<()>;
+
+    /// Pairs of `cf` in key order, starting with `from` (included).
+    fn scan(&self, cf: &str, from: &[u8]) -> Pairs<'_>;
+
+    /// Read and delete `key` in one step: of concurrent callers, only one gets the value.
+    fn take(&self, cf: &'static str, key: &[u8]) -> io::Result<Option<Vec<u8>>>;
+
+    /// Add `delta` to the counter `key` and return its new value, in one step: concurrent
+    /// callers each see a different value.
+    fn add(&self, cf: &'static str, key: &[u8], delta: i64) -> io::Result<i64>;
+
+    /// Apply `batch` like `write` if `key` still holds `expected`, checked in one step with
+    /// the write and with `take`. Returns `false`, writing nothing, when it does not.
+    fn write_if(&self, cf: &'static str, key: &[u8], expected: &[u8], batch: Batch) -> io::Result<bool>;
+
+    /// Reclaim the space of deleted and expired records, where the backend needs it.
+    fn compact(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Write a consistent snapshot of the whole store to the new directory `path`, while
+    /// it stays in use.
+    fn checkpoint(&self, _path: &Path) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "backups need the rocksdb storage backend"))
+    }
+
+    /// Whether every write fails, i.e. on a secondary instance (see `catch_up`).
+    fn is_read_only(&self) -> bool {
+        false
+    }
+
+    /// Bring a secondary instance up to date with the writes of its primary.
+    fn catch_up(&self) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "secondary instances need the rocksdb storage backend"))
+    }
+
+    fn put(&self, cf: &'static str, key: &[u8], value: &[u8]) -> io::Result<()> {
+        let mut batch = Batch::default();
+        batch.put(cf, key, value);
+        self.write(batch)
+    }
+
+    fn delete(&self, cf: &'static str, key: &[u8]) -> io::Result<()> {
+        let mut batch = Batch::default();
+        batch.delete(cf, key);
+        self.write(batch)
+    }
+}
+
+/// The store in use, as managed by Rocket and opened by `open_store`, with the keys
+/// encrypting paste bodies at rest if any. Clones share the same store, i.e. with
+/// background tasks.
+#[derive(Clone)]
+pub struct Store {
+    backend: Arc<dyn PasteStore>,
+    keyring: Option<Arc<Keyring>>,
+}
+
+impl Store {
+    pub fn new(store: impl PasteStore + 'static) -> Self {
+        Store { backend: Arc::new(store), keyring: None }
+    }
+
+    /// Encrypt the paste bodies written from now on with the current key of `keyring`.
+    pub fn with_keyring(self, keyring: Keyring) -> Self {
+        Store { keyring: Some(Arc::new(keyring)), ..self }
+    }
+
+    pub fn keyring(&self) -> Option<&Keyring> {
+        self.keyring.as_deref()
+    }
+}
+
+impl Deref for Store {
+    type Target = dyn PasteStore;
+
+    fn deref(&self) -> &Self::Target {
+        self.backend.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stores(tmp: &std::path::Path) -> Vec<Store> {
+        vec![
+            Store::new(RocksStore::open(tmp.join("rocksdb").to_str().unwrap(), Default::default(), vec![]).unwrap()),
+            Store::new(MemoryStore::default()),
+            Store::new(FsStore::open(tmp.join("fs")).unwrap()),
+        ]
+    }
+
+    #[test]
+    fn backends_agree_on_every_operation() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        for store in stores(tmp.path()) {
+            let mut batch = Batch::default();
+            batch.put(DEFAULT_CF, b"b", b"2");
+            batch.put(DEFAULT_CF, b"a\0x", b"1");
+            batch.put(DEFAULT_CF, b"c", b"3");
+            batch.add(DEFAULT_CF, b"counter", 5);
+            batch.add(DEFAULT_CF, b"counter", -2);
+            store.write(batch).unwrap();
+            store.delete(DEFAULT_CF, b"c").unwrap();
+
+            assert_eq!(store.get(DEFAULT_CF, b"b").unwrap(), Some(b"2".to_vec()));
+            assert_eq!(store.get(DEFAULT_CF, b"c").unwrap(), None);
+            assert_eq!(decode_counter(&store.get(DEFAULT_CF, b"counter").unwrap().unwrap()), 3);
+
+            let keys: Vec<Vec<u8>> = store.scan(DEFAULT_CF, b"a").map(|item| item.unwrap().0).collect();
+            assert_eq!(keys, [&b"a\0x"[..], b"b", b"counter"]);
+            assert_eq!(store.scan(DEFAULT_CF, b"b\0").count(), 1);
+
+            assert_eq!(store.take(DEFAULT_CF, b"b").unwrap(), Some(b"2".to_vec()));
+            assert_eq!(store.take(DEFAULT_CF, b"b").unwrap(), None);
+            assert_eq!(store.add(DEFAULT_CF, b"counter", 1).unwrap(), 4);
+            assert_eq!(store.add(DEFAULT_CF, b"new", -1).unwrap(), -1);
+
+            let mut batch = Batch::default();
+            batch.put(DEFAULT_CF, b"a\0x", b"2");
+            batch.put(DEFAULT_CF, b"d", b"4");
+            assert!(!store.write_if(DEFAULT_CF, b"a\0x", b"0", std::mem::take(&mut batch)).unwrap());
+            assert_eq!(store.get(DEFAULT_CF, b"d").unwrap(), None);
+            batch.put(DEFAULT_CF, b"a\0x", b"2");
+            assert!(store.write_if(DEFAULT_CF, b"a\0x", b"1", batch).unwrap());
+            assert_eq!(store.get(DEFAULT_CF, b"a\0x").unwrap(), Some(b"2".to_vec()));
+            // a taken key is not brought back
+            store.take(DEFAULT_CF, b"a\0x").unwrap();
+            let mut batch = Batch::default();
+            batch.put(DEFAULT_CF, b"a\0x", b"3");
+            assert!(!store.write_if(DEFAULT_CF, b"a\0x", b"2", batch).unwrap());
+            assert_eq!(store.get(DEFAULT_CF, b"a\0x").unwrap(), None);
+            store.compact().unwrap();
+        }
+    }
+    #[test]
+    fn fs_backend_refuses_long_keys_and_lists_keys_after_a_reopen() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let store = FsStore::open(tmp.path()).unwrap();
+        let mut batch = Batch::default();
+        batch.put(DEFAULT_CF, b"short", b"1");
+        batch.put(DEFAULT_CF, [b'k'; 126], b"2");
+        assert_eq!(store.write(batch).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(store.get(DEFAULT_CF, b"short").unwrap(), None);
+
+        store.put(DEFAULT_CF, b"b", b"2").unwrap();
+        store.put(DEFAULT_CF, &[b'k'; 125], b"1").unwrap();
+        store.add(DEFAULT_CF, b"a", 1).unwrap();
+        drop(store);
+
+        let store = FsStore::open(tmp.path()).unwrap();
+        let keys: Vec<Vec<u8>> = store.scan(DEFAULT_CF, b"").map(|item| item.unwrap().0).collect();
+        assert_eq!(keys, [b"a".to_vec(), b"b".to_vec(), vec![b'k'; 125]]);
+    }
+
+    #[test]
+    fn fs_backend_finishes_a_failed_batch_before_the_next_write() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let store = FsStore::open(tmp.path()).unwrap();
+        // a file where the directory of a namespace goes makes the batch fail halfway
+        std::fs::write(tmp.path().join("other"), b"").unwrap();
+        let mut batch = Batch::default();
+        batch.put(DEFAULT_CF, b"first", b"1");
+        batch.put("other", b"second", b"2");
+        assert!(store.write(batch).is_err());
+        assert!(store.put(DEFAULT_CF, b"third", b"3").is_err());
+
+        std::fs::remove_file(tmp.path().join("other")).unwrap();
+        store.put(DEFAULT_CF, b"third", b"3").unwrap();
+        assert_eq!(store.get("other", b"second").unwrap(), Some(b"2".to_vec()));
+        assert_eq!(store.get(DEFAULT_CF, b"third").unwrap(), Some(b"3".to_vec()));
+        let keys: Vec<Vec<u8>> = store.scan("other", b"").map(|item| item.unwrap().0).collect();
+        assert_eq!(keys, [b"second".to_vec()]);
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+
+use rocksdb::checkpoint::Checkpoint;
+use rocksdb::{ColumnFamily, ColumnFamilyDescriptor, Direction, IteratorMode, MergeOperands, Options, WriteBatch, DB};
+
+use super::{decode_counter, Batch, Op, Pairs, PasteStore, DEFAULT_CF};
+
+/// Locks shared by the keys given to `take`, `add` and `write_if`, see `RocksStore::lock_key`.
+const KEY_LOCKS: usize = 64;
+
+/// RocksDB backend, with a column family per namespace.
+pub struct RocksStore {
+    db: DB,
+    names: Vec<&'static str>,
+    /// Serialize `take`, `add` and `write_if` per key, RocksDB has no atomic read-and-write.
+    key_locks: Vec<Mutex<()>>,
+    /// Opened with `open_secondary`.
+    secondary: bool,
+}
+
+fn to_io(e: rocksdb::Error) -> io::Error {
+    io::Error::other(e.into_string())
+}
+
+impl RocksStore {
+    /// Open (or create) the database at `path`. `db_opts` apply to the default column
+    /// family and `cfs` lists the other ones with their options (i.e. compaction filters);
+    /// the counters merge operator is added to all of them.
+    pub fn open(path: &str, mut db_opts: Options, cfs: Vec<(&'static str, Options)>) -> io::Result<Self> {
+        db_opts.create_if_missing(true);
+        db_opts.create_missing_column_families(true);
+        let (db_opts, names, descriptors) = with_counters(db_opts, cfs);
+
+        let db = DB::open_cf_descriptors(&db_opts, path, descriptors).map_err(to_io)?;
+        Ok(RocksStore::new(db, names, false))
+    }
+
+    /// Open the database at `primary`, which another process keeps open and writes to, as
+    /// a RocksDB secondary instance: a read-only view of it, which `catch_up` brings up to
+    /// date. `path` is a directory of the secondary instance's own (for its logs), the
+    /// options are those of `open`.
+    pub fn open_secondary(
+        primary: &str,
+        path: &str,
+        mut db_opts: Options,
+        cfs: Vec<(&'static str, Options)>,
+    ) -> io::Result<Self> {
+        // a secondary keeps every table file of the primary open, as they may be deleted
+        db_opts.set_max_open_files(-1);
+        let (db_opts, names, descriptors) = with_counters(db_opts, cfs);
+
+        let db = DB::open_cf_descriptors_as_secondary(&db_opts, primary, path, descriptors).map_err(to_io)?;
+        Ok(RocksStore::new(db, names, true))
+    }
+
+    fn new(db: DB, names: Vec<&'static str>, secondary: bool) -> Self {
+        let key_locks = (0..KEY_LOCKS).map(|_| Mutex::new(())).collect();
+        RocksStore { db, names, key_locks, secondary }
+    }
+
+    fn cf(&self, name: &str) -> io::Result<&ColumnFamily> {
+        self.db
+            .cf_handle(name)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no column family {name}")))
+    }
+
+    /// Lock `key` of `cf` for a read-and-write. Keys are spread over a few locks, so
+    /// that only takes of colliding keys wait for each other.
+    fn lock_key(&self, cf: &str, key: &[u8]) -> MutexGuard<'_, ()> {
+        let mut hasher = DefaultHasher::new();
+        (cf, key).hash(&mut hasher);
+        self.key_locks[hasher.finish() as usize % KEY_LOCKS].lock().unwrap()
+    }
+}
+
+/// The options with the counters merge operator added to the default column family and to
+/// `cfs`, with the names and descriptors of `cfs`.
+fn with_counters(
+    mut db_opts: Options,
+    cfs: Vec<(&'static str, Options)>,
+) -> (Options, Vec<&'static str>, Vec<ColumnFamilyDescriptor>) {
+    db_opts.set_merge_operator_associative("counters", merge_counters);
+    let names = cfs.iter().map(|(name, _)| *name).collect();
+    let descriptors = cfs
+        .into_iter()
+        .map(|(name, mut opts)| {
+            opts.set_merge_operator_associative("counters", merge_counters);
+            ColumnFamilyDescriptor::new(name, opts)
+        })
+        .collect();
+    (db_opts, names, descriptors)
+}
+
+/// Merge operator of the counters: every operand is a delta added to the current value.
+fn merge_counters(_: &[u8], existing: Option<&[u8]>, operands: &MergeOperands) -> Option<Vec<u8>> {
+    let total = operands
+        .into_iter()
+        .fold(existing.map(decode_counter).unwrap_or(0), |acc, op| acc + decode_counter(op));
+    Some(total.to_le_bytes().to_vec())
+}
+
+impl PasteStore for RocksStore {
+    fn get(&self, cf: &str, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        self.db.get_cf(self.cf(cf)?, key).map_err(to_io)
+    }
+
+    fn write(&self, batch: Batch) -> io::Result<()> {
+        let mut write = WriteBatch::default();
+        for op in batch.into_ops() {
+            match op {
+                Op::Put { cf, key, value } => write.put_cf(self.cf(cf)?, key, value),
+                Op::Delete { cf, key } => write.delete_cf(self.cf(cf)?, key),
+                Op::Add { cf, key, delta } => write.merge_cf(self.cf(cf)?, key, delta.to_le_bytes()),
+            }
+        }
+        self.db.write(write).map_err(to_io)
+    }
+
+    fn scan(&self, cf: &str, from: &[u8]) -> Pairs<'_> {
+        let cf = match self.cf(cf) {
+            Ok(cf) => cf,
+            Err(e) => return Box::new(std::iter::once(Err(e))),
+        };
+        Box::new(
+            self.db
+                .iterator_cf(cf, IteratorMode::From(from, Direction::Forward))
+                .map(|item| item.map(|(key, value)| (key.into_vec(), value.into_vec())).map_err(to_io)),
+        )
+    }
+
+    fn take(&self, cf: &'static str, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        let _take = self.lock_key(cf, key);
+        let value = self.get(cf, key)?;
+        if value.is_some() {
+            self.delete(cf, key)?;
+        }
+        Ok(value)
+    }
+
+    fn add(&self, cf: &'static str, key: &[u8], delta: i64) -> io::Result<i64> {
+        let _add = self.lock_key(cf, key);
+        self.db.merge_cf(self.cf(cf)?, key, delta.to_le_bytes()).map_err(to_io)?;
+        Ok(self.get(cf, key)?.as_deref().map(decode_counter).unwrap_or(0))
+    }
+
+    fn write_if(&self, cf: &'static str, key: &[u8], expected: &[u8], batch: Batch) -> io::Result<bool> {
+        let _check = self.lock_key(cf, key);
+        if self.get(cf, key)?.as_deref() != Some(expected) {
+            return Ok(false);
+        }
+        self.write(batch)?;
+        Ok(true)
+    }
+
+    fn compact(&self) -> io::Result<()> {
+        for name in std::iter::once(DEFAULT_CF).chain(self.names.iter().copied()) {
+            self.db.compact_range_cf(self.cf(name)?, None::<&[u8]>, None::<&[u8]>);
+        }
+        Ok(())
+    }
+
+    /// A RocksDB checkpoint: the table files are hard links when `path` is on the same
+    /// file system, so it is quick and takes little space.
+    fn checkpoint(&self, path: &Path) -> io::Result<()> {
+        Checkpoint::new(&self.db).and_then(|checkpoint| checkpoint.create_checkpoint(path)).map_err(to_io)
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.secondary
+    }
+
+    fn catch_up(&self) -> io::Result<()> {
+        self.db.try_catch_up_with_primary().map_err(to_io)
+    }
+}
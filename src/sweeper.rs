@@ -0,0 +1,73 @@
+use std::io;
+use std::time::Duration;
+
+use rocket::fairing::AdHoc;
+use rocket::tokio;
+
+use crate::lib::store::Store;
+use crate::lib::{purge_expired, Purged};
+
+/// Delete the expired pastes, then compact the store so that their content is physically
+/// removed (RocksDB keeps deleted records on disk until then).
+pub fn sweep(store: &Store) -> io::Result<Purged> {
+    let purged = purge_expired(store)?;
+    store.compact()?;
+    Ok(purged)
+}
+
+/// Fairing running `sweep` every `interval` once the server is up. Compaction alone only
+/// drops expired pastes when RocksDB happens to compact, which can take weeks on a quiet
+/// instance. A zero interval disables the sweeper.
+pub fn fairing(interval: Duration) -> AdHoc {
+    AdHoc::on_liftoff("Expiry sweeper", move |rocket| {
+        Box::pin(async move {
+            let store = match rocket.state::<Store>() {
+                Some(store) if !interval.is_zero() => store.clone(),
+                _ => return,
+            };
+
+            tokio::spawn(async move {
+                let mut ticks = tokio::time::interval(interval);
+                // the first tick completes immediately, leave the startup alone
+                ticks.tick().await;
+                loop {
+                    ticks.tick().await;
+                    let store = store.clone();
+                    match tokio::task::spawn_blocking(move || sweep(&store)).await {
+                        Ok(Ok(purged)) => rocket::info!(
+                            "expiry sweep: removed {} pastes, {} bytes",
+                            purged.ids.len(),
+                            purged.bytes
+                        ),
+                        Ok(Err(e)) => rocket::error!("expiry sweep failed: {e}"),
+                        Err(e) => rocket::error!("expiry sweep panicked: {e}"),
+                    }
+                }
+            });
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::store::MemoryStore;
+    use crate::lib::{get_entry, insert_entry, new_entry, EntryOptions};
+
+    #[test]
+    fn sweep_reports_the_expired_pastes_removed() {
+        let store = Store::new(MemoryStore::default());
+        for (id, ttl) in [("kept", 0), ("expired", 1)] {
+            let mut meta = vec![];
+            new_entry(&mut meta, b"secret", &EntryOptions { ttl, ..Default::default() });
+            insert_entry(id, &meta, b"secret", &store).unwrap();
+        }
+        std::thread::sleep(Duration::from_millis(1100));
+
+        let purged = sweep(&store).unwrap();
+        assert_eq!(purged, Purged { ids: vec!["expired".to_string()], bytes: 6 });
+        assert!(get_entry("expired", &store).unwrap().is_none());
+        assert!(get_entry("kept", &store).unwrap().is_some());
+        assert_eq!(sweep(&store).unwrap(), Purged::default());
+    }
+}
@@ -121,6 +121,10 @@ $(document).ready(function() {
         }
     }
 
+    function tokenKey(id) {
+        return 'pastebin-token-' + id;
+    }
+
     function getDefaultExpiryTime() {
         var expiry = $("#expiry-dropdown-btn").text().split("Expires: ")[1];
         return $("#expiry-dropdown a:contains('"+ expiry +"')").attr('href');
@@ -136,6 +140,11 @@ $(document).ready(function() {
     checkPasswordModal();
     init_plugins();
 
+    // Only the browser that created the paste holds its owner token and may edit it.
+    if ($("#edit-btn").length && localStorage.getItem(tokenKey(window.location.pathname.split('/').pop()))) {
+        $("#edit-btn").removeClass("d-none");
+    }
+
     var state = {
         expiry: getDefaultExpiryTime(),
         burn: 0,
@@ -156,15 +165,25 @@ $(document).ready(function() {
     $("#deletion-confirm-btn").on("click", function(event) {
         event.preventDefault();
 
+        var id = window.location.pathname.split('/').pop();
+        var token = localStorage.getItem(tokenKey(id));
+
         $.ajax({
             url: window.location.pathname,
             type: 'DELETE',
+            headers: token ? { 'X-Paste-Token': token } : {},
             success: function(result) {
+                localStorage.removeItem(tokenKey(id));
+
                 uri = uri_prefix + "/new";
                 uri = replaceUrlParam(uri, 'level', "info");
                 uri = replaceUrlParam(uri, 'glyph', "fas fa-info-circle");
                 uri = replaceUrlParam(uri, 'msg', "The paste has been successfully removed.");
                 window.location.href = encodeURI(uri);
+            },
+            error: function() {
+                $('#deletion-modal').modal('hide');
+                alert("Only the creator of this paste can remove it.");
             }
         });
     });
@@ -190,25 +209,59 @@ $(document).ready(function() {
         event.preventDefault();
 
         var uri = uri_prefix == "" ? "/" : uri_prefix;
-        uri = replaceUrlParam(uri, 'lang', $("#language-selector").val());
-        uri = replaceUrlParam(uri, 'ttl', state.expiry);
-        uri = replaceUrlParam(uri, 'burn', state.burn);
+        if (edit_id) {
+            uri = uri_prefix + "/" + edit_id;
+            uri = replaceUrlParam(uri, 'lang', $("#language-selector").val());
+        } else {
+            uri = replaceUrlParam(uri, 'lang', $("#language-selector").val());
+            uri = replaceUrlParam(uri, 'ttl', state.expiry);
+            uri = replaceUrlParam(uri, 'burn', state.burn);
+        }
 
         var data = $("#content-textarea").val();
         var pass = $("#pastebin-password").val();
 
+        function doPut(payload) {
+            $.ajax({
+                url: replaceUrlParam(uri, 'encrypted', pass.length > 0),
+                type: 'PUT',
+                data: payload,
+                contentType: 'text/plain',
+                headers: { 'X-Paste-Token': localStorage.getItem(tokenKey(edit_id)) },
+                success: function(result) {
+                    window.location.href = uri_prefix + "/" + edit_id;
+                }
+            });
+        }
+
         function doPost(payload) {
+            if (edit_id) {
+                doPut(payload);
+                return;
+            }
+
             $.ajax({
                 url: uri,
                 type: 'POST',
                 data: payload,
                 contentType: 'test/plain',
-                success: function(result) {
+                success: function(result, status, xhr) {
+                    // Remember the owner token so this browser can remove the paste later.
+                    var token = xhr.getResponseHeader('X-Paste-Token');
+                    if (token) {
+                        localStorage.setItem(tokenKey(result.split('/').pop()), token);
+                    }
+
                     var dest = uri_prefix + "/new";
                     dest = replaceUrlParam(dest, 'level', "success");
                     dest = replaceUrlParam(dest, 'glyph', "fas fa-check");
                     dest = replaceUrlParam(dest, 'msg', "The paste has been successfully created:");
                     dest = replaceUrlParam(dest, 'url', result);
+                    // A "burn" paste comes with a link letting its creator check it without burning it.
+                    var preview = xhr.getResponseHeader('X-Paste-Preview');
+                    if (preview) {
+                        dest = replaceUrlParam(dest, 'preview', preview.split('preview=').pop());
+                    }
                     window.location.href = encodeURI(dest);
                 }
             });